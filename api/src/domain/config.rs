@@ -70,8 +70,37 @@ pub struct ConnectionsConfig {
     /// Burst size limit
     #[envconfig(from = "API_VERSION", default = "v1")]
     pub api_version: String,
+    /// How long calls to platforms, other than passthrough calls, may take to complete
     #[envconfig(from = "HTTP_CLIENT_TIMEOUT_SECS", default = "30")]
     pub http_client_timeout_secs: u64,
+    /// How long passthrough calls may wait for data, their streamed bodies stay open for as long
+    /// as the platform keeps sending data
+    #[envconfig(from = "PASSTHROUGH_READ_TIMEOUT_SECS", default = "30")]
+    pub passthrough_read_timeout_secs: u64,
+    /// Consecutive failures of a platform that open its circuit, 0 disables the circuit breaker
+    #[envconfig(from = "CIRCUIT_BREAKER_FAILURE_THRESHOLD", default = "5")]
+    pub circuit_breaker_failure_threshold: u32,
+    /// How long calls to a platform with an open circuit fail before it is probed again
+    #[envconfig(from = "CIRCUIT_BREAKER_OPEN_SECS", default = "30")]
+    pub circuit_breaker_open_secs: u64,
+    /// How long a mapping script may run before it is terminated
+    #[envconfig(from = "JS_RUNTIME_TIMEOUT_MS", default = "5000")]
+    pub js_runtime_timeout_ms: u64,
+    /// Heap of the isolate running the mapping scripts of each thread
+    #[envconfig(from = "JS_RUNTIME_MAX_HEAP_MB", default = "128")]
+    pub js_runtime_max_heap_mb: usize,
+    /// Compiled mapping scripts kept by each thread
+    #[envconfig(from = "JS_RUNTIME_MAX_SCRIPTS", default = "1024")]
+    pub js_runtime_max_scripts: usize,
+    /// Where the responses of unified reads are cached, for the models with a cache TTL
+    #[envconfig(from = "RESPONSE_CACHE_BACKEND", default = "local")]
+    pub response_cache_backend: ResponseCacheBackend,
+    /// Size of the responses kept by the local response cache
+    #[envconfig(from = "RESPONSE_CACHE_MAX_MB", default = "64")]
+    pub response_cache_max_mb: u64,
+    /// Most pages fetched by a unified list with `fetchAll=true`
+    #[envconfig(from = "UNIFIED_FETCH_ALL_MAX_PAGES", default = "50")]
+    pub unified_fetch_all_max_pages: usize,
     #[envconfig(nested = true)]
     pub headers: Headers,
     #[envconfig(nested = true)]
@@ -80,6 +109,9 @@ pub struct ConnectionsConfig {
     pub cache_config: CacheConfig,
     #[envconfig(from = "RATE_LIMIT_ENABLED", default = "true")]
     pub rate_limit_enabled: bool,
+    /// Access keys and connections are limited to their throughput over this sliding window
+    #[envconfig(from = "RATE_LIMIT_WINDOW_SECS", default = "60")]
+    pub rate_limit_window_secs: u64,
     #[envconfig(from = "ENVIRONMENT", default = "development")]
     pub environment: Environment,
     #[envconfig(from = "DATABASE_CONNECTION_DOCKER_IMAGE", default = "pica-database")]
@@ -148,10 +180,40 @@ impl Display for ConnectionsConfig {
         writeln!(f, "JWT_SECRET: ***")?;
         write!(f, "{}", self.secrets_config)?;
         writeln!(f, "API_VERSION: {}", self.api_version)?;
+        writeln!(
+            f,
+            "PASSTHROUGH_READ_TIMEOUT_SECS: {}",
+            self.passthrough_read_timeout_secs
+        )?;
+        writeln!(
+            f,
+            "CIRCUIT_BREAKER_FAILURE_THRESHOLD: {}",
+            self.circuit_breaker_failure_threshold
+        )?;
+        writeln!(
+            f,
+            "CIRCUIT_BREAKER_OPEN_SECS: {}",
+            self.circuit_breaker_open_secs
+        )?;
+        writeln!(f, "JS_RUNTIME_TIMEOUT_MS: {}", self.js_runtime_timeout_ms)?;
+        writeln!(f, "JS_RUNTIME_MAX_HEAP_MB: {}", self.js_runtime_max_heap_mb)?;
+        writeln!(f, "JS_RUNTIME_MAX_SCRIPTS: {}", self.js_runtime_max_scripts)?;
+        writeln!(
+            f,
+            "RESPONSE_CACHE_BACKEND: {}",
+            self.response_cache_backend.as_ref()
+        )?;
+        writeln!(f, "RESPONSE_CACHE_MAX_MB: {}", self.response_cache_max_mb)?;
+        writeln!(
+            f,
+            "UNIFIED_FETCH_ALL_MAX_PAGES: {}",
+            self.unified_fetch_all_max_pages
+        )?;
         writeln!(f, "{}", self.headers)?;
         writeln!(f, "{}", self.db_config)?;
         writeln!(f, "{}", self.cache_config)?;
         writeln!(f, "RATE_LIMIT_ENABLED: {}", self.rate_limit_enabled)?;
+        writeln!(f, "RATE_LIMIT_WINDOW_SECS: {}", self.rate_limit_window_secs)?;
         writeln!(f, "ENVIRONMENT: {}", self.environment)?;
         writeln!(
             f,
@@ -215,3 +277,10 @@ pub enum K8sMode {
     Real,
     Logger,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ResponseCacheBackend {
+    Local,
+    Redis,
+}
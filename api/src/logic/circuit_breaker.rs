@@ -0,0 +1,50 @@
+use super::ReadResponse;
+use crate::{router::ServerResponse, server::AppState};
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+use osentities::PicaError;
+use serde::Deserialize;
+use std::sync::Arc;
+use unified::circuit_breaker::CircuitStatus;
+
+pub fn get_router() -> Router<Arc<AppState>> {
+    Router::new().route("/", get(get_circuit_breakers))
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryParams {
+    #[serde(default)]
+    platform: Option<String>,
+}
+
+/// State of the circuits of the platforms called by this instance
+pub async fn get_circuit_breakers(
+    State(state): State<Arc<AppState>>,
+    query: Option<Query<QueryParams>>,
+) -> Result<Json<ServerResponse<ReadResponse<CircuitStatus>>>, PicaError> {
+    let platform = query.and_then(|q| q.0.platform);
+
+    let rows = state
+        .extractor_caller
+        .circuit_breaker
+        .states()
+        .into_iter()
+        .filter(|status| platform.as_ref().is_none_or(|p| p == &status.platform))
+        .collect::<Vec<_>>();
+
+    let total = rows.len() as u64;
+
+    Ok(Json(ServerResponse::new(
+        "circuit-breakers",
+        ReadResponse {
+            rows,
+            total,
+            skip: 0,
+            limit: total,
+        },
+    )))
+}
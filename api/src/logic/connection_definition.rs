@@ -23,6 +23,7 @@ use osentities::{
     connection_model_definition::{ConnectionModelDefinition, CrudAction},
     event_access::EventAccess,
     id::{prefix::IdPrefix, Id},
+    rate_limit::ProviderRateLimit,
     record_metadata::RecordMetadata,
     settings::Settings,
     ApplicationError, PicaError,
@@ -74,6 +75,9 @@ pub struct CreateRequest {
     pub active: bool,
     #[serde(default)]
     pub markdown: Option<String>,
+    #[serde(default)]
+    #[dummy(default)]
+    pub rate_limits: Vec<ProviderRateLimit>,
 }
 
 impl HookExt<ConnectionDefinition> for CreateRequest {}
@@ -372,6 +376,7 @@ impl RequestExt for CreateRequest {
             settings: self.settings.clone(),
             hidden: false,
             test_delay_in_millis: self.test_delay_in_millis,
+            rate_limits: self.rate_limits.clone(),
             record_metadata: RecordMetadata::default(),
         };
 
@@ -392,6 +397,7 @@ impl RequestExt for CreateRequest {
         record.test_connection = self.test_connection;
         record.platform.clone_from(&self.platform);
         record.multi_env = self.multi_env;
+        record.rate_limits.clone_from(&self.rate_limits);
         record.record_metadata.active = self.active;
         record
     }
@@ -17,7 +17,8 @@ use mongodb::bson::doc;
 use osentities::{
     algebra::MongoStore,
     api_model_config::{
-        ApiModelConfig, AuthMethod, ModelPaths, ResponseBody, SamplesInput, SchemasInput,
+        ApiModelConfig, AuthMethod, ModelPaths, ResponseBody, ResponseContent, RetryPolicy,
+        SamplesInput, SchemasInput,
     },
     connection_model_definition::{
         ConnectionModelDefinition, CrudAction, CrudMapping, ExtractorConfig, PlatformInfo,
@@ -25,6 +26,7 @@ use osentities::{
     },
     event_access::EventAccess,
     id::{prefix::IdPrefix, Id},
+    rate_limit::ProviderRateLimit,
     ApplicationError, InternalError, PicaError,
 };
 use semver::Version;
@@ -191,6 +193,7 @@ pub async fn test_connection_model_definition(
             &payload.request.query_params.unwrap_or(HashMap::new()),
             &Arc::new(secret_result),
             request_body_vec,
+            None,
         )
         .await
         .map_err(|e| {
@@ -311,6 +314,17 @@ pub struct CreateRequest {
     pub active: Option<bool>,
     pub knowledge: Option<String>,
     pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    #[dummy(default)]
+    pub rate_limits: Vec<ProviderRateLimit>,
+    #[serde(default)]
+    #[dummy(default)]
+    pub retry_policy: Option<RetryPolicy>,
+    #[serde(default)]
+    #[dummy(default)]
+    pub response_content: Option<ResponseContent>,
+    #[serde(default)]
+    pub cache_ttl_secs: Option<u64>,
 }
 
 impl HookExt<ConnectionModelDefinition> for CreateRequest {}
@@ -346,6 +360,7 @@ impl RequestExt for CreateRequest {
                 base_url: self.base_url.clone(),
                 path: self.path.clone(),
                 content: Default::default(),
+                response_content: self.response_content,
                 auth_method: self.auth_method.clone(),
                 headers: self.headers.clone(),
                 query_params: self.query_params.clone(),
@@ -353,6 +368,7 @@ impl RequestExt for CreateRequest {
                 samples: self.samples.clone(),
                 responses: self.responses.clone(),
                 paths: self.paths.clone(),
+                retry_policy: self.retry_policy.clone(),
             }),
             action: self.http_method.clone(),
             action_name: self.action_name.clone(),
@@ -364,6 +380,8 @@ impl RequestExt for CreateRequest {
             record_metadata: Default::default(),
             supported: self.supported.unwrap_or(false),
             knowledge: self.knowledge.clone(),
+            rate_limits: self.rate_limits.clone(),
+            cache_ttl_secs: self.cache_ttl_secs,
         };
         record.record_metadata.version = self.version.clone();
 
@@ -400,6 +418,7 @@ impl RequestExt for CreateRequest {
             base_url: self.base_url.clone(),
             path: self.path.clone(),
             content: Default::default(),
+            response_content: self.response_content,
             auth_method: self.auth_method.clone(),
             headers: self.headers.clone(),
             query_params: self.query_params.clone(),
@@ -407,10 +426,13 @@ impl RequestExt for CreateRequest {
             samples: self.samples.clone(),
             responses: self.responses.clone(),
             paths: self.paths.clone(),
+            retry_policy: self.retry_policy.clone(),
         });
         record.mapping.clone_from(&self.mapping);
         record.extractor_config.clone_from(&self.extractor_config);
         record.knowledge.clone_from(&self.knowledge);
+        record.rate_limits.clone_from(&self.rate_limits);
+        record.cache_ttl_secs = self.cache_ttl_secs;
         record.record_metadata.version.clone_from(&self.version);
 
         if let Some(tags) = &self.tags {
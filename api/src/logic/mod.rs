@@ -21,6 +21,7 @@ use std::{collections::BTreeMap, fmt::Debug, future::Future, sync::Arc};
 use tokio::try_join;
 use tracing::error;
 
+pub mod circuit_breaker;
 pub mod common_enum;
 pub mod common_model;
 pub mod connection;
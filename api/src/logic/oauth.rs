@@ -4,11 +4,11 @@ use axum::{
     routing::post,
     Extension, Json, Router,
 };
-use chrono::{Duration, Utc};
+use chrono::Utc;
 use fake::Dummy;
 use mongodb::bson::doc;
 use osentities::{
-    algebra::{MongoStore, TemplateExt},
+    algebra::{oauth_expires_at, MongoStore, TemplateExt},
     connection_definition::ConnectionDefinition,
     connection_oauth_definition::{
         ConnectionOAuthDefinition, OAuthResponse, PlatformSecret, Settings,
@@ -230,14 +230,7 @@ async fn oauth_handler(
         oauth: Some(OAuth::Enabled {
             connection_oauth_definition_id: conn_oauth_definition.id,
             expires_in: Some(oauth_secret.expires_in),
-            expires_at: Some(
-                chrono::Utc::now()
-                    .checked_add_signed(Duration::seconds(oauth_secret.expires_in as i64))
-                    .unwrap_or_else(chrono::Utc::now)
-                    .checked_sub_signed(Duration::seconds(120))
-                    .unwrap_or_else(chrono::Utc::now)
-                    .timestamp(),
-            ),
+            expires_at: Some(oauth_expires_at(oauth_secret.expires_in)),
         }),
         record_metadata: Default::default(),
     };
@@ -170,6 +170,9 @@ fn get_description(name: &str) -> Option<String> {
         CREATED_BEFORE => Some("Return items that were created before this date and time (ISO 8601 format).".to_string()),
         UPDATED_AFTER => Some("Return items that were last updated after this date and time (ISO 8601 format).".to_string()),
             UPDATED_BEFORE => Some("Return items that were last updated before this date and time (ISO 8601 format).".to_string()),
+        FETCH_ALL_KEY => Some("Set to true to follow the cursors of the platform and return every page at once, or page by page as newline delimited json with an `Accept: application/x-ndjson` header.".to_string()),
+        MAX_PAGES_KEY => Some("The maximum number of pages to fetch when fetching all pages.".to_string()),
+        MAX_RECORDS_KEY => Some("The maximum number of items to return when fetching all pages.".to_string()),
         _ => None
     }
 }
@@ -283,6 +286,9 @@ fn parameter(action: &CrudAction) -> Vec<ReferenceOr<Parameter>> {
             CREATED_BEFORE,
             UPDATED_AFTER,
             UPDATED_BEFORE,
+            FETCH_ALL_KEY,
+            MAX_PAGES_KEY,
+            MAX_RECORDS_KEY,
         ]
         .iter()
         .map(|name| {
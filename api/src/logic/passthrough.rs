@@ -1,6 +1,7 @@
 use super::get_connection;
 use crate::{domain::metrics::Metric, server::AppState};
 use axum::{
+    body::{Body, Bytes},
     extract::{Query, State},
     response::IntoResponse,
     routing::get,
@@ -9,8 +10,11 @@ use axum::{
 use bson::doc;
 use cache::local::LocalCacheExt;
 use chrono::Utc;
-use http::{header::CONTENT_LENGTH, HeaderMap, HeaderName, Method, Uri};
-use hyper::body::Bytes;
+use futures::StreamExt;
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
+    HeaderMap, HeaderName, Method, Uri,
+};
 use mongodb::options::FindOneOptions;
 use osentities::connection_model_definition::SparseCMD;
 use osentities::{
@@ -19,12 +23,12 @@ use osentities::{
     encrypted_access_key::EncryptedAccessKey,
     event_access::EventAccess,
     prefix::IdPrefix,
-    AccessKey, ApplicationError, Event, Id, InternalError, Store, META, PASSWORD_LENGTH,
-    QUERY_BY_ID_PASSTHROUGH,
+    AccessKey, ApplicationError, Event, Id, Store, META, PASSWORD_LENGTH, QUERY_BY_ID_PASSTHROUGH,
 };
 use rand::distributions::{Alphanumeric, DistString};
 use serde_json::json;
 use std::{collections::HashMap, sync::Arc};
+use tokio::runtime::Handle;
 use tracing::{error, info};
 use unified::domain::UnifiedMetadataBuilder;
 
@@ -125,6 +129,12 @@ pub async fn passthrough_request(
                 headers.insert(CONTENT_LENGTH, value.clone());
             }
             _ => {
+                // Bodies are returned as they are, so clients need these to read the ones that
+                // are not JSON such as file downloads
+                if key == CONTENT_TYPE || key == CONTENT_DISPOSITION {
+                    headers.insert(key.clone(), value.clone());
+                }
+
                 if let Ok(header_name) =
                     HeaderName::try_from(format!("{PICA_PASSTHROUGH_HEADER}-{key}"))
                 {
@@ -147,7 +157,7 @@ pub async fn passthrough_request(
         .connection_model_definition_string_key
         .clone();
 
-    tokio::spawn(async move {
+    let report_event = async move {
         let connection_secret_header: Option<String> =
             connection_secret_header.to_str().map(|a| a.to_owned()).ok();
 
@@ -269,23 +279,115 @@ pub async fn passthrough_request(
                 };
             }
         };
-    });
+    };
 
-    tokio::spawn(async move {
+    let report_metric = async move {
         let metric = Metric::passthrough(connection);
         if let Err(e) = state.metric_tx.send(metric).await {
             error!("Could not send metric to receiver: {e}");
         }
+    };
+
+    // Bodies are streamed so large downloads and event streams aren't held in memory, they are
+    // reported once the caller is done reading them
+    let runtime = Handle::current();
+    let body = stream_body(model_execution_result, move || {
+        runtime.spawn(report_event);
+        runtime.spawn(report_metric);
     });
 
-    let bytes = model_execution_result.bytes().await.map_err(|e| {
-        error!(
-            "Error retrieving bytes from response in passthrough endpoint: {:?}",
-            e
-        );
+    Ok((request_status_code, headers, body))
+}
+
+/// Streams the body of `response` back to the caller, running `complete` once it was fully sent
+/// or the caller went away
+fn stream_body(response: reqwest::Response, complete: impl FnOnce() + Send + 'static) -> Body {
+    let completion = Completion(Some(Box::new(complete)));
+
+    let body = response.bytes_stream().map(move |chunk| {
+        let _ = &completion;
+
+        chunk.inspect_err(|e| {
+            error!("Error streaming response body in passthrough endpoint: {e}");
+        })
+    });
+
+    Body::from_stream(body)
+}
+
+/// Runs its callback when dropped, which happens once a streamed body was fully sent or the
+/// caller went away
+struct Completion(Option<Box<dyn FnOnce() + Send>>);
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if let Some(complete) = self.0.take() {
+            complete();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Server;
+    use std::sync::mpsc;
+
+    async fn response(server: &Server) -> reqwest::Response {
+        reqwest::get(format!("{}/download", server.url()))
+            .await
+            .expect("Failed to send request")
+    }
+
+    #[tokio::test]
+    async fn test_completes_once_body_is_sent() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/download")
+            .with_body("a".repeat(64 * 1024))
+            .create_async()
+            .await;
+
+        let (completed, completions) = mpsc::channel();
+        let body = stream_body(response(&server).await, move || {
+            let _ = completed.send(());
+        });
+
+        assert!(completions.try_recv().is_err());
+
+        let bytes = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .expect("Failed to read body");
+        assert_eq!(bytes.len(), 64 * 1024);
+
+        assert!(completions.try_recv().is_ok());
+        assert!(completions.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_completes_when_caller_goes_away() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/download")
+            .with_chunked_body(|writer| writer.write_all(&[b'a'; 1024 * 1024]))
+            .create_async()
+            .await;
+
+        let (completed, completions) = mpsc::channel();
+        let mut stream = stream_body(response(&server).await, move || {
+            let _ = completed.send(());
+        })
+        .into_data_stream();
+
+        stream
+            .next()
+            .await
+            .expect("Body ended early")
+            .expect("Failed to read chunk");
+        assert!(completions.try_recv().is_err());
 
-        InternalError::script_error("Error retrieving bytes from response", None)
-    })?;
+        drop(stream);
 
-    Ok((request_status_code, headers, bytes))
+        assert!(completions.try_recv().is_ok());
+    }
 }
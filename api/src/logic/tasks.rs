@@ -1,17 +1,28 @@
-use super::{create, delete, read, update, HookExt, PublicExt, RequestExt};
-use crate::server::{AppState, AppStores};
+use super::{create, delete, read, update, HookExt, PublicExt, ReadResponse, RequestExt};
+use crate::{
+    helper::shape_mongo_filter,
+    router::ServerResponse,
+    server::{AppState, AppStores},
+};
 use axum::{
-    routing::{patch, post},
-    Router,
+    extract::{Path, Query, State},
+    routing::{get, patch, post},
+    Extension, Json, Router,
 };
-use chrono::Utc;
+use bson::doc;
 use fake::Dummy;
 use osentities::{
-    event_access::EventAccess, prefix::IdPrefix, record_metadata::RecordMetadata, task::Task, Id,
+    event_access::EventAccess,
+    prefix::IdPrefix,
+    record_metadata::RecordMetadata,
+    schedule::TaskSchedule,
+    task::{Task, TaskCallback, TaskRetryPolicy, TaskRun, TaskState, TaskTarget},
+    ApplicationError, Id, InternalError, PicaError,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::try_join;
 
 pub fn get_router() -> Router<Arc<AppState>> {
     Router::new()
@@ -23,16 +34,45 @@ pub fn get_router() -> Router<Arc<AppState>> {
             "/:id",
             patch(update::<CreateRequest, Task>).delete(delete::<CreateRequest, Task>),
         )
+        .route("/:id/replay", post(replay))
+        .route("/:id/runs", get(runs))
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Dummy)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateRequest {
     pub start_time: i64,
+    /// Ignored when the task has a target
+    #[serde(default)]
     pub endpoint: String,
     pub payload: Value,
+    #[serde(with = "http_serde_ext_ios::method", default = "default_method")]
+    #[dummy(expr = "http::Method::POST")]
+    pub method: http::Method,
+    #[serde(default)]
+    #[dummy(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    #[dummy(default)]
+    pub query_params: BTreeMap<String, String>,
+    #[serde(default)]
+    #[dummy(default)]
+    pub target: Option<TaskTarget>,
+    #[serde(default)]
+    #[dummy(default)]
+    pub callback: Option<TaskCallback>,
     #[serde(rename = "await")]
     pub r#await: bool,
+    #[serde(default)]
+    #[dummy(default)]
+    pub retry_policy: TaskRetryPolicy,
+    #[serde(default)]
+    #[dummy(default)]
+    pub schedule: Option<TaskSchedule>,
+}
+
+fn default_method() -> http::Method {
+    http::Method::POST
 }
 
 impl RequestExt for CreateRequest {
@@ -41,14 +81,28 @@ impl RequestExt for CreateRequest {
     fn from(&self) -> Option<Task> {
         Some(Task {
             id: Id::now(IdPrefix::Task),
-            start_time: Utc::now().timestamp_millis(),
+            start_time: self.start_time,
             worker_id: 0,
             end_time: None,
             payload: self.payload.clone(),
             endpoint: self.endpoint.clone(),
+            method: self.method.clone(),
+            headers: self.headers.clone(),
+            query_params: self.query_params.clone(),
+            target: self.target.clone(),
+            callback: self.callback.clone(),
             status: None,
             r#await: self.r#await,
             log_trail: vec![],
+            schedule: self.schedule.clone(),
+            retry_policy: self.retry_policy.clone(),
+            attempts: 0,
+            state: TaskState::Pending,
+            last_error: None,
+            claimed_by: None,
+            lease_expires_at: None,
+            ownership: None,
+            environment: None,
             metadata: RecordMetadata::default(),
         })
     }
@@ -57,9 +111,100 @@ impl RequestExt for CreateRequest {
         stores.tasks
     }
 
-    fn access(&self, _: Arc<EventAccess>) -> Option<Self::Output> {
-        self.from()
+    fn access(&self, event_access: Arc<EventAccess>) -> Option<Self::Output> {
+        let mut task = self.from()?;
+        task.ownership = Some(event_access.ownership.clone());
+        task.environment = Some(event_access.environment);
+        // Tasks can only call the connections of the access key that created them
+        if let Some(target) = task.target.as_mut() {
+            target.ownership_id = Some(event_access.ownership.id.to_string());
+        }
+        Some(task)
     }
 }
 impl HookExt<Task> for CreateRequest {}
-impl PublicExt<Task> for CreateRequest {}
+impl PublicExt<Task> for CreateRequest {
+    fn public(task: Task) -> Value {
+        task.to_public()
+    }
+}
+
+/// Executes a dead-lettered task again, dead-lettered tasks can be listed with `?state=deadLettered`
+async fn replay(
+    access: Option<Extension<Arc<EventAccess>>>,
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let store = &state.app_stores.tasks;
+
+    let mut query = shape_mongo_filter(None, access.map(|Extension(e)| e), None);
+    query.filter.insert("_id", &id);
+
+    let Some(mut task) = store.get_one(query.filter).await? else {
+        return Err(ApplicationError::not_found(
+            &format!("Task with id {id} not found"),
+            None,
+        ));
+    };
+
+    if task.state != TaskState::DeadLettered {
+        return Err(ApplicationError::bad_request(
+            "Only dead-lettered tasks can be replayed",
+            None,
+        ));
+    }
+
+    task.replay();
+
+    let document = bson::to_document(&task).map_err(|e| {
+        tracing::error!("Could not serialize task into document: {e}");
+        InternalError::serialize_error(e.to_string().as_str(), None)
+    })?;
+
+    store.update_one(&id, doc! { "$set": document }).await?;
+
+    Ok(Json(ServerResponse::new("replay", task.to_public())))
+}
+
+/// Executions of a task, most recent first
+async fn runs(
+    access: Option<Extension<Arc<EventAccess>>>,
+    Path(id): Path<String>,
+    query: Option<Query<BTreeMap<String, String>>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ServerResponse<ReadResponse<TaskRun>>>, PicaError> {
+    let mut owned = shape_mongo_filter(None, access.map(|Extension(e)| e), None).filter;
+    owned.insert("_id", &id);
+
+    if state.app_stores.tasks.count(owned, Some(1)).await? == 0 {
+        return Err(ApplicationError::not_found(
+            &format!("Task with id {id} not found"),
+            None,
+        ));
+    }
+
+    let mut query = shape_mongo_filter(query, None, None);
+    query.filter.insert("taskId", id);
+
+    let store = &state.app_stores.task_runs;
+    let count = store.count(query.filter.clone(), None);
+    let find = store.get_many(
+        Some(query.filter),
+        None,
+        Some(doc! { "startedAt": -1 }),
+        Some(query.limit),
+        Some(query.skip),
+    );
+
+    let (total, rows) = try_join!(count, find)?;
+
+    Ok(Json(ServerResponse::new(
+        "runs",
+        ReadResponse {
+            rows,
+            total,
+            skip: query.skip,
+            limit: query.limit,
+        },
+    )))
+}
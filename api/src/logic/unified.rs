@@ -1,27 +1,41 @@
 use super::get_connection;
 use crate::{domain::config::Headers, domain::metrics::Metric, server::AppState};
 use axum::{
-    extract::{Path, Query, State},
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequest, Path, Query, Request, State},
     response::{IntoResponse, Response},
     routing::{delete, get, patch, post, put},
     Extension, Json, Router,
 };
 use bson::doc;
 use convert_case::{Case, Casing};
-use http::{HeaderMap, HeaderName};
+use futures::{future, stream, Stream, StreamExt};
+use http::{
+    header::{ACCEPT, CONTENT_TYPE},
+    HeaderMap, HeaderName, HeaderValue,
+};
 use osentities::{
     connection_model_definition::CrudAction,
     constant::{PASSWORD_LENGTH, PICA_PASSTHROUGH_HEADER},
     destination::Action,
     encrypted_access_key::EncryptedAccessKey,
     event_access::EventAccess,
-    AccessKey, ApplicationError, Event, InternalError, META,
+    AccessKey, ApplicationError, Connection, Event, InternalError, PicaError, META,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
 use tracing::error;
-use unified::domain::RequestCrudBuilder;
+use unified::{
+    domain::RequestCrudBuilder,
+    multipart::{self, is_multipart},
+    pagination::{merge_pages, PageBudget},
+    unified::UnifiedResponse,
+};
+
+/// Content type of lists streamed page by page
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
 
 pub fn get_router() -> Router<Arc<AppState>> {
     Router::new()
@@ -34,6 +48,42 @@ pub fn get_router() -> Router<Arc<AppState>> {
         .route("/:model/:id", delete(delete_request))
 }
 
+/// Body of unified writes, either JSON or `multipart/form-data` decoded into JSON with its
+/// files as base64 file parts
+pub struct UnifiedBody(pub Value);
+
+#[async_trait]
+impl<S> FromRequest<S> for UnifiedBody
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_multipart(req.headers()) {
+            let Json(body) = Json::<Value>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            return Ok(Self(body));
+        }
+
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        multipart::decode(&content_type, body)
+            .await
+            .map(Self)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PathParams {
     pub id: String,
@@ -71,7 +121,7 @@ pub async fn update_request(
     Path(params): Path<PathParams>,
     headers: HeaderMap,
     query_params: Option<Query<HashMap<String, String>>>,
-    Json(body): Json<Value>,
+    UnifiedBody(body): UnifiedBody,
 ) -> impl IntoResponse {
     process_request(
         access,
@@ -96,7 +146,7 @@ pub async fn upsert_request(
     Path(model): Path<String>,
     headers: HeaderMap,
     query_params: Option<Query<HashMap<String, String>>>,
-    Json(body): Json<Value>,
+    UnifiedBody(body): UnifiedBody,
 ) -> impl IntoResponse {
     process_request(
         access,
@@ -169,7 +219,7 @@ pub async fn create_request(
     Path(model): Path<String>,
     headers: HeaderMap,
     query_params: Option<Query<HashMap<String, String>>>,
-    Json(body): Json<Value>,
+    UnifiedBody(body): UnifiedBody,
 ) -> impl IntoResponse {
     process_request(
         access,
@@ -218,7 +268,7 @@ pub async fn process_request(
     query_params: Option<Query<HashMap<String, String>>>,
     action: Action,
     payload: Option<Value>,
-) -> impl IntoResponse {
+) -> Result<Response, PicaError> {
     let Some(connection_key_header) = headers.get(&state.config.headers.connection_header) else {
         return Err(ApplicationError::bad_request(
             "Missing connection key header",
@@ -237,12 +287,33 @@ pub async fn process_request(
         e
     })?;
 
-    let Query(query_params) = query_params.unwrap_or_default();
+    let Query(mut query_params) = query_params.unwrap_or_default();
+
+    // Only lists can be fetched across their pages
+    let budget = match &action {
+        Action::Unified {
+            action: CrudAction::GetMany,
+            ..
+        } => PageBudget::from_query_params(
+            &mut query_params,
+            state.config.unified_fetch_all_max_pages,
+        )?,
+        _ => None,
+    };
+    let stream_pages = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains(NDJSON_CONTENT_TYPE));
 
     let access_key_header_value = headers.get(&state.config.headers.auth_header).cloned();
 
     remove_event_headers(&mut headers, &state.config.headers);
 
+    // Multipart bodies were decoded into JSON by `UnifiedBody`
+    if is_multipart(&headers) {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    }
+
     let Action::Unified {
         name: model_name,
         action: action_name,
@@ -256,128 +327,257 @@ pub async fn process_request(
         connection.platform, connection.platform_version, model_name, action_name,
     );
 
-    let mut response = state
-        .extractor_caller
-        .dispatch_unified_request(
-            connection.clone(),
-            action.clone(),
-            state.config.environment,
-            RequestCrudBuilder::default()
-                .headers(headers)
-                .query_params(query_params)
-                .body(payload)
-                .build()
-                .map_err(|e| {
-                    error!("Error building request crud: {e}");
-                    InternalError::invalid_argument(
-                        &format!("Error building request crud: {e}"),
-                        None,
-                    )
-                })?,
-            state.app_caches.connection_model_definition.clone(),
-        )
-        .await
-        .inspect_err(|e| {
-            error!(
-                "Error executing connection model definition in unified endpoint: {}",
-                e.to_string()
-            );
+    let params = RequestCrudBuilder::default()
+        .headers(headers)
+        .query_params(query_params)
+        .body(payload)
+        .build()
+        .map_err(|e| {
+            error!("Error building request crud: {e}");
+            InternalError::invalid_argument(&format!("Error building request crud: {e}"), None)
         })?;
 
-    *response.response.headers_mut() = response
-        .response
-        .headers()
-        .iter()
-        .map(|(key, value)| {
-            (
-                HeaderName::try_from(format!("{PICA_PASSTHROUGH_HEADER}-{key}")).unwrap(),
-                value.clone(),
-            )
-        })
-        .collect::<HeaderMap>();
-
-    let (parts, body) = response.response.into_parts();
-    let mut metadata = body
-        .get(META)
-        .unwrap_or(&response.metadata.as_value())
-        .clone();
-
-    if let Some(Ok(encrypted_access_key)) =
-        access_key_header_value.map(|v| v.to_str().map(|s| s.to_string()))
-    {
-        if let Ok(encrypted_access_key) = EncryptedAccessKey::parse(&encrypted_access_key) {
-            let password: [u8; PASSWORD_LENGTH] = state
-                .config
-                .event_access_password
-                .as_bytes()
-                .try_into()
-                .map_err(|e| {
-                    error!("event_access_password is not 32 bytes in length: {e}");
-                    InternalError::decryption_error(
-                        "event_access_password is not 32 bytes in length",
-                        None,
-                    )
-                    .set_meta(&metadata)
-                })?;
+    let reporter = Reporter {
+        state: state.clone(),
+        connection: connection.clone(),
+        action: action.clone(),
+        event_name,
+        access_key_header_value,
+    };
 
-            let access_key = AccessKey::parse(&encrypted_access_key, &password).map_err(|e| {
-                error!("Could not decrypt access key: {e}");
-                InternalError::decryption_error("Could not decrypt access key", None)
-                    .set_meta(&metadata)
-            })?;
-            let status_code = parts.status.as_u16();
+    let log_error = |e: &PicaError| {
+        error!(
+            "Error executing connection model definition in unified endpoint: {}",
+            e.to_string()
+        );
+    };
 
-            if let Some(meta) = metadata.as_object_mut() {
-                meta.insert("status_code".to_string(), json!(status_code));
-                meta.insert("path".to_string(), json!("v1/unified"));
-            };
+    let (response, body) = match budget {
+        None => {
+            let response = state
+                .extractor_caller
+                .dispatch_unified_request(
+                    connection,
+                    action,
+                    state.config.environment,
+                    params,
+                    state.app_caches.connection_model_definition.clone(),
+                )
+                .await
+                .inspect_err(log_error)?;
 
-            let body = serde_json::to_string(&json!({
-                META: metadata,
-            }))
-            .map_err(|e| {
-                error!("Could not serialize meta body to string: {e}");
-                InternalError::invalid_argument("Could not serialize meta body to string", None)
-                    .set_meta(&metadata)
-            })?;
-
-            let name = if parts.status.is_success() {
-                format!("{event_name}::request-succeeded",)
-            } else {
-                format!("{event_name}::request-failed",)
-            };
-            let event = Event::new(
-                &access_key,
-                &encrypted_access_key,
-                &name,
-                parts.headers.clone(),
-                body,
+            reporter.report(response).await?
+        }
+        Some(budget) => {
+            let pages = state.extractor_caller.dispatch_unified_pages(
+                connection,
+                action,
+                state.config.environment,
+                params,
+                state.app_caches.connection_model_definition.clone(),
+                budget,
             );
-            if let Err(e) = state.event_tx.send(event).await {
-                error!("Could not send event to receiver: {e}");
+
+            if stream_pages {
+                return reporter.stream(pages).await.inspect_err(log_error);
             }
+
+            // Each page is a request to the platform, tracked on its own
+            let pages = pages.collect::<Vec<_>>().await;
+            for page in pages.iter().flatten() {
+                reporter.track(page).await?;
+            }
+
+            let merged = pages
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|pages| {
+                    merge_pages(pages)
+                        .ok_or_else(|| InternalError::unknown("No page was fetched", None))
+                })
+                .inspect_err(log_error)?;
+
+            reporter.shape(merged)
         }
     };
 
-    let metric = Metric::unified(connection.clone(), action);
-    if let Err(e) = state.metric_tx.send(metric).await {
-        error!("Could not send metric to receiver: {e}");
+    Ok((response, Json(body)).into_response())
+}
+
+/// Sends the event and the metric of each unified response returned to the caller
+#[derive(Clone)]
+struct Reporter {
+    state: Arc<AppState>,
+    connection: Arc<Connection>,
+    action: Action,
+    event_name: String,
+    access_key_header_value: Option<HeaderValue>,
+}
+
+impl Reporter {
+    async fn report(&self, response: UnifiedResponse) -> Result<(Response<()>, Value), PicaError> {
+        self.track(&response).await?;
+
+        Ok(self.shape(response))
+    }
+
+    /// Sends the event and the metric of a response
+    async fn track(&self, response: &UnifiedResponse) -> Result<(), PicaError> {
+        let state = &self.state;
+        let status = response.response.status();
+        let mut metadata = metadata(response);
+
+        if let Some(Ok(encrypted_access_key)) = self
+            .access_key_header_value
+            .as_ref()
+            .map(|v| v.to_str().map(|s| s.to_string()))
+        {
+            if let Ok(encrypted_access_key) = EncryptedAccessKey::parse(&encrypted_access_key) {
+                let password: [u8; PASSWORD_LENGTH] = state
+                    .config
+                    .event_access_password
+                    .as_bytes()
+                    .try_into()
+                    .map_err(|e| {
+                        error!("event_access_password is not 32 bytes in length: {e}");
+                        InternalError::decryption_error(
+                            "event_access_password is not 32 bytes in length",
+                            None,
+                        )
+                        .set_meta(&metadata)
+                    })?;
+
+                let access_key =
+                    AccessKey::parse(&encrypted_access_key, &password).map_err(|e| {
+                        error!("Could not decrypt access key: {e}");
+                        InternalError::decryption_error("Could not decrypt access key", None)
+                            .set_meta(&metadata)
+                    })?;
+                let status_code = status.as_u16();
+
+                if let Some(meta) = metadata.as_object_mut() {
+                    meta.insert("status_code".to_string(), json!(status_code));
+                    meta.insert("path".to_string(), json!("v1/unified"));
+                };
+
+                let body = serde_json::to_string(&json!({
+                    META: metadata,
+                }))
+                .map_err(|e| {
+                    error!("Could not serialize meta body to string: {e}");
+                    InternalError::invalid_argument("Could not serialize meta body to string", None)
+                        .set_meta(&metadata)
+                })?;
+
+                let name = if status.is_success() {
+                    format!("{}::request-succeeded", self.event_name)
+                } else {
+                    format!("{}::request-failed", self.event_name)
+                };
+                let event = Event::new(
+                    &access_key,
+                    &encrypted_access_key,
+                    &name,
+                    passthrough_headers(response.response.headers()),
+                    body,
+                );
+                if let Err(e) = state.event_tx.send(event).await {
+                    error!("Could not send event to receiver: {e}");
+                }
+            }
+        };
+
+        let metric = Metric::unified(self.connection.clone(), self.action.clone());
+        if let Err(e) = state.metric_tx.send(metric).await {
+            error!("Could not send metric to receiver: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Shapes a response as returned to the caller
+    fn shape(&self, response: UnifiedResponse) -> (Response<()>, Value) {
+        let metadata = metadata(&response);
+        let (mut parts, body) = response.response.into_parts();
+        parts.headers = passthrough_headers(&parts.headers);
+
+        let response = Response::from_parts(parts, ());
+
+        if response.status().is_client_error() || response.status().is_server_error() {
+            let body = json!({
+                META: metadata,
+                "error": body,
+            });
+
+            (response, body)
+        } else {
+            (response, body)
+        }
     }
 
-    let response = Response::from_parts(parts, ());
+    /// Streams the pages as newline delimited json once the first one is fetched, so failing to
+    /// fetch it returns the usual error response. A later page failing ends the stream with its
+    /// error.
+    async fn stream(
+        self,
+        pages: impl Stream<Item = Result<UnifiedResponse, PicaError>> + Send + 'static,
+    ) -> Result<Response, PicaError> {
+        let mut pages = Box::pin(pages);
+        let first = pages
+            .next()
+            .await
+            .unwrap_or_else(|| Err(InternalError::unknown("No page was fetched", None)))?;
+        let (response, first) = self.report(first).await?;
+
+        let rest = pages.then(move |page| {
+            let reporter = self.clone();
 
-    if response.status().is_client_error() || response.status().is_server_error() {
-        let body = json!({
-            META: metadata,
-            "error": body,
+            async move {
+                match page {
+                    Ok(page) => reporter.report(page).await.map(|(_, body)| body),
+                    Err(e) => Err(e),
+                }
+                .unwrap_or_else(|e| e.as_response_json())
+            }
         });
+        let lines = stream::once(future::ready(first)).chain(rest).map(|line| {
+            let mut line = serde_json::to_vec(&line).unwrap_or_default();
+            line.push(b'\n');
+            Ok::<_, Infallible>(line)
+        });
+
+        let mut response = response.map(|_| Body::from_stream(lines));
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(NDJSON_CONTENT_TYPE));
 
-        Ok((response, Json(body)))
-    } else {
-        Ok((response, Json(body)))
+        Ok(response)
     }
 }
 
+/// Metadata of a response, the one returned by the platform mapping if any
+fn metadata(response: &UnifiedResponse) -> Value {
+    response
+        .response
+        .body()
+        .get(META)
+        .cloned()
+        .unwrap_or_else(|| response.metadata.as_value())
+}
+
+/// Headers returned by the platform, prefixed to tell them apart from the headers of the api
+fn passthrough_headers(headers: &HeaderMap) -> HeaderMap {
+    headers
+        .iter()
+        .map(|(key, value)| {
+            (
+                HeaderName::try_from(format!("{PICA_PASSTHROUGH_HEADER}-{key}")).unwrap(),
+                value.clone(),
+            )
+        })
+        .collect()
+}
+
 fn remove_event_headers(headers: &mut HeaderMap, headers_config: &Headers) {
     headers.remove(&headers_config.auth_header);
     headers.remove(&headers_config.connection_header);
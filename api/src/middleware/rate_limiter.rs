@@ -7,20 +7,26 @@ use axum::{
     response::{IntoResponse, Response},
     Extension,
 };
-use cache::remote::RedisCache;
-use http::{HeaderName, Request};
-use osentities::{event_access::EventAccess, ApplicationError};
-use redis::AsyncCommands;
-use std::sync::Arc;
-use tokio::sync::{
-    mpsc::{channel, Sender},
-    oneshot,
+use bson::doc;
+use cache::{
+    local::{ConnectionHeaderCache, ConnectionHeaderKey, LocalCacheExt},
+    remote::RedisCache,
+    window::{SlidingWindow, SlidingWindows, WindowCount},
 };
+use chrono::Utc;
+use http::{header::RETRY_AFTER, HeaderMap, HeaderName, Request};
+use osentities::{event_access::EventAccess, ApplicationError, Connection, MongoStore};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc::Sender;
 use tracing::warn;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct RateLimiter {
-    tx: Sender<(Arc<str>, oneshot::Sender<u64>)>,
+    windows: SlidingWindows,
+    throughput_key: String,
+    window: Duration,
+    connections: MongoStore<Connection>,
+    connections_cache: ConnectionHeaderCache,
     key_header_name: HeaderName,
     limit_header_name: HeaderName,
     remaining_header_name: HeaderName,
@@ -28,13 +34,44 @@ pub struct RateLimiter {
     metric_tx: Sender<Metric>,
 }
 
+/// Requests counted against a limit over the sliding window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until a request leaves the window and frees up a slot
+    pub reset_secs: u64,
+}
+
+impl Usage {
+    fn new(limit: u64, allowed: bool, count: WindowCount) -> Self {
+        Self {
+            allowed,
+            limit,
+            remaining: limit.saturating_sub(count.count),
+            reset_secs: (count.reset_ms.max(0) as u64).div_ceil(1000),
+        }
+    }
+
+    /// The usage reported to the caller out of two limits applying to a request
+    fn tightest(self, other: Usage) -> Usage {
+        match (self.allowed, other.allowed) {
+            (true, false) => other,
+            (false, true) => self,
+            _ if other.remaining < self.remaining => other,
+            _ => self,
+        }
+    }
+}
+
 impl RateLimiter {
     pub async fn from_state(state: Arc<AppState>) -> Result<Self> {
         if !state.config.rate_limit_enabled {
             return Err(anyhow::anyhow!("Rate limiting is disabled"));
         };
 
-        let mut redis = RedisCache::new(&state.config.cache_config)
+        let redis = RedisCache::new(&state.config.cache_config)
             .await
             .inspect(|_| {
                 tracing::info!("Connected to redis at {}", state.config.cache_config.url);
@@ -44,21 +81,6 @@ impl RateLimiter {
                 state.config.cache_config.url
             ))?;
 
-        let (tx, mut rx) = channel::<(Arc<str>, oneshot::Sender<u64>)>(1024);
-
-        let throughput_key = state.config.cache_config.api_throughput_key.clone();
-
-        tokio::spawn(async move {
-            while let Some((id, tx)) = rx.recv().await {
-                let count: u64 = redis
-                    .inner
-                    .hincr(&throughput_key, id.as_ref(), 1)
-                    .await
-                    .unwrap_or_default();
-                let _ = tx.send(count);
-            }
-        });
-
         let key_header_name =
             HeaderName::from_lowercase(state.config.headers.connection_header.as_bytes()).unwrap();
 
@@ -73,7 +95,11 @@ impl RateLimiter {
             HeaderName::from_lowercase(state.config.headers.rate_limit_reset.as_bytes()).unwrap();
 
         Ok(RateLimiter {
-            tx,
+            windows: SlidingWindows::new(redis),
+            throughput_key: state.config.cache_config.api_throughput_key.clone(),
+            window: Duration::from_secs(state.config.rate_limit_window_secs),
+            connections: state.app_stores.connection.clone(),
+            connections_cache: state.app_caches.connections_cache.clone(),
             metric_tx: state.metric_tx.clone(),
             key_header_name,
             limit_header_name,
@@ -82,15 +108,92 @@ impl RateLimiter {
         })
     }
 
-    pub async fn get_request_count(&self, id: Arc<str>) -> u64 {
-        let (tx, rx) = oneshot::channel();
-        match self.tx.send((id, tx)).await {
-            Ok(()) => rx.await.unwrap_or_default(),
+    /// Counts a request against every limit if all of them allow it, so that a request rejected
+    /// by one does not use up the others. Requests are let through if redis can't be reached.
+    pub async fn check(&self, limits: &[(String, u64)]) -> Usage {
+        let windows = limits
+            .iter()
+            .map(|(key, limit)| SlidingWindow {
+                key: format!("{}:{key}", self.throughput_key),
+                limit: *limit,
+                window: self.window,
+            })
+            .collect::<Vec<_>>();
+
+        let usages = match self
+            .windows
+            .check(&windows, Utc::now().timestamp_millis())
+            .await
+        {
+            Ok((allowed, counts)) => limits
+                .iter()
+                .zip(counts)
+                .map(|((_, limit), count)| Usage::new(*limit, allowed, count))
+                .collect::<Vec<_>>(),
             Err(e) => {
-                warn!("Could not send to redis task: {e}");
-                0
+                warn!("Could not check the rate limits: {e}");
+                limits
+                    .iter()
+                    .map(|(_, limit)| Usage {
+                        allowed: true,
+                        limit: *limit,
+                        remaining: *limit,
+                        reset_secs: 0,
+                    })
+                    .collect()
             }
+        };
+
+        usages.into_iter().reduce(Usage::tightest).unwrap_or(Usage {
+            allowed: true,
+            limit: 0,
+            remaining: 0,
+            reset_secs: 0,
+        })
+    }
+
+    /// Throughput limit of the connection the request is made with, if any
+    async fn connection_limit(
+        &self,
+        event_access: &EventAccess,
+        headers: &HeaderMap,
+    ) -> Option<(String, u64)> {
+        let connection_key = headers.get(&self.key_header_name)?;
+
+        let connection = self
+            .connections_cache
+            .get_or_insert_with_filter(
+                &ConnectionHeaderKey {
+                    ownership: event_access.ownership.id.clone(),
+                    header: connection_key.clone(),
+                },
+                self.connections.clone(),
+                doc! {
+                    "key": connection_key.to_str().ok()?,
+                    "ownership.buildableId": event_access.ownership.id.as_ref(),
+                    "deleted": false
+                },
+                None,
+            )
+            .await
+            // Unknown connections are rejected by the handlers
+            .ok()?;
+
+        // Connections without a throughput limit are only limited by their access key
+        if connection.throughput.limit == 0 {
+            return None;
         }
+
+        Some((
+            format!("connection:{}", connection.throughput.key),
+            connection.throughput.limit,
+        ))
+    }
+
+    fn insert_headers(&self, headers: &mut HeaderMap, usage: Usage) {
+        headers.insert(self.limit_header_name.clone(), usage.limit.into());
+        headers.insert(self.remaining_header_name.clone(), usage.remaining.into());
+        headers.insert(self.reset_header_name.clone(), usage.reset_secs.into());
     }
 }
 
@@ -100,13 +203,16 @@ pub async fn rate_limit_middleware(
     req: Request<Body>,
     next: Next,
 ) -> Result<Response, Response> {
-    let throughput = event_access.throughput;
+    let limits = std::iter::once((
+        event_access.ownership.id.to_string(),
+        event_access.throughput,
+    ))
+    .chain(state.connection_limit(&event_access, req.headers()).await)
+    .collect::<Vec<_>>();
 
-    let count = state
-        .get_request_count(event_access.ownership.id.clone())
-        .await;
+    let usage = state.check(&limits).await;
 
-    if count >= throughput {
+    if !usage.allowed {
         let _ = state
             .metric_tx
             .send(Metric::rate_limited(
@@ -118,22 +224,54 @@ pub async fn rate_limit_middleware(
             ApplicationError::too_many_requests("Rate limit exceeded", None).into_response();
 
         let headers = res.headers_mut();
-
-        headers.insert(state.limit_header_name.clone(), throughput.into());
-        headers.insert(state.remaining_header_name.clone(), 0.into());
-        headers.insert(state.reset_header_name.clone(), 60.into());
+        state.insert_headers(headers, usage);
+        headers.insert(RETRY_AFTER, usage.reset_secs.into());
 
         Err(res)
     } else {
         let mut res = next.run(req).await;
-        let headers = res.headers_mut();
+        state.insert_headers(res.headers_mut(), usage);
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        headers.insert(state.limit_header_name.clone(), throughput.into());
-        headers.insert(
-            state.remaining_header_name.clone(),
-            (throughput - count).into(),
+    fn count(count: u64, reset_ms: i64) -> WindowCount {
+        WindowCount { count, reset_ms }
+    }
+
+    #[test]
+    fn test_usage_from_window() {
+        let usage = Usage::new(10, true, count(4, 1_500));
+        assert_eq!(
+            usage,
+            Usage {
+                allowed: true,
+                limit: 10,
+                remaining: 6,
+                reset_secs: 2,
+            }
         );
-        headers.insert(state.reset_header_name.clone(), 60.into());
-        Ok(res)
+
+        let usage = Usage::new(10, false, count(10, 0));
+        assert!(!usage.allowed);
+        assert_eq!(usage.remaining, 0);
+        assert_eq!(usage.reset_secs, 0);
+    }
+
+    #[test]
+    fn test_tightest_usage() {
+        let access_key = Usage::new(100, true, count(10, 60_000));
+        let connection = Usage::new(20, true, count(15, 30_000));
+        assert_eq!(access_key.tightest(connection), connection);
+        assert_eq!(connection.tightest(access_key), connection);
+
+        let denied = Usage::new(200, false, count(200, 1_000));
+        let spared = Usage::new(20, false, count(15, 30_000));
+        assert_eq!(spared.tightest(denied), denied);
+        assert_eq!(denied.tightest(spared), denied);
     }
 }
@@ -1,6 +1,6 @@
 use crate::{
     logic::{
-        connection, connection_definition,
+        circuit_breaker, connection, connection_definition,
         connection_model_definition::{get_available_actions, test_connection_model_definition},
         connection_model_schema::{
             public_get_connection_model_schema, PublicGetConnectionModelSchema,
@@ -40,6 +40,7 @@ pub async fn get_router(state: &Arc<AppState>) -> Router<Arc<AppState>> {
         .nest("/knowledge", knowledge::get_router())
         .nest("/tasks", tasks::get_router())
         .nest("/metrics", metrics::get_router())
+        .nest("/circuit-breakers", circuit_breaker::get_router())
         .nest("/oauth", oauth::get_router())
         .nest("/passthrough", passthrough::get_router())
         .nest("/secrets", secrets::get_router())
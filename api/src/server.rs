@@ -1,7 +1,7 @@
 use crate::{
     domain::{
         track::{LoggerTracker, PosthogTracker, Track, TrackedMetric},
-        ConnectionsConfig, K8sMode, Metric,
+        ConnectionsConfig, K8sMode, Metric, ResponseCacheBackend,
     },
     helper::{K8sDriver, K8sDriverImpl, K8sDriverLogger},
     logic::{
@@ -12,9 +12,13 @@ use crate::{
 };
 use anyhow::{anyhow, Context, Result};
 use axum::Router;
-use cache::local::{
-    ConnectionDefinitionCache, ConnectionHeaderCache, ConnectionModelDefinitionCacheIdKey,
-    ConnectionModelDefinitionCacheStringKey, ConnectionOAuthDefinitionCache, EventAccessCache,
+use cache::{
+    local::{
+        ConnectionDefinitionCache, ConnectionHeaderCache, ConnectionModelDefinitionCacheIdKey,
+        ConnectionModelDefinitionCacheStringKey, ConnectionOAuthDefinitionCache, EventAccessCache,
+    },
+    remote::RedisCache,
+    window::SlidingWindows,
 };
 use mongodb::{options::UpdateOptions, Client, Database};
 use osentities::{
@@ -28,14 +32,19 @@ use osentities::{
     page::PlatformPage,
     secret::Secret,
     secrets::SecretServiceProvider,
-    task::Task,
+    task::{Task, TaskRun},
     user::UserClient,
     Connection, Event, GoogleKms, IOSKms, PlatformData, PublicConnection, SecretExt, Store,
 };
 use std::{sync::Arc, time::Duration};
 use tokio::{net::TcpListener, sync::mpsc::Sender, time::timeout, try_join};
 use tracing::{error, info, trace, warn};
-use unified::unified::{UnifiedCacheTTLs, UnifiedDestination};
+use unified::{
+    algebra::jsruntime::{JSRuntimeConfig, JSRuntimeImpl},
+    circuit_breaker::CircuitBreakerConfig,
+    response_cache::ResponseCache,
+    unified::{UnifiedCacheTTLs, UnifiedDestination},
+};
 
 #[derive(Clone)]
 pub struct AppStores {
@@ -60,6 +69,7 @@ pub struct AppStores {
     pub secrets: MongoStore<Secret>,
     pub settings: MongoStore<Settings>,
     pub tasks: MongoStore<Task>,
+    pub task_runs: MongoStore<TaskRun>,
 }
 
 #[derive(Clone)]
@@ -103,6 +113,13 @@ impl Server {
             .connect_timeout(Duration::from_secs(30))
             .pool_idle_timeout(Duration::from_secs(30))
             .build()?;
+        // Passthrough bodies are streamed back to callers, so calls are bound by how long they
+        // go without receiving data rather than by how long they take to complete
+        let streaming_client = reqwest::ClientBuilder::new()
+            .read_timeout(Duration::from_secs(config.passthrough_read_timeout_secs))
+            .connect_timeout(Duration::from_secs(30))
+            .pool_idle_timeout(Duration::from_secs(30))
+            .build()?;
         let model_config = MongoStore::new(&db, &Store::ConnectionModelDefinitions).await?;
         let oauth_config = MongoStore::new(&db, &Store::ConnectionOAuthDefinitions).await?;
         let frontend_oauth_config =
@@ -127,6 +144,7 @@ impl Server {
         let clients = MongoStore::new(&db, &Store::Clients).await?;
         let secrets_store = MongoStore::<Secret>::new(&db, &Store::Secrets).await?;
         let tasks = MongoStore::new(&db, &Store::Tasks).await?;
+        let task_runs = MongoStore::new(&db, &Store::TaskRuns).await?;
 
         let secrets_client: Arc<dyn SecretExt + Sync + Send> = match config.secrets_config.provider
         {
@@ -148,21 +166,54 @@ impl Server {
             _ => Arc::new(LoggerTracker),
         };
 
+        let response_cache = match config.response_cache_backend {
+            ResponseCacheBackend::Local => {
+                ResponseCache::local(config.response_cache_max_mb * 1024 * 1024)
+            }
+            ResponseCacheBackend::Redis => ResponseCache::Remote(
+                RedisCache::new(&config.cache_config)
+                    .await
+                    .with_context(|| "Could not connect to redis for the response cache")?,
+            ),
+        };
+
         let extractor_caller = UnifiedDestination::new(
             config.db_config.clone(),
             config.cache_size,
             secrets_client.clone(),
             UnifiedCacheTTLs {
                 connection_cache_ttl_secs: config.connection_cache_ttl_secs,
+                connection_definition_cache_ttl_secs: config.connection_definition_cache_ttl_secs,
                 connection_model_schema_cache_ttl_secs: config
                     .connection_model_schema_cache_ttl_secs,
                 connection_model_definition_cache_ttl_secs: config
                     .connection_model_definition_cache_ttl_secs,
                 secret_cache_ttl_secs: config.secret_cache_ttl_secs,
             },
+            http_client.clone(),
+            CircuitBreakerConfig {
+                failure_threshold: config.circuit_breaker_failure_threshold,
+                open_duration: Duration::from_secs(config.circuit_breaker_open_secs),
+            },
+            response_cache,
         )
         .await
-        .with_context(|| "Could not initialize extractor caller")?;
+        .with_context(|| "Could not initialize extractor caller")?
+        .with_streaming_client(streaming_client)
+        .with_js_runtime(JSRuntimeImpl::new(JSRuntimeConfig {
+            timeout: Duration::from_millis(config.js_runtime_timeout_ms),
+            max_heap_bytes: config.js_runtime_max_heap_mb * 1024 * 1024,
+            max_scripts: config.js_runtime_max_scripts,
+        }));
+
+        // Without redis every instance gets the whole allowance of the platforms
+        let extractor_caller = match RedisCache::new(&config.cache_config).await {
+            Ok(redis) => extractor_caller.with_shared_rate_limits(SlidingWindows::new(redis)),
+            Err(e) => {
+                warn!("Could not connect to redis to share the rate limits of the platforms: {e}");
+                extractor_caller
+            }
+        };
 
         let app_stores = AppStores {
             db: db.clone(),
@@ -186,6 +237,7 @@ impl Server {
             event,
             clients,
             tasks,
+            task_runs,
         };
 
         let event_access_cache =
@@ -531,6 +531,10 @@ impl TestServer {
             active: Some(true),
             knowledge: None,
             tags: None,
+            rate_limits: vec![],
+            retry_policy: None,
+            response_content: None,
+            cache_ttl_secs: None,
         };
 
         let res = self
@@ -5,4 +5,5 @@ pub mod crud;
 pub mod pagination;
 pub mod passthrough;
 pub mod schema;
+pub mod tasks;
 pub mod unified;
@@ -75,6 +75,10 @@ async fn test_passthrough_api() {
         active: Some(true),
         knowledge: None,
         tags: None,
+        rate_limits: vec![],
+        retry_policy: None,
+        response_content: None,
+        cache_ttl_secs: None,
     };
 
     let create_model_definition_response = server
@@ -0,0 +1,101 @@
+use crate::context::TestServer;
+use http::{Method, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn test_task_runs_only_visible_to_owner() {
+    let db_name = Uuid::new_v4().to_string();
+    let owner = TestServer::new(Some(db_name.clone())).await;
+    let other = TestServer::new(Some(db_name)).await;
+
+    let res = owner
+        .send_request::<Value, Value>(
+            "v1/tasks",
+            Method::POST,
+            Some(&owner.live_key),
+            Some(&json!({
+                "startTime": 0,
+                "endpoint": "https://example.com",
+                "payload": {},
+                "await": false
+            })),
+        )
+        .await
+        .expect("Failed to create task");
+    assert_eq!(res.code, StatusCode::OK);
+
+    let id = res.data["_id"].as_str().expect("Missing task id");
+
+    let res = owner
+        .send_request::<Value, Value>(
+            &format!("v1/tasks/{id}/runs"),
+            Method::GET,
+            Some(&owner.live_key),
+            None,
+        )
+        .await
+        .expect("Failed to read runs");
+    assert_eq!(res.code, StatusCode::OK);
+
+    for key in [&other.live_key, &owner.test_key] {
+        let res = owner
+            .send_request::<Value, Value>(
+                &format!("v1/tasks/{id}/runs"),
+                Method::GET,
+                Some(key),
+                None,
+            )
+            .await
+            .expect("Failed to read runs");
+        assert_eq!(res.code, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn test_replay_only_allowed_to_owner() {
+    let db_name = Uuid::new_v4().to_string();
+    let owner = TestServer::new(Some(db_name.clone())).await;
+    let other = TestServer::new(Some(db_name)).await;
+
+    let res = owner
+        .send_request::<Value, Value>(
+            "v1/tasks",
+            Method::POST,
+            Some(&owner.live_key),
+            Some(&json!({
+                "startTime": 0,
+                "endpoint": "https://example.com",
+                "payload": {},
+                "await": false
+            })),
+        )
+        .await
+        .expect("Failed to create task");
+    assert_eq!(res.code, StatusCode::OK);
+
+    let id = res.data["_id"].as_str().expect("Missing task id");
+
+    let res = other
+        .send_request::<Value, Value>(
+            &format!("v1/tasks/{id}/replay"),
+            Method::POST,
+            Some(&other.live_key),
+            None,
+        )
+        .await
+        .expect("Failed to replay task");
+    assert_eq!(res.code, StatusCode::NOT_FOUND);
+
+    // Found by its owner, but only dead-lettered tasks can be replayed
+    let res = owner
+        .send_request::<Value, Value>(
+            &format!("v1/tasks/{id}/replay"),
+            Method::POST,
+            Some(&owner.live_key),
+            None,
+        )
+        .await
+        .expect("Failed to replay task");
+    assert_eq!(res.code, StatusCode::BAD_REQUEST);
+}
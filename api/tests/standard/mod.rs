@@ -46,6 +46,7 @@ fn test_json_connection_model_definition() {
             )])),
             query_params: None,
             content: Some(ContentType::Json),
+            response_content: None,
             schemas: SchemasInput {
                 headers: None,
                 query_params: None,
@@ -60,6 +61,7 @@ fn test_json_connection_model_definition() {
             },
             responses: vec![],
             paths: None,
+            retry_policy: None,
         }),
         extractor_config: None,
         test_connection_status: TestConnection::default(),
@@ -74,6 +76,8 @@ fn test_json_connection_model_definition() {
         record_metadata: RecordMetadata::test(),
         supported: false,
         knowledge: None,
+        rate_limits: vec![],
+        cache_ttl_secs: None,
     };
 
     assert!(
@@ -279,6 +283,7 @@ fn test_json_connection_definition() {
         hidden: true,
         test_connection: Some(Id::test(IdPrefix::Connection)),
         test_delay_in_millis: None,
+        rate_limits: vec![],
         record_metadata: RecordMetadata::test(),
     };
 
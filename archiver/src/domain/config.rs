@@ -1,15 +1,25 @@
+use super::policy::ArchivePolicies;
+use crate::event::summary::RunStatus;
+use crate::export::ExportFormats;
 use crate::storage::StorageProvider;
+use anyhow::{anyhow, Result};
+use chrono::Duration;
 use envconfig::Envconfig;
 use osentities::database::DatabaseConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
 use strum::{AsRefStr, EnumString};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, AsRefStr)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, AsRefStr, Serialize, Deserialize)]
 #[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
 pub enum Mode {
     Dump,
     DumpDelete,
     NoOp,
+    Restore,
 }
 
 #[derive(Envconfig, Clone)]
@@ -24,6 +34,20 @@ pub struct ArchiverConfig {
     pub gs_storage_uri: String,
     #[envconfig(from = "STORAGE_PROVIDER", default = "google-cloud")]
     pub storage_provider: StorageProvider,
+    #[envconfig(from = "LOCAL_STORAGE_PATH", default = "archives")]
+    pub local_storage_path: String,
+    #[envconfig(from = "S3_BUCKET", default = "event-archives-local")]
+    pub s3_bucket: String,
+    #[envconfig(from = "S3_REGION", default = "us-east-1")]
+    pub s3_region: String,
+    /// Custom endpoint for S3-compatible stores such as MinIO
+    #[envconfig(from = "S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+    #[envconfig(from = "S3_PART_SIZE_BYTES", default = "8388608")]
+    pub s3_part_size_bytes: usize,
+    /// Comma separated list of formats to export (`bson`, `ndjson`), BSON is always included
+    #[envconfig(from = "EXPORT_FORMATS", default = "bson")]
+    pub export_formats: ExportFormats,
     #[envconfig(from = "MAX_RETRIES", default = "3")]
     pub max_retries: u32,
     #[envconfig(from = "READ_BUFFER_SIZE_BYTES", default = "262144")]
@@ -40,8 +64,94 @@ pub struct ArchiverConfig {
     pub concurrent_chunks: usize,
     #[envconfig(from = "SLEEP_AFTER_FINISH_DUMP_SECS", default = "10")]
     pub sleep_after_finish: u64,
+    /// Serves the archiving progress on `/status` and Prometheus metrics on `/metrics`
+    #[envconfig(from = "STATUS_SERVER_ADDRESS", default = "0.0.0.0:5007")]
+    pub status_server_address: SocketAddr,
+    /// Days the summary of a finished run is kept in the archives collection, forever if unset.
+    /// Summaries of runs that uploaded archives are always kept so that those can be restored.
+    #[envconfig(from = "ARCHIVES_RETENTION_DAYS")]
+    pub archives_retention_days: Option<i64>,
+    /// Days the summary of a failed run is kept in the archives collection, forever if unset
+    #[envconfig(from = "ARCHIVES_FAILED_RETENTION_DAYS")]
+    pub archives_failed_retention_days: Option<i64>,
     #[envconfig(from = "MODE", default = "dump")]
     pub mode: Mode,
+    /// Collections archived by this process, only `EVENT_COLLECTION_NAME` is archived if unset
+    #[envconfig(from = "ARCHIVE_POLICIES")]
+    pub archive_policies: Option<ArchivePolicies>,
+    /// Archives whose events overlap `[RESTORE_START_TIME, RESTORE_END_TIME)` (in milliseconds) are restored
+    #[envconfig(from = "RESTORE_START_TIME", default = "0")]
+    pub restore_start_time: i64,
+    #[envconfig(from = "RESTORE_END_TIME")]
+    pub restore_end_time: Option<i64>,
+    /// Collection the archived events are restored into, defaults to `EVENT_COLLECTION_NAME`
+    #[envconfig(from = "RESTORE_COLLECTION_NAME")]
+    pub restore_collection_name: Option<String>,
+}
+
+impl ArchiverConfig {
+    pub fn retention(&self, status: RunStatus) -> Option<Duration> {
+        match status {
+            RunStatus::Finished => self.archives_retention_days,
+            RunStatus::Failed => self.archives_failed_retention_days,
+        }
+        .map(Duration::days)
+    }
+
+    /// One config per archived collection, with the settings of its policy applied
+    pub fn policies(&self) -> Result<Vec<ArchiverConfig>> {
+        let Some(policies) = &self.archive_policies else {
+            return Ok(vec![self.clone()]);
+        };
+
+        let mut collections = HashSet::new();
+
+        policies
+            .iter()
+            .map(|policy| {
+                if !collections.insert(policy.collection.as_str()) {
+                    return Err(anyhow!(
+                        "Collection {} has more than one archive policy",
+                        policy.collection
+                    ));
+                }
+
+                if policy.mode == Some(Mode::Restore) {
+                    return Err(anyhow!(
+                        "Restore mode can't be used in the archive policy of {}",
+                        policy.collection
+                    ));
+                }
+
+                let mut config = self.clone();
+                config.archive_policies = None;
+                config.event_collection_name = policy.collection.clone();
+                config.mode = policy.mode.unwrap_or(self.mode);
+                config.min_date_days = policy.min_date_days.unwrap_or(self.min_date_days);
+                config.chunk_size_minutes =
+                    policy.chunk_size_minutes.unwrap_or(self.chunk_size_minutes);
+                config.chunk_to_process_in_days = policy
+                    .chunk_to_process_in_days
+                    .unwrap_or(self.chunk_to_process_in_days);
+
+                Ok(config)
+            })
+            .collect()
+    }
+
+    /// Collection the runs of this config read from, or write into when restoring
+    pub fn archived_collection(&self) -> &str {
+        match self.mode {
+            Mode::Restore => self.restore_collection_name(),
+            _ => &self.event_collection_name,
+        }
+    }
+
+    pub fn restore_collection_name(&self) -> &str {
+        self.restore_collection_name
+            .as_deref()
+            .unwrap_or(&self.event_collection_name)
+    }
 }
 
 impl Display for ArchiverConfig {
@@ -53,12 +163,22 @@ impl Display for ArchiverConfig {
                 writeln!(f, "GS_STORAGE_BUCKET: {}", self.gs_storage_bucket)?;
                 writeln!(f, "GS_STORAGE_URI: {}", self.gs_storage_uri)?;
             }
+            StorageProvider::Local => {
+                writeln!(f, "LOCAL_STORAGE_PATH: {}", self.local_storage_path)?;
+            }
+            StorageProvider::S3 => {
+                writeln!(f, "S3_BUCKET: {}", self.s3_bucket)?;
+                writeln!(f, "S3_REGION: {}", self.s3_region)?;
+                writeln!(f, "S3_ENDPOINT: {:?}", self.s3_endpoint)?;
+                writeln!(f, "S3_PART_SIZE_BYTES: {}", self.s3_part_size_bytes)?;
+            }
         }
         writeln!(
             f,
             "PROCESSING_CHUNK_TIMEOUT_SECS: {}",
             self.processing_chunk_timeout_secs
         )?;
+        writeln!(f, "EXPORT_FORMATS: {}", self.export_formats)?;
         writeln!(f, "READ_BUFFER_SIZE_BYTES: {}", self.read_buffer_size)?;
         writeln!(f, "MIN_DATE_DAYS: {}", self.min_date_days)?;
         writeln!(f, "CHUNK_SIZE_MINUTES: {}", self.chunk_size_minutes)?;
@@ -74,7 +194,91 @@ impl Display for ArchiverConfig {
             self.sleep_after_finish
         )?;
         writeln!(f, "CONCURRENT_CHUNKS: {}", self.concurrent_chunks)?;
+        writeln!(f, "STATUS_SERVER_ADDRESS: {}", self.status_server_address)?;
+        writeln!(
+            f,
+            "ARCHIVES_RETENTION_DAYS: {:?}",
+            self.archives_retention_days
+        )?;
+        writeln!(
+            f,
+            "ARCHIVES_FAILED_RETENTION_DAYS: {:?}",
+            self.archives_failed_retention_days
+        )?;
         writeln!(f, "MODE: {}", self.mode.as_ref())?;
+        if let Some(policies) = &self.archive_policies {
+            writeln!(f, "ARCHIVE_POLICIES: {policies}")?;
+        }
+        if self.mode == Mode::Restore {
+            writeln!(f, "RESTORE_START_TIME: {}", self.restore_start_time)?;
+            writeln!(f, "RESTORE_END_TIME: {:?}", self.restore_end_time)?;
+            writeln!(
+                f,
+                "RESTORE_COLLECTION_NAME: {}",
+                self.restore_collection_name()
+            )?;
+        }
         write!(f, "{}", self.db_config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config(policies: &str) -> ArchiverConfig {
+        ArchiverConfig::init_from_hashmap(&HashMap::from([
+            ("MODE".to_string(), "dump".to_string()),
+            ("MIN_DATE_DAYS".to_string(), "30".to_string()),
+            ("ARCHIVE_POLICIES".to_string(), policies.to_string()),
+        ]))
+        .expect("Failed to load config")
+    }
+
+    #[test]
+    fn test_policies() {
+        let policies = config(
+            r#"[
+                {"collection": "external-events", "mode": "dump-delete"},
+                {"collection": "tasks", "minDateDays": 7, "chunkSizeMinutes": 60}
+            ]"#,
+        )
+        .policies()
+        .expect("Failed to resolve policies");
+
+        assert_eq!(policies.len(), 2);
+
+        assert_eq!(policies[0].event_collection_name, "external-events");
+        assert_eq!(policies[0].mode, Mode::DumpDelete);
+        assert_eq!(policies[0].min_date_days, 30);
+        assert_eq!(policies[0].chunk_size_minutes, 5);
+
+        assert_eq!(policies[1].event_collection_name, "tasks");
+        assert_eq!(policies[1].mode, Mode::Dump);
+        assert_eq!(policies[1].min_date_days, 7);
+        assert_eq!(policies[1].chunk_size_minutes, 60);
+    }
+
+    #[test]
+    fn test_invalid_policies() {
+        assert!(
+            config(r#"[{"collection": "tasks"}, {"collection": "tasks"}]"#)
+                .policies()
+                .is_err()
+        );
+        assert!(config(r#"[{"collection": "tasks", "mode": "restore"}]"#)
+            .policies()
+            .is_err());
+    }
+
+    #[test]
+    fn test_without_policies() {
+        let config =
+            ArchiverConfig::init_from_hashmap(&HashMap::new()).expect("Failed to load config");
+        let policies = config.policies().expect("Failed to resolve policies");
+
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].event_collection_name, "external-events");
+    }
+}
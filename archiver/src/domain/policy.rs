@@ -0,0 +1,45 @@
+use super::config::Mode;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Archiving settings of a single collection. Unset values fall back to the global config.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivePolicy {
+    pub collection: String,
+    pub mode: Option<Mode>,
+    pub min_date_days: Option<i64>,
+    pub chunk_size_minutes: Option<i64>,
+    pub chunk_to_process_in_days: Option<i64>,
+}
+
+/// JSON list of archive policies, e.g.
+/// `[{"collection": "external-events", "mode": "dump-delete"}, {"collection": "tasks", "minDateDays": 7}]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivePolicies(Vec<ArchivePolicy>);
+
+impl ArchivePolicies {
+    pub fn iter(&self) -> impl Iterator<Item = &ArchivePolicy> {
+        self.0.iter()
+    }
+}
+
+impl FromStr for ArchivePolicies {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ArchivePolicies(serde_json::from_str(s)?))
+    }
+}
+
+impl Display for ArchivePolicies {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let collections = self
+            .0
+            .iter()
+            .map(|policy| policy.collection.as_str())
+            .collect::<Vec<_>>();
+        write!(f, "{}", collections.join(","))
+    }
+}
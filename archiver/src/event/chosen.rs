@@ -8,20 +8,28 @@ pub struct DateChosen {
     #[serde(rename = "_id")]
     id: Id,
     reference: Id,
+    /// Archived collection. Only missing on events written before multiple collections were supported.
+    #[serde(default)]
+    collection: String,
     starts_from: i64,
     ends_at: i64,
 }
 
 impl DateChosen {
-    pub fn new(reference: Id, starts_from: i64, ends_at: i64) -> Self {
+    pub fn new(reference: Id, collection: String, starts_from: i64, ends_at: i64) -> Self {
         Self {
             id: Id::now(IdPrefix::Archive),
             reference,
+            collection,
             starts_from,
             ends_at,
         }
     }
 
+    pub fn starts_from(&self) -> i64 {
+        self.starts_from
+    }
+
     pub fn event_date(&self) -> i64 {
         self.ends_at
     }
@@ -1,4 +1,6 @@
+use super::started::Started;
 use super::EventMetadata;
+use crate::storage::Extension;
 use chrono::{DateTime, Utc};
 use osentities::{prefix::IdPrefix, Id};
 use serde::{Deserialize, Serialize};
@@ -9,23 +11,98 @@ pub struct Completed {
     #[serde(rename = "_id")]
     id: Id,
     reference: Id,
+    /// Archived collection. Only missing on events written before multiple collections were supported.
+    #[serde(default)]
+    collection: String,
     path: String,
+    /// Names of the uploaded objects in the storage provider. Empty for archives
+    /// completed before the names were recorded.
+    #[serde(default)]
+    files: Vec<String>,
+    /// Number of archived events and size of the exported files
+    #[serde(default)]
+    count: u64,
+    #[serde(default)]
+    bytes: u64,
     completed_at: DateTime<Utc>,
     start_time: i64,
     end_time: i64,
 }
 
 impl Completed {
-    pub fn new(path: String, id: Id, start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> Self {
+    pub fn new(
+        path: String,
+        files: Vec<String>,
+        count: u64,
+        bytes: u64,
+        started: &Started,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Self {
         Self {
             id: Id::now(IdPrefix::Archive),
             path,
-            reference: id,
+            files,
+            count,
+            bytes,
+            reference: started.reference(),
+            collection: started.collection().to_string(),
             completed_at: Utc::now(),
             start_time: start_time.timestamp_millis(),
             end_time: end_time.timestamp_millis(),
         }
     }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn completed_at(&self) -> DateTime<Utc> {
+        self.completed_at
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn start_time(&self) -> i64 {
+        self.start_time
+    }
+
+    pub fn end_time(&self) -> i64 {
+        self.end_time
+    }
+
+    /// Names of the uploaded objects. Older events only recorded the path of the
+    /// metadata file, so the archive name is derived from it.
+    pub fn files(&self) -> Vec<String> {
+        if !self.files.is_empty() {
+            return self.files.clone();
+        }
+
+        let metadata = self
+            .path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&self.path)
+            .to_string();
+
+        match metadata.strip_suffix(Extension::Metadata.as_ref()) {
+            Some(base) => vec![
+                format!("{base}{}", Extension::Bson.as_ref()),
+                metadata.clone(),
+            ],
+            None => vec![metadata],
+        }
+    }
 }
 
 impl EventMetadata for Completed {
@@ -33,3 +110,29 @@ impl EventMetadata for Completed {
         self.reference
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_derived_from_legacy_path() {
+        let completed: Completed = serde_json::from_value(serde_json::json!({
+            "_id": Id::now(IdPrefix::Archive),
+            "reference": Id::now(IdPrefix::Archive),
+            "path": "gs://bucket/2024-01-01-1-part-0-external-events.metadata.json.gz",
+            "completedAt": Utc::now(),
+            "startTime": 0,
+            "endTime": 1,
+        }))
+        .expect("Failed to deserialize completed event");
+
+        assert_eq!(
+            completed.files(),
+            vec![
+                "2024-01-01-1-part-0-external-events.bson.gz".to_string(),
+                "2024-01-01-1-part-0-external-events.metadata.json.gz".to_string(),
+            ]
+        );
+    }
+}
@@ -26,6 +26,14 @@ impl Failed {
             failed_at: Utc::now(),
         }
     }
+
+    pub fn failed_at(&self) -> DateTime<Utc> {
+        self.failed_at
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
 }
 
 impl EventMetadata for Failed {
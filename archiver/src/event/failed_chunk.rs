@@ -0,0 +1,71 @@
+use super::EventMetadata;
+use chrono::{DateTime, Utc};
+use osentities::{prefix::IdPrefix, Id};
+use serde::{Deserialize, Serialize};
+
+/// A chunk that could not be archived after all retries. Unlike the other events it is
+/// not part of a run summary, it is kept until a later run archives the chunk.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedChunk {
+    #[serde(rename = "_id")]
+    id: Id,
+    reference: Id,
+    collection: String,
+    start_time: i64,
+    end_time: i64,
+    part: u64,
+    /// Number of runs that failed to archive the chunk
+    attempts: u32,
+    reason: String,
+    failed_at: DateTime<Utc>,
+}
+
+impl FailedChunk {
+    pub fn new(
+        reference: Id,
+        collection: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        part: u64,
+        reason: String,
+    ) -> Self {
+        Self {
+            id: Id::now(IdPrefix::Archive),
+            reference,
+            collection,
+            start_time: start_time.timestamp_millis(),
+            end_time: end_time.timestamp_millis(),
+            part,
+            attempts: 1,
+            reason,
+            failed_at: Utc::now(),
+        }
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    pub fn start_time(&self) -> i64 {
+        self.start_time
+    }
+
+    pub fn end_time(&self) -> i64 {
+        self.end_time
+    }
+
+    pub fn part(&self) -> u64 {
+        self.part
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+impl EventMetadata for FailedChunk {
+    fn reference(&self) -> Id {
+        self.reference
+    }
+}
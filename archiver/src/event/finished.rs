@@ -20,6 +20,10 @@ impl Finished {
             finished_at: Utc::now(),
         }
     }
+
+    pub fn finished_at(&self) -> DateTime<Utc> {
+        self.finished_at
+    }
 }
 
 impl EventMetadata for Finished {
@@ -2,19 +2,27 @@ pub mod chosen;
 pub mod completed;
 pub mod dumped;
 pub mod failed;
+pub mod failed_chunk;
 pub mod finished;
+pub mod restored;
 pub mod started;
+pub mod summary;
 pub mod uploaded;
+pub mod verified;
 
 use chosen::DateChosen;
 use completed::Completed;
 use dumped::Dumped;
 use failed::Failed;
+use failed_chunk::FailedChunk;
 use finished::Finished;
 use osentities::Id;
+use restored::Restored;
 use serde::{Deserialize, Serialize};
 use started::Started;
+use summary::Summary;
 use uploaded::Uploaded;
+use verified::Verified;
 
 pub trait EventMetadata {
     fn reference(&self) -> Id;
@@ -27,16 +35,24 @@ pub enum Event {
     Started(Started),
     /// Archive process has chosen the date to dump. Emitted when the archive process has chosen the date to dump.
     DateChosen(DateChosen),
-    /// Archive process dumped event. Emitted when mongodump finishes dumping the database.
+    /// Archive process dumped event. Emitted when the events of a chunk are exported to local files.
     Dumped(Dumped),
     /// Archive process failed event. Emitted when the archive process fails in some way.
     Failed(Failed),
     /// Archive process uploaded event. Emitted after the selected storage provider uploads any file (by default, the archive file and metadata file).
     Uploaded(Uploaded),
+    /// Archive process verified event. Emitted once the uploaded files match the local files and the event count.
+    Verified(Verified),
     /// Archive process completed event. Emitted when all dumped files are uploaded.
     Completed(Completed),
     /// Archive process finished event. Emitted when the archive process is finished.
     Finished(Finished),
+    /// Archive restore event. Emitted when the files of a completed archive are restored into a collection.
+    Restored(Restored),
+    /// Compacted run. Replaces all the events of a run once it is finished or failed.
+    Summary(Summary),
+    /// Chunk that could not be archived after all retries. Removed once a later run archives it.
+    FailedChunk(FailedChunk),
 }
 
 impl Event {
@@ -53,8 +69,12 @@ impl EventMetadata for Event {
             Event::Dumped(event) => event.reference(),
             Event::Failed(event) => event.reference(),
             Event::Uploaded(event) => event.reference(),
+            Event::Verified(event) => event.reference(),
             Event::Completed(event) => event.reference(),
             Event::Finished(event) => event.reference(),
+            Event::Restored(event) => event.reference(),
+            Event::Summary(event) => event.reference(),
+            Event::FailedChunk(event) => event.reference(),
         }
     }
 }
@@ -0,0 +1,46 @@
+use super::EventMetadata;
+use chrono::{DateTime, Utc};
+use osentities::{prefix::IdPrefix, Id};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Restored {
+    #[serde(rename = "_id")]
+    id: Id,
+    reference: Id,
+    /// Id of the `Completed` event whose files were restored
+    archive: Id,
+    collection: String,
+    restored_at: DateTime<Utc>,
+    start_time: i64,
+    end_time: i64,
+}
+
+impl Restored {
+    pub fn new(id: Id, archive: Id, collection: String, start_time: i64, end_time: i64) -> Self {
+        Self {
+            id: Id::now(IdPrefix::Archive),
+            reference: id,
+            archive,
+            collection,
+            restored_at: Utc::now(),
+            start_time,
+            end_time,
+        }
+    }
+
+    pub fn archive(&self) -> Id {
+        self.archive
+    }
+
+    pub fn restored_at(&self) -> DateTime<Utc> {
+        self.restored_at
+    }
+}
+
+impl EventMetadata for Restored {
+    fn reference(&self) -> Id {
+        self.reference
+    }
+}
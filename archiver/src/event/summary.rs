@@ -0,0 +1,251 @@
+use super::completed::Completed;
+use super::started::Started;
+use super::{Event, EventMetadata};
+use chrono::{DateTime, Duration, Utc};
+use osentities::{prefix::IdPrefix, Id};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Finished,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    #[serde(rename = "_id")]
+    id: Id,
+    reference: Id,
+    collection: String,
+    status: RunStatus,
+    started_at: DateTime<Utc>,
+    ended_at: DateTime<Utc>,
+    duration_ms: i64,
+    /// Window chosen by a dump run
+    starts_from: Option<i64>,
+    ends_at: Option<i64>,
+    /// Number of archived events
+    count: u64,
+    /// Size of the exported files
+    bytes: u64,
+    remote_paths: Vec<String>,
+    /// Completed archives of the run, kept so that they can still be restored
+    archives: Vec<Completed>,
+    /// Ids of the archives restored by a restore run
+    restored: Vec<Id>,
+    errors: Vec<String>,
+    /// Picked up by the TTL index of the archives collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<bson::DateTime>,
+}
+
+impl Summary {
+    /// Collapses the events of the run started by `started` into a single document
+    pub fn from_events(started: &Started, events: &[Event]) -> Self {
+        let mut summary = Self {
+            id: Id::now(IdPrefix::Archive),
+            reference: started.reference(),
+            collection: started.collection().to_string(),
+            status: RunStatus::Failed,
+            started_at: started.started_at(),
+            ended_at: started.started_at(),
+            duration_ms: 0,
+            starts_from: None,
+            ends_at: None,
+            count: 0,
+            bytes: 0,
+            remote_paths: vec![],
+            archives: vec![],
+            restored: vec![],
+            errors: vec![],
+            expires_at: None,
+        };
+
+        for event in events {
+            let at = match event {
+                Event::DateChosen(e) => {
+                    summary.starts_from = Some(e.starts_from());
+                    summary.ends_at = Some(e.event_date());
+                    None
+                }
+                Event::Completed(e) => {
+                    summary.count += e.count();
+                    summary.bytes += e.bytes();
+                    summary.remote_paths.push(e.path().to_string());
+                    summary.archives.push(e.clone());
+                    Some(e.completed_at())
+                }
+                Event::Restored(e) => {
+                    summary.restored.push(e.archive());
+                    Some(e.restored_at())
+                }
+                Event::Failed(e) => {
+                    summary.errors.push(e.reason().to_string());
+                    Some(e.failed_at())
+                }
+                Event::Finished(e) => {
+                    summary.status = RunStatus::Finished;
+                    Some(e.finished_at())
+                }
+                Event::Started(_)
+                | Event::Dumped(_)
+                | Event::Uploaded(_)
+                | Event::Verified(_)
+                | Event::Summary(_)
+                | Event::FailedChunk(_) => None,
+            };
+
+            if let Some(at) = at {
+                summary.ended_at = summary.ended_at.max(at);
+            }
+        }
+
+        summary.duration_ms = (summary.ended_at - summary.started_at).num_milliseconds();
+        summary
+    }
+
+    /// Lets the TTL index remove the summary once `retention` has passed since the run ended.
+    /// Summaries listing archives never expire, they are the only record the restore mode finds
+    /// those archives through.
+    pub fn expires_after(mut self, retention: Option<Duration>) -> Self {
+        self.expires_at = retention
+            .filter(|_| self.archives.is_empty())
+            .map(|retention| {
+                bson::DateTime::from_millis((self.ended_at + retention).timestamp_millis())
+            });
+        self
+    }
+
+    pub fn status(&self) -> RunStatus {
+        self.status
+    }
+
+    pub fn ends_at(&self) -> Option<i64> {
+        self.ends_at
+    }
+
+    pub fn archives(&self) -> &[Completed] {
+        &self.archives
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn duration_ms(&self) -> i64 {
+        self.duration_ms
+    }
+}
+
+impl EventMetadata for Summary {
+    fn reference(&self) -> Id {
+        self.reference
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{chosen::DateChosen, failed::Failed, finished::Finished};
+
+    #[test]
+    fn test_summary_from_finished_run() {
+        let started = Started::new("external-events".into());
+        let start = Utc::now();
+        let end = start + Duration::minutes(5);
+
+        let events = vec![
+            Event::Started(started.clone()),
+            Event::DateChosen(DateChosen::new(
+                started.reference(),
+                started.collection().to_string(),
+                start.timestamp_millis(),
+                end.timestamp_millis(),
+            )),
+            Event::Completed(Completed::new(
+                "file:///archives/a.metadata.json.gz".into(),
+                vec![],
+                10,
+                100,
+                &started,
+                start,
+                end,
+            )),
+            Event::Completed(Completed::new(
+                "file:///archives/b.metadata.json.gz".into(),
+                vec![],
+                5,
+                50,
+                &started,
+                start,
+                end,
+            )),
+            Event::Finished(Finished::new(started.reference())),
+        ];
+
+        let summary =
+            Summary::from_events(&started, &events).expires_after(Some(Duration::days(1)));
+
+        assert_eq!(summary.status(), RunStatus::Finished);
+        assert_eq!(summary.reference(), started.reference());
+        assert_eq!(summary.ends_at(), Some(end.timestamp_millis()));
+        assert_eq!(summary.count(), 15);
+        assert_eq!(summary.bytes(), 150);
+        assert_eq!(summary.archives().len(), 2);
+        assert_eq!(
+            summary.remote_paths,
+            vec![
+                "file:///archives/a.metadata.json.gz".to_string(),
+                "file:///archives/b.metadata.json.gz".to_string()
+            ]
+        );
+        assert!(summary.duration_ms() >= 0);
+        assert_eq!(summary.expires_at, None);
+    }
+
+    #[test]
+    fn test_summary_without_archives_expires() {
+        let started = Started::new("external-events".into());
+        let events = vec![
+            Event::Started(started.clone()),
+            Event::Finished(Finished::new(started.reference())),
+        ];
+
+        let summary =
+            Summary::from_events(&started, &events).expires_after(Some(Duration::days(1)));
+
+        assert_eq!(summary.status(), RunStatus::Finished);
+        assert_eq!(
+            summary.expires_at,
+            Some(bson::DateTime::from_millis(
+                (summary.ended_at + Duration::days(1)).timestamp_millis()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_summary_from_failed_run() {
+        let started = Started::new("external-events".into());
+        let events = vec![
+            Event::Started(started.clone()),
+            Event::Failed(Failed::new(
+                "mongo is down".into(),
+                started.reference(),
+                started.started_at(),
+                Utc::now(),
+            )),
+        ];
+
+        let summary = Summary::from_events(&started, &events).expires_after(None);
+
+        assert_eq!(summary.status(), RunStatus::Failed);
+        assert_eq!(summary.errors, vec!["mongo is down".to_string()]);
+        assert_eq!(summary.ends_at(), None);
+        assert_eq!(summary.expires_at, None);
+    }
+}
@@ -0,0 +1,45 @@
+use super::EventMetadata;
+use chrono::{DateTime, Utc};
+use osentities::{prefix::IdPrefix, Id};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Verified {
+    #[serde(rename = "_id")]
+    id: Id,
+    reference: Id,
+    verified_at: DateTime<Utc>,
+    start_time: i64,
+    end_time: i64,
+    /// Number of events in the archive, matching the collection at the time of verification
+    count: u64,
+    /// Names of the verified objects
+    files: Vec<String>,
+}
+
+impl Verified {
+    pub fn new(
+        id: Id,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        count: u64,
+        files: Vec<String>,
+    ) -> Self {
+        Self {
+            id: Id::now(IdPrefix::Archive),
+            reference: id,
+            verified_at: Utc::now(),
+            start_time: start_time.timestamp_millis(),
+            end_time: end_time.timestamp_millis(),
+            count,
+            files,
+        }
+    }
+}
+
+impl EventMetadata for Verified {
+    fn reference(&self) -> Id {
+        self.reference
+    }
+}
@@ -0,0 +1,285 @@
+use crate::storage::Extension;
+use anyhow::{anyhow, Result};
+use bson::{doc, Bson, Document};
+use flate2::{write::GzEncoder, Compression};
+use futures::TryStreamExt;
+use mongodb::Collection;
+use osentities::Unit;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    io::Write,
+    path::Path,
+    str::FromStr,
+};
+use strum::{AsRefStr, EnumString};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+/// Formats chunks can be exported in. Parquet is not supported yet, it needs the arrow writer
+/// which is tracked separately, so configuring it fails instead of silently exporting less.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ExportFormat {
+    /// Concatenated BSON documents, the format `mongorestore` reads
+    Bson,
+    /// One relaxed extended JSON document per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> Extension {
+        match self {
+            ExportFormat::Bson => Extension::Bson,
+            ExportFormat::Ndjson => Extension::Ndjson,
+        }
+    }
+
+    fn encode(&self, document: &Document, buffer: &mut Vec<u8>) -> Result<Unit> {
+        match self {
+            ExportFormat::Bson => document.to_writer(buffer)?,
+            ExportFormat::Ndjson => {
+                serde_json::to_writer(&mut *buffer, document)?;
+                buffer.push(b'\n');
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats to export each chunk in. BSON is always exported as it is what restores read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportFormats(Vec<ExportFormat>);
+
+impl ExportFormats {
+    pub fn iter(&self) -> impl Iterator<Item = &ExportFormat> {
+        self.0.iter()
+    }
+}
+
+impl FromStr for ExportFormats {
+    type Err = strum::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut formats = vec![ExportFormat::Bson];
+
+        for format in s.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let format = ExportFormat::from_str(format)?;
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+
+        Ok(ExportFormats(formats))
+    }
+}
+
+impl Display for ExportFormats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let formats = self.0.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        write!(f, "{}", formats.join(","))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub count: u64,
+    /// Size of all the exported files, metadata included
+    pub bytes: u64,
+    /// Hex encoded SHA-256 of the uncompressed content of each exported file
+    pub checksums: BTreeMap<String, String>,
+}
+
+/// Streams the documents matching `filter` into gzip'd files next to `base_path`, one per
+/// format, followed by a `mongodump` compatible metadata file.
+pub async fn export(
+    collection: &Collection<Document>,
+    filter: Document,
+    base_path: &Path,
+    formats: &ExportFormats,
+    buffer_size: usize,
+) -> Result<ExportSummary> {
+    let mut writers = Vec::new();
+    for format in formats.iter() {
+        let path = base_path.with_extension(format.extension().as_ref());
+        writers.push((*format, GzipFile::create(&path, buffer_size).await?));
+    }
+
+    let mut cursor = collection.find(filter).await?;
+    let mut buffer = Vec::new();
+    let mut count = 0;
+
+    while let Some(document) = cursor.try_next().await? {
+        for (format, writer) in writers.iter_mut() {
+            buffer.clear();
+            format.encode(&document, &mut buffer)?;
+            writer.write(&buffer).await?;
+        }
+
+        count += 1;
+    }
+
+    let mut checksums = BTreeMap::new();
+    let mut bytes = 0;
+    for (format, writer) in writers {
+        let (checksum, written) = writer.finish().await?;
+        checksums.insert(format.as_ref().to_string(), checksum);
+        bytes += written;
+    }
+
+    let indexes = collection
+        .list_indexes()
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .map(|index| bson::to_bson(&index))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Extra fields are ignored by `mongorestore`
+    let metadata = doc! {
+        "options": {},
+        "indexes": indexes,
+        "collectionName": collection.name(),
+        "type": "collection",
+        "count": count as i64,
+        "checksums": bson::to_bson(&checksums)?,
+    };
+
+    let path = base_path.with_extension(Extension::Metadata.as_ref());
+    let mut writer = GzipFile::create(&path, buffer_size).await?;
+    writer
+        .write(&serde_json::to_vec(
+            &Bson::Document(metadata).into_canonical_extjson(),
+        )?)
+        .await?;
+    let (_, written) = writer.finish().await?;
+
+    Ok(ExportSummary {
+        count,
+        bytes: bytes + written,
+        checksums,
+    })
+}
+
+/// Gzip compresses into memory and flushes to disk once `buffer_size` bytes are pending,
+/// so that no blocking file IO happens on the runtime.
+struct GzipFile {
+    file: File,
+    encoder: GzEncoder<Vec<u8>>,
+    hasher: Sha256,
+    buffer_size: usize,
+    written: u64,
+}
+
+impl GzipFile {
+    async fn create(path: &Path, buffer_size: usize) -> Result<Self> {
+        let file = File::create(path)
+            .await
+            .map_err(|e| anyhow!("Failed to create export file {path:?}: {e}"))?;
+
+        Ok(GzipFile {
+            file,
+            encoder: GzEncoder::new(Vec::with_capacity(buffer_size), Compression::default()),
+            hasher: Sha256::new(),
+            buffer_size,
+            written: 0,
+        })
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<Unit> {
+        self.hasher.update(bytes);
+        self.encoder.write_all(bytes)?;
+
+        if self.encoder.get_ref().len() >= self.buffer_size {
+            let compressed = std::mem::take(self.encoder.get_mut());
+            self.file.write_all(&compressed).await?;
+            self.written += compressed.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the remaining data and returns the checksum of the uncompressed content
+    /// along with the size of the compressed file
+    async fn finish(mut self) -> Result<(String, u64)> {
+        let compressed = self.encoder.finish()?;
+        self.file.write_all(&compressed).await?;
+        self.file.flush().await?;
+
+        let written = self.written + compressed.len() as u64;
+
+        Ok((format!("{:x}", self.hasher.finalize()), written))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_export_formats() {
+        assert_eq!(
+            ExportFormats::from_str("").expect("Failed to parse formats"),
+            ExportFormats(vec![ExportFormat::Bson])
+        );
+        assert_eq!(
+            ExportFormats::from_str("ndjson, bson").expect("Failed to parse formats"),
+            ExportFormats(vec![ExportFormat::Bson, ExportFormat::Ndjson])
+        );
+        assert!(ExportFormats::from_str("csv").is_err());
+        assert!(ExportFormats::from_str("ndjson,parquet").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gzip_file() {
+        let dir = TempDir::new().expect("Failed to create temp dir");
+        let path = dir.path().join("events.bson.gz");
+
+        let first = doc! { "_id": "1", "createdAt": 1_i64 };
+        let second = doc! { "_id": "2", "createdAt": 2_i64 };
+
+        // A tiny buffer forces intermediate flushes
+        let mut writer = GzipFile::create(&path, 8)
+            .await
+            .expect("Failed to create file");
+        let mut content = Vec::new();
+        for document in [&first, &second] {
+            let mut buffer = Vec::new();
+            ExportFormat::Bson
+                .encode(document, &mut buffer)
+                .expect("Failed to encode document");
+            writer.write(&buffer).await.expect("Failed to write");
+            content.extend(buffer);
+        }
+        let (checksum, written) = writer.finish().await.expect("Failed to finish file");
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(std::fs::File::open(&path).expect("Failed to open file"))
+            .read_to_end(&mut decompressed)
+            .expect("Failed to decompress file");
+
+        assert_eq!(
+            written,
+            std::fs::metadata(&path)
+                .expect("Failed to read file metadata")
+                .len()
+        );
+        assert_eq!(decompressed, content);
+        assert_eq!(checksum, format!("{:x}", Sha256::digest(&content)));
+
+        let mut reader = decompressed.as_slice();
+        assert_eq!(
+            Document::from_reader(&mut reader).expect("Failed to read document"),
+            first
+        );
+        assert_eq!(
+            Document::from_reader(&mut reader).expect("Failed to read document"),
+            second
+        );
+    }
+}
@@ -1,5 +1,8 @@
 mod domain;
 mod event;
+mod export;
+mod retention;
+mod status;
 mod storage;
 
 use crate::domain::config::{ArchiverConfig, Mode};
@@ -14,31 +17,37 @@ use event::chosen::DateChosen;
 use event::completed::Completed;
 use event::dumped::Dumped;
 use event::failed::Failed;
+use event::failed_chunk::FailedChunk;
+use event::restored::Restored;
 use event::started::Started;
+use event::summary::RunStatus;
 use event::uploaded::Uploaded;
+use event::verified::Verified;
 use event::{Event, EventMetadata};
+use export::{export, ExportSummary};
 use futures::future::ready;
 use futures::stream::{self, Stream};
 use futures::{StreamExt, TryStreamExt};
 use mongodb::options::FindOneOptions;
-use mongodb::Client;
+use mongodb::{Client, Database};
 use osentities::telemetry::{get_subscriber, init_subscriber};
 use osentities::{MongoStore, Store, Unit};
-use std::process::Command;
+use reqwest_retry::{policies::ExponentialBackoff, RetryDecision, RetryPolicy};
+use retention::{compact, ensure_ttl_index};
+use status::ArchiverStatus;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
-use storage::google_cloud::GoogleCloudStorage;
-use storage::{Extension, Storage, StorageProvider};
+use std::time::{Duration, SystemTime};
+use storage::{verify_upload, ArchiveStorage, Extension, Storage};
 use tempfile::TempDir;
+use tokio::process::Command;
 
 #[tokio::main]
 async fn main() -> Result<Unit> {
     dotenv().ok();
     let config = Arc::new(ArchiverConfig::init_from_env()?);
-    let storage = Arc::new(match config.storage_provider {
-        StorageProvider::GoogleCloud => GoogleCloudStorage::new(&config).await?,
-    });
+    let storage = Arc::new(ArchiveStorage::new(&config).await?);
 
     let subscriber = get_subscriber("archiver".into(), "info".into(), std::io::stdout, None);
     init_subscriber(subscriber);
@@ -47,36 +56,111 @@ async fn main() -> Result<Unit> {
 
     let client = Arc::new(Client::with_uri_str(&config.db_config.event_db_url).await?);
     let database = Arc::new(client.database(&config.db_config.event_db_name));
-    // TODO: Add TTL to the archived events
     let archives: Arc<MongoStore<Event>> =
         Arc::new(MongoStore::new(&database, &Store::Archives).await?);
+    ensure_ttl_index(&archives).await?;
+    assign_legacy_collection(&config, &archives).await?;
 
+    let policies = match config.mode {
+        // Restoring is a one-off operation for a single collection
+        Mode::Restore => vec![config.as_ref().clone()],
+        _ => config.policies()?,
+    };
+
+    let status = Arc::new(ArchiverStatus::new(&policies));
+
+    let runs = futures::future::try_join_all(policies.into_iter().map(|policy| {
+        run(
+            Arc::new(policy),
+            database.clone(),
+            archives.clone(),
+            storage.clone(),
+            status.clone(),
+        )
+    }));
+
+    tokio::select! {
+        res = status::serve(config.status_server_address, status.clone()) => res,
+        res = runs => res.map(|_| ()),
+    }
+}
+
+/// Events written before multiple collections were supported belong to `EVENT_COLLECTION_NAME`
+async fn assign_legacy_collection(
+    config: &ArchiverConfig,
+    archives: &MongoStore<Event>,
+) -> Result<Unit> {
+    archives
+        .collection
+        .update_many(
+            doc! {
+                "type": { "$in": ["DateChosen", "Completed"] },
+                "collection": { "$exists": false }
+            },
+            doc! { "$set": { "collection": &config.event_collection_name } },
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn run(
+    config: Arc<ArchiverConfig>,
+    database: Arc<Database>,
+    archives: Arc<MongoStore<Event>>,
+    storage: Arc<impl Storage>,
+    status: Arc<ArchiverStatus>,
+) -> Result<Unit> {
     let store = Store::from_str(&config.event_collection_name).map_err(|e| anyhow::anyhow!(e))?;
     let target_store: Arc<MongoStore<Document>> =
         Arc::new(MongoStore::new(&database, &store).await?);
 
     loop {
-        let started = Started::new(config.event_collection_name.clone());
+        let started = Started::new(config.archived_collection().to_string());
         archives
             .create_one(&Event::Started(started.clone()))
             .await?;
+        status.run_started(&started);
 
         let res = match config.mode {
-            Mode::Dump => dump(&config, &archives, &started, &storage, &target_store, false).await,
+            Mode::Dump => {
+                dump(
+                    &config,
+                    &archives,
+                    &started,
+                    &storage,
+                    &target_store,
+                    &status,
+                    false,
+                )
+                .await
+            }
             Mode::DumpDelete => {
-                dump(&config, &archives, &started, &storage, &target_store, true).await
+                dump(
+                    &config,
+                    &archives,
+                    &started,
+                    &storage,
+                    &target_store,
+                    &status,
+                    true,
+                )
+                .await
             }
             Mode::NoOp => Ok(()),
+            Mode::Restore => restore(&config, &archives, &started, &storage).await,
         }
         .inspect_err(|e| {
-            tracing::error!("Error in archiver: {e}");
+            tracing::error!("Error in archiver for {}: {e}", started.collection());
         });
 
-        match res {
+        let failure = match res {
             Ok(_) => {
                 archives
                     .create_one(&Event::Finished(Finished::new(started.reference())))
                     .await?;
+                status.run_ended(&started, RunStatus::Finished);
+                None
             }
             Err(e) => {
                 archives
@@ -87,10 +171,32 @@ async fn main() -> Result<Unit> {
                         Utc::now(),
                     )))
                     .await?;
+                status.run_ended(&started, RunStatus::Failed);
+                Some(e)
             }
         };
 
-        tracing::info!("Sleeping for {} seconds", config.sleep_after_finish);
+        match compact(&config, &archives, &started).await {
+            Ok(summary) => tracing::info!(
+                "Compacted run {}: {} events, {} bytes archived in {} ms",
+                started.reference(),
+                summary.count(),
+                summary.bytes(),
+                summary.duration_ms()
+            ),
+            Err(e) => tracing::error!("Failed to compact run {}: {e}", started.reference()),
+        }
+
+        // Restoring is a one-off operation, it is re-run manually if needed
+        if config.mode == Mode::Restore {
+            return failure.map_or(Ok(()), Err);
+        }
+
+        tracing::info!(
+            "Sleeping for {} seconds before archiving {} again",
+            config.sleep_after_finish,
+            started.collection()
+        );
         tokio::time::sleep(Duration::from_secs(config.sleep_after_finish)).await;
     }
 }
@@ -101,6 +207,7 @@ async fn dump(
     started: &Started,
     storage: &Arc<impl Storage>,
     target_store: &Arc<MongoStore<Document>>,
+    status: &ArchiverStatus,
     destructive: bool,
 ) -> Result<Unit> {
     tracing::info!(
@@ -108,6 +215,18 @@ async fn dump(
         started.collection()
     );
 
+    // The window only advances once the chunks previous runs failed to archive are archived
+    retry_failed_chunks(
+        config,
+        archives,
+        storage,
+        target_store,
+        started,
+        status,
+        destructive,
+    )
+    .await?;
+
     let document = target_store
         .collection
         .find_one(doc! {})
@@ -138,7 +257,8 @@ async fn dump(
     let last_chosen_date_event = archives
         .collection
         .find_one(doc! {
-            "type": "DateChosen"
+            "type": "DateChosen",
+            "collection": started.collection()
         })
         .with_options(
             FindOneOptions::builder()
@@ -175,6 +295,34 @@ async fn dump(
         _ => 0,
     };
 
+    // Finished runs are compacted into summaries, which keep the chosen window
+    let summarized_at = archives
+        .collection
+        .find_one(doc! {
+            "type": "Summary",
+            "collection": started.collection(),
+            "status": "Finished",
+            "endsAt": { "$ne": null }
+        })
+        .with_options(
+            FindOneOptions::builder()
+                .sort(doc! { "endsAt": -1 })
+                .build(),
+        )
+        .await?
+        .and_then(|event| match event {
+            Event::Summary(summary) => summary.ends_at(),
+            _ => None,
+        })
+        .unwrap_or(0);
+
+    tracing::info!("Last summarized date: {}", summarized_at);
+
+    let started_at = started_at.max(summarized_at);
+    if started_at > 0 {
+        status.archived_until(started.collection(), started_at);
+    }
+
     let start = match Utc.timestamp_millis_opt(start.max(started_at)) {
         LocalResult::Single(date) => date,
         _ => return Err(anyhow!("Invalid timestamp")),
@@ -193,10 +341,16 @@ async fn dump(
     archives
         .create_one(&Event::DateChosen(DateChosen::new(
             started.reference(),
+            started.collection().to_string(),
             start.timestamp_millis(),
             end.timestamp_millis(),
         )))
         .await?;
+    status.window_chosen(
+        started.collection(),
+        start.timestamp_millis(),
+        end.timestamp_millis(),
+    );
 
     tracing::info!("Start date: {}, End date: {}", start, end);
 
@@ -205,52 +359,42 @@ async fn dump(
     let stream = chunks
         .enumerate()
         .map(|(index, (start_time, end_time))| async move {
-            tracing::info!(
-                "Processing events between {} - ({}) and {} - ({})",
+            let chunk = ChunkWindow {
                 start_time,
-                start_time.timestamp_millis(),
                 end_time,
-                end_time.timestamp_millis()
-            );
+                part: index,
+            };
+
+            tracing::info!("Processing events of chunk {chunk}");
 
-            let saved = save(
+            match process_chunk(
                 config,
                 archives,
                 storage,
                 target_store,
                 started,
-                (&start_time, &end_time),
-                index,
+                chunk,
+                destructive,
             )
-            .await;
-
-            match saved {
-                Ok(0) => {
+            .await
+            {
+                Ok(summary) if summary.count == 0 => {
+                    status.chunk_processed(started.collection(), 0, 0);
                     tracing::warn!("No events found between {} and {}", start_time, end_time);
-                    return Ok(());
                 }
-                Ok(count) => {
-                    tracing::info!("Archive saved successfully, saved {} events", count);
+                Ok(summary) => {
+                    status.chunk_processed(started.collection(), summary.count, summary.bytes);
+                    tracing::info!("Archive saved successfully, saved {} events", summary.count);
                 }
                 Err(e) => {
-                    tracing::error!("Failed to save archive: {e}");
+                    tracing::error!("Failed to archive chunk {chunk}: {e}");
+                    status.chunk_failed(started.collection());
+                    record_failed_chunk(archives, started, chunk, &e).await?;
 
                     return Err(e);
                 }
             };
 
-            if destructive {
-                tracing::warn!("Deleting old events as destructive mode is enabled");
-                let filter = doc! {
-                    "createdAt": {
-                        "$gte": start_time.timestamp_millis(),
-                        "$lt": end_time.timestamp_millis()
-                    }
-                };
-
-                target_store.collection.delete_many(filter).await?;
-                tracing::warn!("Old events deleted successfully");
-            }
             Ok::<_, anyhow::Error>(())
         });
 
@@ -269,8 +413,7 @@ async fn dump(
         for error in &errors {
             tracing::error!("Error: {:?}", error);
         }
-        // If we return an error it'll prevent from moving forward in the loop
-        // return Err(anyhow!("Encountered {} errors during processing", errors.len()));
+        // Failed chunks are recorded and retried by the next run before it moves forward
     } else {
         tracing::info!("All chunks processed successfully.");
     }
@@ -278,6 +421,249 @@ async fn dump(
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy)]
+struct ChunkWindow {
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    part: usize,
+}
+
+impl ChunkWindow {
+    fn filter(&self) -> Document {
+        doc! {
+            "createdAt": {
+                "$gte": self.start_time.timestamp_millis(),
+                "$lt": self.end_time.timestamp_millis()
+            }
+        }
+    }
+}
+
+impl TryFrom<&FailedChunk> for ChunkWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(chunk: &FailedChunk) -> Result<Self> {
+        let timestamp = |millis| match Utc.timestamp_millis_opt(millis) {
+            LocalResult::Single(date) => Ok(date),
+            _ => Err(anyhow!("Invalid timestamp {millis}")),
+        };
+
+        Ok(ChunkWindow {
+            start_time: timestamp(chunk.start_time())?,
+            end_time: timestamp(chunk.end_time())?,
+            part: chunk.part() as usize,
+        })
+    }
+}
+
+impl Display for ChunkWindow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} - ({}) to {} - ({}), part {}",
+            self.start_time,
+            self.start_time.timestamp_millis(),
+            self.end_time,
+            self.end_time.timestamp_millis(),
+            self.part
+        )
+    }
+}
+
+/// Archives a chunk, retrying with backoff, and deletes its events in destructive mode
+async fn process_chunk(
+    config: &ArchiverConfig,
+    archives: &MongoStore<Event>,
+    storage: &Arc<impl Storage>,
+    target_store: &MongoStore<Document>,
+    started: &Started,
+    chunk: ChunkWindow,
+    destructive: bool,
+) -> Result<ExportSummary> {
+    let policy = ExponentialBackoff::builder().build_with_max_retries(config.max_retries);
+    let first_attempt = SystemTime::now();
+    let mut retries = 0;
+
+    let summary = loop {
+        let saved = save(
+            config,
+            archives,
+            storage,
+            target_store,
+            started,
+            (&chunk.start_time, &chunk.end_time),
+            chunk.part,
+        )
+        .await;
+
+        match saved {
+            Ok(summary) => break summary,
+            Err(e) => match policy.should_retry(first_attempt, retries) {
+                RetryDecision::Retry { execute_after } => {
+                    let wait = execute_after
+                        .duration_since(SystemTime::now())
+                        .unwrap_or_default();
+                    tracing::warn!(
+                        "Failed to save chunk {chunk} on attempt {}, retrying in {wait:?}: {e}",
+                        retries + 1
+                    );
+
+                    tokio::time::sleep(wait).await;
+                    retries += 1;
+                }
+                RetryDecision::DoNotRetry => return Err(e),
+            },
+        }
+    };
+
+    // `save` only succeeds once the uploaded archive has been verified
+    let count = summary.count;
+    if destructive && count > 0 {
+        // Events written into the window after it was exported were never archived
+        let current = target_store
+            .collection
+            .count_documents(chunk.filter())
+            .await?;
+        if current != count {
+            return Err(anyhow!(
+                "Chunk {chunk} has {current} events but {count} were archived, not deleting"
+            ));
+        }
+
+        tracing::warn!("Deleting old events as destructive mode is enabled");
+        target_store.collection.delete_many(chunk.filter()).await?;
+        tracing::warn!("Old events deleted successfully");
+    }
+
+    Ok(summary)
+}
+
+async fn record_failed_chunk(
+    archives: &MongoStore<Event>,
+    started: &Started,
+    chunk: ChunkWindow,
+    error: &anyhow::Error,
+) -> Result<Unit> {
+    let existing = archives
+        .collection
+        .find_one(doc! {
+            "type": "FailedChunk",
+            "collection": started.collection(),
+            "startTime": chunk.start_time.timestamp_millis(),
+            "endTime": chunk.end_time.timestamp_millis()
+        })
+        .await?;
+
+    match existing {
+        Some(Event::FailedChunk(failed)) => {
+            archives
+                .update_one(
+                    &failed.id().to_string(),
+                    doc! {
+                        "$set": {
+                            "reference": started.reference().to_string(),
+                            "reason": error.to_string(),
+                            "failedAt": bson::to_bson(&Utc::now())?
+                        },
+                        "$inc": { "attempts": 1 }
+                    },
+                )
+                .await?
+        }
+        _ => {
+            archives
+                .create_one(&Event::FailedChunk(FailedChunk::new(
+                    started.reference(),
+                    started.collection().to_string(),
+                    chunk.start_time,
+                    chunk.end_time,
+                    chunk.part as u64,
+                    error.to_string(),
+                )))
+                .await?
+        }
+    }
+
+    Ok(())
+}
+
+async fn retry_failed_chunks(
+    config: &ArchiverConfig,
+    archives: &MongoStore<Event>,
+    storage: &Arc<impl Storage>,
+    target_store: &MongoStore<Document>,
+    started: &Started,
+    status: &ArchiverStatus,
+    destructive: bool,
+) -> Result<Unit> {
+    let pending = archives
+        .collection
+        .find(doc! {
+            "type": "FailedChunk",
+            "collection": started.collection()
+        })
+        .sort(doc! { "startTime": 1 })
+        .await?
+        .try_filter_map(|event| async move {
+            Ok(match event {
+                Event::FailedChunk(chunk) => Some(chunk),
+                _ => None,
+            })
+        })
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("Retrying {} previously failed chunks", pending.len());
+
+    let mut failures = 0;
+    for failed in pending {
+        let chunk = ChunkWindow::try_from(&failed)?;
+
+        match process_chunk(
+            config,
+            archives,
+            storage,
+            target_store,
+            started,
+            chunk,
+            destructive,
+        )
+        .await
+        {
+            Ok(summary) => {
+                status.chunk_processed(started.collection(), summary.count, summary.bytes);
+                tracing::info!(
+                    "Archived previously failed chunk {chunk} with {} events after {} failed runs",
+                    summary.count,
+                    failed.attempts()
+                );
+                archives
+                    .collection
+                    .delete_one(doc! { "_id": failed.id().to_string() })
+                    .await?;
+            }
+            Err(e) => {
+                tracing::error!("Failed to archive previously failed chunk {chunk}: {e}");
+                status.chunk_failed(started.collection());
+                record_failed_chunk(archives, started, chunk, &e).await?;
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(anyhow!(
+            "{failures} previously failed chunks could not be archived"
+        ));
+    }
+
+    Ok(())
+}
+
 async fn save(
     config: &ArchiverConfig,
     archive: &MongoStore<Event>,
@@ -286,7 +672,7 @@ async fn save(
     started_event: &Started,
     times: (&DateTime<Utc>, &DateTime<Utc>),
     part: usize,
-) -> Result<u64> {
+) -> Result<ExportSummary> {
     let (start_time, end_time) = times;
     let tmp_dir = TempDir::new()?;
     let filter = doc! {
@@ -310,7 +696,7 @@ async fn save(
     );
 
     if count == 0 {
-        return Ok(0);
+        return Ok(ExportSummary::default());
     }
 
     // Run this only on debug mode
@@ -325,22 +711,26 @@ async fn save(
         tracing::info!("Total size of all the events is {}", mem_size);
     }
 
-    let command = Command::new("mongodump")
-        .arg("--uri")
-        .arg(&config.db_config.event_db_url)
-        .arg("--db")
-        .arg(&config.db_config.event_db_name)
-        .arg("--collection")
-        .arg(&config.event_collection_name)
-        .arg("--query")
-        .arg(serde_json::to_string(&filter)?)
-        .arg("--out")
-        .arg(tmp_dir.path())
-        .arg("--gzip")
-        .output()?;
+    let base_path = tmp_dir.path().join(&config.event_collection_name);
 
-    if !command.status.success() {
-        return Err(anyhow!("Command mongodump failed: {:?}", command));
+    let summary = export(
+        &target_store.collection,
+        filter.clone(),
+        &base_path,
+        &config.export_formats,
+        config.read_buffer_size,
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to export events: {e}"))?;
+
+    if summary.count != count {
+        return Err(anyhow!(
+            "Exported {} events but {} were counted between {} and {}",
+            summary.count,
+            count,
+            start_time,
+            end_time
+        ));
     }
 
     archive
@@ -351,18 +741,17 @@ async fn save(
         )))
         .await?;
 
-    let base_path = tmp_dir
-        .path()
-        .join(&config.db_config.event_db_name)
-        .join(&config.event_collection_name);
-
     let suffix = format!("{}-part-{}", start_time.timestamp_millis(), part);
 
-    if let Err(e) = storage
-        .upload_file(&base_path, &Extension::Bson, config, suffix.clone())
-        .await
-    {
-        return Err(anyhow!("Failed to upload bson file: {e}"));
+    let mut files = Vec::new();
+    for format in config.export_formats.iter() {
+        match storage
+            .upload_file(&base_path, &format.extension(), config, suffix.clone())
+            .await
+        {
+            Ok(name) => files.push((format.extension(), name)),
+            Err(e) => return Err(anyhow!("Failed to upload {} file: {e}", format.as_ref())),
+        };
     }
 
     archive
@@ -377,12 +766,50 @@ async fn save(
         .upload_file(&base_path, &Extension::Metadata, config, suffix.clone())
         .await?;
 
-    let remote_path = format!("gs://{}/{}", config.gs_storage_bucket, name);
+    let remote_path = storage.remote_path(&name, config);
+    files.push((Extension::Metadata, name));
+
+    for (extension, name) in &files {
+        verify_upload(
+            storage.as_ref(),
+            &base_path.with_extension(extension.as_ref()),
+            name,
+            config,
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to verify upload: {e}"))?;
+    }
+
+    let counted = target_store.collection.count_documents(filter).await?;
+    if counted != summary.count {
+        return Err(anyhow!(
+            "Archived {} events but {} are now stored between {} and {}",
+            summary.count,
+            counted,
+            start_time,
+            end_time
+        ));
+    }
+
+    let files = files.into_iter().map(|(_, name)| name).collect::<Vec<_>>();
+
+    archive
+        .create_one(&Event::Verified(Verified::new(
+            started_event.reference(),
+            *start_time,
+            *end_time,
+            counted,
+            files.clone(),
+        )))
+        .await?;
 
     archive
         .create_one(&Event::Completed(Completed::new(
             remote_path.clone(),
-            started_event.reference(),
+            files,
+            summary.count,
+            summary.bytes,
+            started_event,
             *start_time,
             *end_time,
         )))
@@ -397,7 +824,186 @@ async fn save(
         end_time
     );
 
-    Ok(count)
+    Ok(summary)
+}
+
+async fn restore(
+    config: &Arc<ArchiverConfig>,
+    archives: &Arc<MongoStore<Event>>,
+    started: &Started,
+    storage: &Arc<impl Storage>,
+) -> Result<Unit> {
+    let end_time = config.restore_end_time.unwrap_or(i64::MAX);
+
+    tracing::info!(
+        "Starting archiver in restore mode for the {} collection, restoring archives between {} and {}",
+        started.collection(),
+        config.restore_start_time,
+        end_time
+    );
+
+    // Archives of compacted runs are kept in their summary
+    let mut completed = archives
+        .collection
+        .find(doc! {
+            "$or": [
+                {
+                    "type": "Completed",
+                    "collection": &config.event_collection_name,
+                    "startTime": { "$lt": end_time },
+                    "endTime": { "$gt": config.restore_start_time }
+                },
+                {
+                    "type": "Summary",
+                    "collection": &config.event_collection_name,
+                    "archives": {
+                        "$elemMatch": {
+                            "startTime": { "$lt": end_time },
+                            "endTime": { "$gt": config.restore_start_time }
+                        }
+                    }
+                }
+            ]
+        })
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .flat_map(|event| match event {
+            Event::Completed(completed) => vec![completed],
+            Event::Summary(summary) => summary.archives().to_vec(),
+            _ => vec![],
+        })
+        .filter(|completed| {
+            completed.start_time() < end_time && completed.end_time() > config.restore_start_time
+        })
+        .collect::<Vec<_>>();
+    completed.sort_by_key(|completed| completed.start_time());
+
+    tracing::info!("Found {} archives to restore", completed.len());
+
+    let errors = stream::iter(completed.iter())
+        .map(|completed| async move {
+            restore_archive(config, archives, started, storage, completed)
+                .await
+                .inspect_err(|e| {
+                    tracing::error!("Failed to restore archive {}: {e}", completed.path())
+                })
+        })
+        .buffer_unordered(config.concurrent_chunks)
+        .filter(|result| ready(result.is_err()))
+        .count()
+        .await;
+
+    if errors > 0 {
+        // Restored archives are skipped on the next run, so a restore can be retried safely
+        return Err(anyhow!("Failed to restore {errors} archives"));
+    }
+
+    tracing::info!("All archives restored successfully.");
+
+    Ok(())
+}
+
+async fn restore_archive(
+    config: &ArchiverConfig,
+    archives: &MongoStore<Event>,
+    started: &Started,
+    storage: &Arc<impl Storage>,
+    completed: &Completed,
+) -> Result<Unit> {
+    let restored = archives
+        .collection
+        .find_one(doc! {
+            "$or": [
+                { "type": "Restored", "archive": completed.id().to_string() },
+                { "type": "Summary", "restored": completed.id().to_string() }
+            ],
+            "collection": started.collection()
+        })
+        .await?;
+
+    if restored.is_some() {
+        tracing::info!(
+            "Archive {} was already restored into {}, skipping",
+            completed.path(),
+            started.collection()
+        );
+        return Ok(());
+    }
+
+    // mongorestore expects the `<db>/<collection>.<extension>` layout produced by mongodump
+    let tmp_dir = TempDir::new()?;
+    let base_path = tmp_dir
+        .path()
+        .join(&config.db_config.event_db_name)
+        .join(&config.event_collection_name);
+    tokio::fs::create_dir_all(tmp_dir.path().join(&config.db_config.event_db_name)).await?;
+
+    for name in completed.files() {
+        // Other export formats are meant for analytics and can't be restored
+        let Some(extension) = [Extension::Bson, Extension::Metadata]
+            .into_iter()
+            .find(|extension| name.ends_with(extension.as_ref()))
+        else {
+            continue;
+        };
+
+        storage
+            .download_file(&name, &base_path.with_extension(extension.as_ref()), config)
+            .await
+            .map_err(|e| anyhow!("Failed to download {name}: {e}"))?;
+    }
+
+    let source = format!(
+        "{}.{}",
+        config.db_config.event_db_name, config.event_collection_name
+    );
+
+    // Without `--drop` documents are only inserted, those whose `_id` already exists in the
+    // target collection are rejected instead of duplicated
+    let command = Command::new("mongorestore")
+        .arg("--uri")
+        .arg(&config.db_config.event_db_url)
+        .arg("--nsInclude")
+        .arg(&source)
+        .arg("--nsFrom")
+        .arg(&source)
+        .arg("--nsTo")
+        .arg(format!(
+            "{}.{}",
+            config.db_config.event_db_name,
+            started.collection()
+        ))
+        .arg("--dir")
+        .arg(tmp_dir.path())
+        .arg("--gzip")
+        .output()
+        .await?;
+
+    if !command.status.success() {
+        return Err(anyhow!("Command mongorestore failed: {:?}", command));
+    }
+
+    archives
+        .create_one(&Event::Restored(Restored::new(
+            started.reference(),
+            completed.id(),
+            started.collection().to_string(),
+            completed.start_time(),
+            completed.end_time(),
+        )))
+        .await?;
+
+    tracing::info!(
+        "Restored archive {} for events between {} and {} into {}",
+        completed.path(),
+        completed.start_time(),
+        completed.end_time(),
+        started.collection()
+    );
+
+    Ok(())
 }
 
 pub trait DivideBy {
@@ -432,3 +1038,39 @@ impl DivideBy for DateTime<Utc> {
         Box::new(stream)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osentities::{prefix::IdPrefix, Id};
+
+    #[test]
+    fn test_chunk_window_from_failed_chunk() {
+        let start_time = Utc.timestamp_millis_opt(1_700_000_000_000).unwrap();
+        let end_time = start_time + CDuration::minutes(5);
+
+        let failed = FailedChunk::new(
+            Id::now(IdPrefix::Archive),
+            "external-events".into(),
+            start_time,
+            end_time,
+            3,
+            "timeout".into(),
+        );
+
+        let chunk = ChunkWindow::try_from(&failed).expect("Failed to convert chunk");
+
+        assert_eq!(chunk.start_time, start_time);
+        assert_eq!(chunk.end_time, end_time);
+        assert_eq!(chunk.part, 3);
+        assert_eq!(
+            chunk.filter(),
+            doc! {
+                "createdAt": {
+                    "$gte": 1_700_000_000_000_i64,
+                    "$lt": 1_700_000_300_000_i64
+                }
+            }
+        );
+    }
+}
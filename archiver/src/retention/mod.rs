@@ -0,0 +1,67 @@
+use crate::domain::config::ArchiverConfig;
+use crate::event::started::Started;
+use crate::event::summary::Summary;
+use crate::event::{Event, EventMetadata};
+use anyhow::Result;
+use bson::doc;
+use futures::TryStreamExt;
+use mongodb::{options::IndexOptions, IndexModel};
+use osentities::{MongoStore, Unit};
+use std::time::Duration;
+
+/// Creates the TTL index that removes summaries once their `expiresAt` has passed.
+/// Documents without `expiresAt` are never removed.
+pub async fn ensure_ttl_index(archives: &MongoStore<Event>) -> Result<Unit> {
+    archives
+        .collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expiresAt": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .name("expiresAt_ttl".to_string())
+                        .expire_after(Duration::ZERO)
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Replaces the events of a finished or failed run with a single summary document
+pub async fn compact(
+    config: &ArchiverConfig,
+    archives: &MongoStore<Event>,
+    started: &Started,
+) -> Result<Summary> {
+    let reference = started.reference().to_string();
+    // Failed chunks outlive their run until a later run archives them
+    let filter = doc! {
+        "$or": [
+            { "_id": &reference },
+            { "reference": &reference }
+        ],
+        "type": { "$nin": ["Summary", "FailedChunk"] }
+    };
+
+    let events = archives
+        .collection
+        .find(filter.clone())
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let summary = Summary::from_events(started, &events);
+    let retention = config.retention(summary.status());
+    let summary = summary.expires_after(retention);
+
+    // The summary is stored first so that a crash in between never loses a run
+    archives
+        .create_one(&Event::Summary(summary.clone()))
+        .await?;
+    archives.collection.delete_many(filter).await?;
+
+    Ok(summary)
+}
@@ -0,0 +1,344 @@
+use crate::domain::config::{ArchiverConfig, Mode};
+use crate::event::{started::Started, summary::RunStatus, EventMetadata};
+use anyhow::{anyhow, Result};
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Json, Router};
+use chrono::{DateTime, Duration, Utc};
+use osentities::{Id, Unit};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpListener;
+
+/// Progress of the archiving of a single collection
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionStatus {
+    mode: Mode,
+    min_date_days: i64,
+    current_run: Option<Id>,
+    current_run_started_at: Option<DateTime<Utc>>,
+    last_run_status: Option<RunStatus>,
+    last_run_ended_at: Option<DateTime<Utc>>,
+    /// Last window chosen by a dump run, in milliseconds
+    starts_from: Option<i64>,
+    ends_at: Option<i64>,
+    /// Events created before this date (in milliseconds) are archived
+    archived_until: Option<i64>,
+    /// How far `archived_until` is behind `MIN_DATE_DAYS` ago, in milliseconds
+    lag_ms: Option<i64>,
+    chunks_processed: u64,
+    chunks_failed: u64,
+    events_archived: u64,
+    bytes_uploaded: u64,
+    runs_finished: u64,
+    runs_failed: u64,
+}
+
+impl CollectionStatus {
+    fn new(config: &ArchiverConfig) -> Self {
+        Self {
+            mode: config.mode,
+            min_date_days: config.min_date_days,
+            current_run: None,
+            current_run_started_at: None,
+            last_run_status: None,
+            last_run_ended_at: None,
+            starts_from: None,
+            ends_at: None,
+            archived_until: None,
+            lag_ms: None,
+            chunks_processed: 0,
+            chunks_failed: 0,
+            events_archived: 0,
+            bytes_uploaded: 0,
+            runs_finished: 0,
+            runs_failed: 0,
+        }
+    }
+
+    fn with_lag(mut self, now: DateTime<Utc>) -> Self {
+        let max_end = (now - Duration::days(self.min_date_days)).timestamp_millis();
+        self.lag_ms = self
+            .archived_until
+            .map(|archived_until| (max_end - archived_until).max(0));
+        self
+    }
+}
+
+/// In memory progress of every collection archived by this process, served over HTTP
+#[derive(Debug)]
+pub struct ArchiverStatus {
+    collections: Mutex<BTreeMap<String, CollectionStatus>>,
+}
+
+impl ArchiverStatus {
+    pub fn new(configs: &[ArchiverConfig]) -> Self {
+        let collections = configs
+            .iter()
+            .map(|config| {
+                (
+                    config.archived_collection().to_string(),
+                    CollectionStatus::new(config),
+                )
+            })
+            .collect();
+
+        Self {
+            collections: Mutex::new(collections),
+        }
+    }
+
+    fn update(&self, collection: &str, f: impl FnOnce(&mut CollectionStatus)) {
+        match self.collections.lock() {
+            Ok(mut collections) => {
+                if let Some(status) = collections.get_mut(collection) {
+                    f(status);
+                }
+            }
+            Err(e) => tracing::error!("Failed to update the status of {collection}: {e}"),
+        }
+    }
+
+    pub fn run_started(&self, started: &Started) {
+        self.update(started.collection(), |status| {
+            status.current_run = Some(started.reference());
+            status.current_run_started_at = Some(started.started_at());
+        });
+    }
+
+    pub fn run_ended(&self, started: &Started, run_status: RunStatus) {
+        self.update(started.collection(), |status| {
+            status.current_run = None;
+            status.current_run_started_at = None;
+            status.last_run_status = Some(run_status);
+            status.last_run_ended_at = Some(Utc::now());
+            match run_status {
+                RunStatus::Finished => status.runs_finished += 1,
+                RunStatus::Failed => status.runs_failed += 1,
+            }
+        });
+    }
+
+    /// Records the date up to which the previous runs archived the collection
+    pub fn archived_until(&self, collection: &str, archived_until: i64) {
+        self.update(collection, |status| {
+            status.archived_until = Some(archived_until);
+        });
+    }
+
+    pub fn window_chosen(&self, collection: &str, starts_from: i64, ends_at: i64) {
+        self.update(collection, |status| {
+            status.starts_from = Some(starts_from);
+            status.ends_at = Some(ends_at);
+        });
+    }
+
+    pub fn chunk_processed(&self, collection: &str, count: u64, bytes: u64) {
+        self.update(collection, |status| {
+            status.chunks_processed += 1;
+            status.events_archived += count;
+            status.bytes_uploaded += bytes;
+        });
+    }
+
+    pub fn chunk_failed(&self, collection: &str) {
+        self.update(collection, |status| {
+            status.chunks_failed += 1;
+        });
+    }
+
+    pub fn snapshot(&self, now: DateTime<Utc>) -> BTreeMap<String, CollectionStatus> {
+        match self.collections.lock() {
+            Ok(collections) => collections
+                .iter()
+                .map(|(collection, status)| (collection.clone(), status.clone().with_lag(now)))
+                .collect(),
+            Err(e) => {
+                tracing::error!("Failed to read the archiver status: {e}");
+                BTreeMap::new()
+            }
+        }
+    }
+
+    /// Renders the status in the Prometheus text exposition format
+    pub fn metrics(&self, now: DateTime<Utc>) -> String {
+        let snapshot = self.snapshot(now);
+        let mut output = String::new();
+
+        let mut metric =
+            |name: &str, kind: &str, help: &str, value: fn(&CollectionStatus) -> Option<f64>| {
+                let _ = writeln!(output, "# HELP {name} {help}");
+                let _ = writeln!(output, "# TYPE {name} {kind}");
+                for (collection, status) in &snapshot {
+                    if let Some(value) = value(status) {
+                        let _ = writeln!(
+                            output,
+                            "{name}{{collection=\"{}\"}} {value}",
+                            escape_label(collection)
+                        );
+                    }
+                }
+            };
+
+        metric(
+            "archiver_running",
+            "gauge",
+            "Whether a run is in progress for the collection.",
+            |s| Some(if s.current_run.is_some() { 1.0 } else { 0.0 }),
+        );
+        metric(
+            "archiver_lag_seconds",
+            "gauge",
+            "How far archiving is behind MIN_DATE_DAYS ago.",
+            |s| s.lag_ms.map(|lag| lag as f64 / 1000.0),
+        );
+        metric(
+            "archiver_archived_until_timestamp_seconds",
+            "gauge",
+            "Events created before this date are archived.",
+            |s| s.archived_until.map(|at| at as f64 / 1000.0),
+        );
+        metric(
+            "archiver_window_end_timestamp_seconds",
+            "gauge",
+            "End of the last window chosen by a dump run.",
+            |s| s.ends_at.map(|at| at as f64 / 1000.0),
+        );
+        metric(
+            "archiver_chunks_processed_total",
+            "counter",
+            "Chunks archived since the archiver started.",
+            |s| Some(s.chunks_processed as f64),
+        );
+        metric(
+            "archiver_chunks_failed_total",
+            "counter",
+            "Chunks that could not be archived since the archiver started.",
+            |s| Some(s.chunks_failed as f64),
+        );
+        metric(
+            "archiver_events_archived_total",
+            "counter",
+            "Events archived since the archiver started.",
+            |s| Some(s.events_archived as f64),
+        );
+        metric(
+            "archiver_uploaded_bytes_total",
+            "counter",
+            "Bytes uploaded since the archiver started.",
+            |s| Some(s.bytes_uploaded as f64),
+        );
+        metric(
+            "archiver_runs_finished_total",
+            "counter",
+            "Runs that finished since the archiver started.",
+            |s| Some(s.runs_finished as f64),
+        );
+        metric(
+            "archiver_runs_failed_total",
+            "counter",
+            "Runs that failed since the archiver started.",
+            |s| Some(s.runs_failed as f64),
+        );
+
+        output
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub async fn serve(address: SocketAddr, status: Arc<ArchiverStatus>) -> Result<Unit> {
+    let app = Router::new()
+        .route("/status", get(get_status))
+        .route("/metrics", get(get_metrics))
+        .with_state(status);
+
+    tracing::info!("Status server listening on {address}");
+
+    let listener = TcpListener::bind(address).await?;
+
+    axum::serve(listener, app.into_make_service())
+        .await
+        .map_err(|e| anyhow!("Status server error: {e}"))
+}
+
+async fn get_status(State(status): State<Arc<ArchiverStatus>>) -> impl IntoResponse {
+    Json(status.snapshot(Utc::now()))
+}
+
+async fn get_metrics(State(status): State<Arc<ArchiverStatus>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        status.metrics(Utc::now()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envconfig::Envconfig;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_status_metrics() {
+        let config = ArchiverConfig::init_from_hashmap(&HashMap::from([(
+            "MIN_DATE_DAYS".to_string(),
+            "30".to_string(),
+        )]))
+        .expect("Failed to load config");
+        let status = ArchiverStatus::new(&[config]);
+        let now = Utc::now();
+
+        let started = Started::new("external-events".into());
+        status.run_started(&started);
+        let archived_until = (now - Duration::days(31)).timestamp_millis();
+        status.archived_until("external-events", archived_until);
+        status.window_chosen(
+            "external-events",
+            archived_until,
+            (now - Duration::days(30)).timestamp_millis(),
+        );
+        status.chunk_processed("external-events", 10, 100);
+        status.chunk_processed("external-events", 5, 50);
+        status.chunk_failed("external-events");
+        // Collections that are not archived by this process are ignored
+        status.chunk_failed("tasks");
+
+        let snapshot = status.snapshot(now);
+        let events = &snapshot["external-events"];
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(events.current_run, Some(started.reference()));
+        assert_eq!(events.lag_ms, Some(Duration::days(1).num_milliseconds()));
+        assert_eq!(events.chunks_processed, 2);
+        assert_eq!(events.chunks_failed, 1);
+        assert_eq!(events.events_archived, 15);
+        assert_eq!(events.bytes_uploaded, 150);
+
+        let metrics = status.metrics(now);
+        assert!(metrics.contains("# TYPE archiver_lag_seconds gauge"));
+        assert!(metrics.contains("archiver_lag_seconds{collection=\"external-events\"} 86400"));
+        assert!(metrics.contains("archiver_running{collection=\"external-events\"} 1"));
+        assert!(
+            metrics.contains("archiver_uploaded_bytes_total{collection=\"external-events\"} 150")
+        );
+
+        status.run_ended(&started, RunStatus::Finished);
+        let metrics = status.metrics(now);
+        assert!(metrics.contains("archiver_running{collection=\"external-events\"} 0"));
+        assert!(metrics.contains("archiver_runs_finished_total{collection=\"external-events\"} 1"));
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
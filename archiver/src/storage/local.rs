@@ -0,0 +1,143 @@
+use super::{construct_file_name, md5_parts, ObjectInfo, Storage};
+use crate::domain::config::ArchiverConfig;
+use crate::Extension;
+use anyhow::{Context, Result};
+use osentities::Unit;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone)]
+pub struct LocalStorage {
+    directory: PathBuf,
+}
+
+impl LocalStorage {
+    pub async fn new(config: &ArchiverConfig) -> Result<Self> {
+        let directory = PathBuf::from(&config.local_storage_path);
+
+        tokio::fs::create_dir_all(&directory)
+            .await
+            .with_context(|| format!("Failed to create archive directory {directory:?}"))?;
+
+        Ok(LocalStorage { directory })
+    }
+}
+
+impl Storage for LocalStorage {
+    async fn upload_file(
+        &self,
+        base_path: &Path,
+        extension: &Extension,
+        _config: &ArchiverConfig,
+        suffix: String,
+    ) -> Result<String> {
+        let path = base_path.with_extension(extension.as_ref());
+        let name = construct_file_name(&path, suffix)?;
+
+        tokio::fs::copy(&path, self.directory.join(&name))
+            .await
+            .with_context(|| format!("Failed to copy {path:?} into {:?}", self.directory))?;
+
+        Ok(name)
+    }
+
+    async fn download_file(
+        &self,
+        name: &str,
+        destination: &Path,
+        _config: &ArchiverConfig,
+    ) -> Result<Unit> {
+        let path = self.directory.join(name);
+
+        tokio::fs::copy(&path, destination)
+            .await
+            .with_context(|| format!("Failed to copy {path:?} into {destination:?}"))?;
+
+        Ok(())
+    }
+
+    async fn object_info(&self, name: &str, config: &ArchiverConfig) -> Result<ObjectInfo> {
+        self.local_info(&self.directory.join(name), config).await
+    }
+
+    async fn local_info(&self, path: &Path, _config: &ArchiverConfig) -> Result<ObjectInfo> {
+        let (size, parts) = md5_parts(path, usize::MAX).await?;
+
+        Ok(ObjectInfo {
+            size,
+            hash: format!("{:x}", parts[0]),
+        })
+    }
+
+    fn remote_path(&self, name: &str, _config: &ArchiverConfig) -> String {
+        format!("file://{}", self.directory.join(name).display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envconfig::Envconfig;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_upload_and_download_file() {
+        let source = TempDir::new().expect("Failed to create source dir");
+        let target = TempDir::new().expect("Failed to create target dir");
+
+        let config = ArchiverConfig::init_from_hashmap(&HashMap::from([(
+            "LOCAL_STORAGE_PATH".to_string(),
+            target.path().display().to_string(),
+        )]))
+        .expect("Failed to load config");
+
+        let base_path = source.path().join("events");
+        tokio::fs::write(base_path.with_extension(Extension::Bson.as_ref()), b"bson")
+            .await
+            .expect("Failed to write bson file");
+
+        let storage = LocalStorage::new(&config)
+            .await
+            .expect("Failed to create storage");
+        let name = storage
+            .upload_file(&base_path, &Extension::Bson, &config, "1-part-0".into())
+            .await
+            .expect("Failed to upload file");
+
+        assert!(name.ends_with("1-part-0-events.bson.gz"));
+        assert_eq!(
+            tokio::fs::read(target.path().join(&name))
+                .await
+                .expect("Failed to read uploaded file"),
+            b"bson"
+        );
+        assert_eq!(
+            storage.remote_path(&name, &config),
+            format!("file://{}", target.path().join(&name).display())
+        );
+
+        assert_eq!(
+            storage
+                .object_info(&name, &config)
+                .await
+                .expect("Failed to read object info"),
+            storage
+                .local_info(&base_path.with_extension(Extension::Bson.as_ref()), &config)
+                .await
+                .expect("Failed to read local info")
+        );
+
+        let downloaded = source.path().join("downloaded.bson.gz");
+        storage
+            .download_file(&name, &downloaded, &config)
+            .await
+            .expect("Failed to download file");
+
+        assert_eq!(
+            tokio::fs::read(&downloaded)
+                .await
+                .expect("Failed to read downloaded file"),
+            b"bson"
+        );
+    }
+}
@@ -1,15 +1,31 @@
 pub mod google_cloud;
+pub mod local;
+pub mod s3;
 
 use crate::domain::config::ArchiverConfig;
-use anyhow::Result;
-use std::{future::Future, ops::Deref, path::Path};
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use google_cloud::GoogleCloudStorage;
+use local::LocalStorage;
+use md5::{digest::Output, Digest, Md5};
+use osentities::Unit;
+use s3::S3Storage;
+use std::{
+    future::Future,
+    ops::Deref,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use strum::{AsRefStr, EnumString};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 
 #[derive(Debug, Clone, PartialEq, Eq, EnumString, AsRefStr)]
 #[strum(serialize_all = "kebab-case")]
 pub enum StorageProvider {
     GoogleCloud,
-    // TODO: Implement LocalStorage
+    Local,
+    S3,
 }
 
 #[derive(Debug)]
@@ -33,6 +49,7 @@ impl Chunk {
 pub enum Extension {
     Bson,
     Metadata,
+    Ndjson,
 }
 
 impl AsRef<str> for Extension {
@@ -40,6 +57,7 @@ impl AsRef<str> for Extension {
         match self {
             Extension::Bson => "bson.gz",
             Extension::Metadata => "metadata.json.gz",
+            Extension::Ndjson => "ndjson.gz",
         }
     }
 }
@@ -52,6 +70,13 @@ impl Deref for Extension {
     }
 }
 
+/// Size and hash of an object, hashed the way its storage provider reports it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub size: u64,
+    pub hash: String,
+}
+
 pub trait Storage {
     fn upload_file(
         &self,
@@ -60,4 +85,314 @@ pub trait Storage {
         config: &ArchiverConfig,
         suffix: String,
     ) -> impl Future<Output = Result<String>>;
+
+    /// Downloads a previously uploaded object into `destination`
+    fn download_file(
+        &self,
+        name: &str,
+        destination: &Path,
+        config: &ArchiverConfig,
+    ) -> impl Future<Output = Result<Unit>>;
+
+    /// Reads back the size and hash of an uploaded object from the provider
+    fn object_info(
+        &self,
+        name: &str,
+        config: &ArchiverConfig,
+    ) -> impl Future<Output = Result<ObjectInfo>>;
+
+    /// Size and hash the provider is expected to report once a local file is uploaded
+    fn local_info(
+        &self,
+        path: &Path,
+        config: &ArchiverConfig,
+    ) -> impl Future<Output = Result<ObjectInfo>>;
+
+    /// Fully qualified location of an uploaded object, as recorded in the archive events
+    fn remote_path(&self, name: &str, config: &ArchiverConfig) -> String;
+}
+
+/// Checks that the object uploaded as `name` is identical to the local file at `path`
+pub async fn verify_upload(
+    storage: &impl Storage,
+    path: &Path,
+    name: &str,
+    config: &ArchiverConfig,
+) -> Result<ObjectInfo> {
+    let local = storage.local_info(path, config).await?;
+    let remote = storage.object_info(name, config).await?;
+
+    if local != remote {
+        return Err(anyhow!(
+            "Uploaded object {name} does not match {path:?}: expected {local:?}, found {remote:?}"
+        ));
+    }
+
+    Ok(remote)
+}
+
+pub enum ArchiveStorage {
+    GoogleCloud(Box<GoogleCloudStorage>),
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+impl ArchiveStorage {
+    pub async fn new(config: &ArchiverConfig) -> Result<Self> {
+        Ok(match config.storage_provider {
+            StorageProvider::GoogleCloud => {
+                ArchiveStorage::GoogleCloud(Box::new(GoogleCloudStorage::new(config).await?))
+            }
+            StorageProvider::Local => ArchiveStorage::Local(LocalStorage::new(config).await?),
+            StorageProvider::S3 => ArchiveStorage::S3(S3Storage::new(config).await?),
+        })
+    }
+}
+
+impl Storage for ArchiveStorage {
+    async fn upload_file(
+        &self,
+        base_path: &Path,
+        extension: &Extension,
+        config: &ArchiverConfig,
+        suffix: String,
+    ) -> Result<String> {
+        match self {
+            ArchiveStorage::GoogleCloud(s) => {
+                s.upload_file(base_path, extension, config, suffix).await
+            }
+            ArchiveStorage::Local(s) => s.upload_file(base_path, extension, config, suffix).await,
+            ArchiveStorage::S3(s) => s.upload_file(base_path, extension, config, suffix).await,
+        }
+    }
+
+    async fn download_file(
+        &self,
+        name: &str,
+        destination: &Path,
+        config: &ArchiverConfig,
+    ) -> Result<Unit> {
+        match self {
+            ArchiveStorage::GoogleCloud(s) => s.download_file(name, destination, config).await,
+            ArchiveStorage::Local(s) => s.download_file(name, destination, config).await,
+            ArchiveStorage::S3(s) => s.download_file(name, destination, config).await,
+        }
+    }
+
+    async fn object_info(&self, name: &str, config: &ArchiverConfig) -> Result<ObjectInfo> {
+        match self {
+            ArchiveStorage::GoogleCloud(s) => s.object_info(name, config).await,
+            ArchiveStorage::Local(s) => s.object_info(name, config).await,
+            ArchiveStorage::S3(s) => s.object_info(name, config).await,
+        }
+    }
+
+    async fn local_info(&self, path: &Path, config: &ArchiverConfig) -> Result<ObjectInfo> {
+        match self {
+            ArchiveStorage::GoogleCloud(s) => s.local_info(path, config).await,
+            ArchiveStorage::Local(s) => s.local_info(path, config).await,
+            ArchiveStorage::S3(s) => s.local_info(path, config).await,
+        }
+    }
+
+    fn remote_path(&self, name: &str, config: &ArchiverConfig) -> String {
+        match self {
+            ArchiveStorage::GoogleCloud(s) => s.remote_path(name, config),
+            ArchiveStorage::Local(s) => s.remote_path(name, config),
+            ArchiveStorage::S3(s) => s.remote_path(name, config),
+        }
+    }
+}
+
+async fn process_file_in_chunks<F, Fut>(
+    file_path: &PathBuf,
+    chunk_size: usize,
+    timeout: Duration,
+    process_chunk: F,
+) -> Result<Unit>
+where
+    F: Fn(Chunk) -> Fut + Send,
+    Fut: Future<Output = Result<Unit>> + Send,
+{
+    let file = File::open(file_path).await?;
+    let mut buffered_reader = BufReader::with_capacity(chunk_size, file);
+
+    let mut current_position: u64 = 0;
+
+    loop {
+        let chunk = buffered_reader.fill_buf().await?;
+        let chunk_length = chunk.len();
+
+        if chunk_length == 0 {
+            break;
+        }
+
+        let first_byte = current_position;
+        let last_byte = current_position + chunk_length as u64 - 1;
+
+        let chunk = Chunk {
+            data: chunk.to_vec(),
+            first_byte,
+            last_byte,
+        };
+
+        current_position = last_byte + 1;
+
+        tokio::time::timeout(timeout, async { process_chunk(chunk).await }).await??;
+
+        tracing::debug!("Processed chunk of size {}", chunk_length);
+
+        buffered_reader.consume(chunk_length);
+    }
+
+    Ok(())
+}
+
+/// MD5 digests of consecutive `part_size` parts of a file, along with its size.
+/// An empty file has a single digest.
+async fn md5_parts(path: &Path, part_size: usize) -> Result<(u64, Vec<Output<Md5>>)> {
+    let mut file = File::open(path).await?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut hasher = Md5::new();
+    let mut parts = vec![];
+    let mut in_part = 0;
+    let mut size = 0;
+
+    loop {
+        let max = buffer.len().min(part_size - in_part);
+        let read = file.read(&mut buffer[..max]).await?;
+
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..read]);
+        in_part += read;
+        size += read as u64;
+
+        if in_part == part_size {
+            parts.push(hasher.finalize_reset());
+            in_part = 0;
+        }
+    }
+
+    if in_part > 0 || parts.is_empty() {
+        parts.push(hasher.finalize());
+    }
+
+    Ok((size, parts))
+}
+
+fn construct_file_name(path: &Path, suffix: String) -> Result<String> {
+    let file_name = path
+        .file_name()
+        .context("Missing file name")?
+        .to_str()
+        .context("Invalid file name: {path:?}")?;
+
+    let timestamp = Utc::now().format("%Y-%m-%d");
+    let file_name = format!("{}-{}-{}", timestamp, suffix, file_name);
+
+    Ok(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake::{Fake, Faker};
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn test_md5_parts() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        temp_file
+            .write_all(b"abcdefghij")
+            .expect("Failed to write to temp file");
+
+        let (size, parts) = md5_parts(temp_file.path(), 4)
+            .await
+            .expect("Failed to hash file");
+        assert_eq!(size, 10);
+        assert_eq!(
+            parts,
+            vec![
+                Md5::digest(b"abcd"),
+                Md5::digest(b"efgh"),
+                Md5::digest(b"ij")
+            ]
+        );
+
+        let (_, parts) = md5_parts(temp_file.path(), usize::MAX)
+            .await
+            .expect("Failed to hash file");
+        assert_eq!(parts, vec![Md5::digest(b"abcdefghij")]);
+
+        let empty = NamedTempFile::new().expect("Failed to create temp file");
+        let (size, parts) = md5_parts(empty.path(), 4)
+            .await
+            .expect("Failed to hash file");
+        assert_eq!(size, 0);
+        assert_eq!(parts, vec![Md5::digest(b"")]);
+    }
+
+    #[test]
+    fn test_get_file_name() {
+        let string: String = Faker.fake();
+        let file_name = construct_file_name(&PathBuf::from(string), "1-2".into())
+            .expect("Failed to get file name");
+        let now = Utc::now().format("%Y-%m-%d").to_string();
+        assert!(file_name.contains('-'));
+        assert!(file_name.contains(now.as_str()));
+        assert!(file_name.contains("1-2"));
+    }
+
+    #[tokio::test]
+    async fn test_process_file_in_chunks() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let content = b"abcdefghijklmnopqrstuvwxyz0123456789"; // 36 bytes
+        temp_file
+            .write_all(content)
+            .expect("Failed to write to temp file");
+
+        let path = temp_file.path().to_path_buf(); // Keep the temp file open
+
+        let chunk_size = 10;
+
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let chunks_ref = Arc::clone(&chunks);
+
+        process_file_in_chunks(&path, chunk_size, Duration::from_secs(30), |chunk| {
+            let chunks = Arc::clone(&chunks_ref);
+            async move {
+                let mut chunks = chunks.lock().expect("Failed to lock chunks");
+                chunks.push((chunk.first_byte(), chunk.last_byte(), chunk.data.clone()));
+                Ok(())
+            }
+        })
+        .await
+        .expect("Failed to process file");
+
+        let chunks = chunks.lock().expect("Failed to lock chunks");
+        assert_eq!(chunks.len(), 4);
+
+        assert_eq!(chunks[0].0, 0);
+        assert_eq!(chunks[0].1, 9);
+        assert_eq!(chunks[0].2, b"abcdefghij".to_vec());
+
+        assert_eq!(chunks[1].0, 10);
+        assert_eq!(chunks[1].1, 19);
+        assert_eq!(chunks[1].2, b"klmnopqrst".to_vec());
+
+        assert_eq!(chunks[2].0, 20);
+        assert_eq!(chunks[2].1, 29);
+        assert_eq!(chunks[2].2, b"uvwxyz0123".to_vec());
+
+        assert_eq!(chunks[3].0, 30);
+        assert_eq!(chunks[3].1, 35);
+        assert_eq!(chunks[3].2, b"456789".to_vec());
+    }
 }
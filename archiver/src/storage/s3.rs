@@ -0,0 +1,223 @@
+use super::{construct_file_name, md5_parts, ObjectInfo, Storage};
+use crate::domain::config::ArchiverConfig;
+use crate::Extension;
+use anyhow::{anyhow, Context, Result};
+use aws_config::{retry::RetryConfig, BehaviorVersion, Region};
+use aws_sdk_s3::{
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use md5::{Digest, Md5};
+use osentities::Unit;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// S3 rejects multipart uploads whose non-final parts are smaller than 5 MiB
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct S3Storage {
+    client: Client,
+}
+
+impl S3Storage {
+    pub async fn new(config: &ArchiverConfig) -> Result<Self> {
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(config.s3_region.clone()))
+            .retry_config(RetryConfig::standard().with_max_attempts(config.max_retries + 1))
+            .load()
+            .await;
+
+        let mut builder = aws_sdk_s3::config::Builder::from(&sdk_config);
+        if let Some(endpoint) = &config.s3_endpoint {
+            // Self-hosted S3-compatible stores (e.g. MinIO) don't support virtual-hosted buckets
+            builder = builder.endpoint_url(endpoint).force_path_style(true);
+        }
+
+        Ok(S3Storage {
+            client: Client::from_conf(builder.build()),
+        })
+    }
+}
+
+impl Storage for S3Storage {
+    async fn upload_file(
+        &self,
+        base_path: &Path,
+        extension: &Extension,
+        config: &ArchiverConfig,
+        suffix: String,
+    ) -> Result<String> {
+        let path = base_path.with_extension(extension.as_ref());
+        let name = construct_file_name(&path, suffix)?;
+
+        let upload_id = self
+            .client
+            .create_multipart_upload()
+            .bucket(&config.s3_bucket)
+            .key(&name)
+            .send()
+            .await?
+            .upload_id
+            .context("Missing upload id for multipart upload")?;
+
+        match upload_parts(&self.client, &path, &name, &upload_id, config).await {
+            Ok(parts) => {
+                self.client
+                    .complete_multipart_upload()
+                    .bucket(&config.s3_bucket)
+                    .key(&name)
+                    .upload_id(&upload_id)
+                    .multipart_upload(
+                        CompletedMultipartUpload::builder()
+                            .set_parts(Some(parts))
+                            .build(),
+                    )
+                    .send()
+                    .await?;
+
+                Ok(name)
+            }
+            Err(e) => {
+                if let Err(abort) = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&config.s3_bucket)
+                    .key(&name)
+                    .upload_id(&upload_id)
+                    .send()
+                    .await
+                {
+                    tracing::error!("Failed to abort multipart upload for {name}: {abort}");
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn download_file(
+        &self,
+        name: &str,
+        destination: &Path,
+        config: &ArchiverConfig,
+    ) -> Result<Unit> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&config.s3_bucket)
+            .key(name)
+            .send()
+            .await?;
+
+        let mut reader = object.body.into_async_read();
+        let mut file = File::create(destination).await?;
+        tokio::io::copy_buf(&mut reader, &mut file).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    async fn object_info(&self, name: &str, config: &ArchiverConfig) -> Result<ObjectInfo> {
+        let object = self
+            .client
+            .head_object()
+            .bucket(&config.s3_bucket)
+            .key(name)
+            .send()
+            .await?;
+
+        Ok(ObjectInfo {
+            size: object
+                .content_length
+                .with_context(|| format!("Missing content length for {name}"))?
+                as u64,
+            hash: object
+                .e_tag
+                .with_context(|| format!("Missing ETag for {name}"))?
+                .trim_matches('"')
+                .to_string(),
+        })
+    }
+
+    async fn local_info(&self, path: &Path, config: &ArchiverConfig) -> Result<ObjectInfo> {
+        // Objects are always uploaded in parts, so their ETag is the MD5 of the part digests
+        // followed by the number of parts
+        let (size, parts) = md5_parts(path, config.s3_part_size_bytes.max(MIN_PART_SIZE)).await?;
+
+        let mut hasher = Md5::new();
+        for part in &parts {
+            hasher.update(part);
+        }
+
+        Ok(ObjectInfo {
+            size,
+            hash: format!("{:x}-{}", hasher.finalize(), parts.len()),
+        })
+    }
+
+    fn remote_path(&self, name: &str, config: &ArchiverConfig) -> String {
+        format!("s3://{}/{}", config.s3_bucket, name)
+    }
+}
+
+async fn upload_parts(
+    client: &Client,
+    path: &Path,
+    name: &str,
+    upload_id: &str,
+    config: &ArchiverConfig,
+) -> Result<Vec<CompletedPart>> {
+    let part_size = config.s3_part_size_bytes.max(MIN_PART_SIZE);
+    let timeout = Duration::from_secs(config.processing_chunk_timeout_secs);
+    let mut file = File::open(path).await?;
+    let mut parts = vec![];
+
+    loop {
+        let mut data = Vec::with_capacity(part_size);
+        (&mut file)
+            .take(part_size as u64)
+            .read_to_end(&mut data)
+            .await?;
+
+        // An empty file still needs a single (empty) part to complete the upload
+        if data.is_empty() && !parts.is_empty() {
+            break;
+        }
+
+        let part_number = parts.len() as i32 + 1;
+        let length = data.len();
+
+        let response = tokio::time::timeout(
+            timeout,
+            client
+                .upload_part()
+                .bucket(&config.s3_bucket)
+                .key(name)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(data))
+                .send(),
+        )
+        .await
+        .map_err(|_| anyhow!("Timed out uploading part {part_number} of {name}"))??;
+
+        parts.push(
+            CompletedPart::builder()
+                .set_e_tag(response.e_tag)
+                .part_number(part_number)
+                .build(),
+        );
+
+        tracing::debug!("Uploaded part {part_number} of {name} ({length} bytes)");
+
+        if length < part_size {
+            break;
+        }
+    }
+
+    Ok(parts)
+}
@@ -1,2 +1,3 @@
 pub mod local;
 pub mod remote;
+pub mod window;
@@ -1,6 +1,6 @@
 use futures::Future;
 use http::HeaderValue;
-use moka::future::Cache;
+use moka::{future::Cache, Expiry};
 use mongodb::bson::Document;
 use mongodb::options::FindOneOptions;
 use osentities::connection_definition::ConnectionDefinition;
@@ -9,13 +9,15 @@ use osentities::connection_model_schema::ConnectionModelSchema;
 use osentities::connection_oauth_definition::ConnectionOAuthDefinition;
 use osentities::destination::Destination;
 use osentities::event_access::EventAccess;
-use osentities::{ApplicationError, Connection, Id, MongoStore, PicaError, Secret, Unit};
+use osentities::{
+    ApplicationError, Connection, Id, InternalError, MongoStore, PicaError, Secret, Unit,
+};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub trait LocalCacheExt<K, V>
 where
@@ -111,6 +113,18 @@ where
             ),
         }
     }
+
+    /// Like `get_or_insert_with_fn`, but concurrent callers asking for the same
+    /// missing key share a single evaluation of `fut`.
+    pub async fn get_or_try_insert_coalesced<Fut>(&self, key: &K, fut: Fut) -> Result<V, PicaError>
+    where
+        Fut: Future<Output = Result<V, PicaError>>,
+    {
+        self.inner
+            .try_get_with(key.clone(), fut)
+            .await
+            .map_err(|e| e.as_ref().clone())
+    }
 }
 
 impl<K, V> LocalCacheExt<K, V> for GenericCache<K, V>
@@ -140,6 +154,80 @@ where
     }
 }
 
+/// Expires each entry after the time to live it was inserted with
+struct EntryTtl;
+
+impl<V> Expiry<String, (V, Duration)> for EntryTtl {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        (_, ttl): &(V, Duration),
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(*ttl)
+    }
+}
+
+/// Cache whose entries each have their own time to live and can be invalidated by key prefix
+#[derive(Clone)]
+pub struct ExpiringCache<V>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    inner: Arc<Cache<String, (V, Duration)>>,
+}
+
+impl<V> ExpiringCache<V>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new(size: u64) -> Self {
+        Self {
+            inner: Arc::new(
+                Cache::builder()
+                    .max_capacity(size)
+                    .expire_after(EntryTtl)
+                    .support_invalidation_closures()
+                    .build(),
+            ),
+        }
+    }
+
+    /// Bounds the cache by the total weight of its entries, as given by `weigher`, instead of
+    /// their count
+    pub fn weighted(max_weight: u64, weigher: fn(&V) -> u32) -> Self {
+        Self {
+            inner: Arc::new(
+                Cache::builder()
+                    .max_capacity(max_weight)
+                    .weigher(move |_, (value, _): &(V, Duration)| weigher(value))
+                    .expire_after(EntryTtl)
+                    .support_invalidation_closures()
+                    .build(),
+            ),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<V> {
+        self.inner.get(key).await.map(|(value, _)| value)
+    }
+
+    pub async fn insert(&self, key: String, value: V, ttl: Duration) {
+        self.inner.insert(key, (value, ttl)).await;
+    }
+
+    /// Invalidates every entry whose key starts with `prefix`
+    pub fn invalidate_prefix(&self, prefix: &str) -> Result<Unit, PicaError> {
+        let prefix = prefix.to_string();
+        self.inner
+            .invalidate_entries_if(move |key, _| key.starts_with(&prefix))
+            .map(|_| ())
+            .map_err(|e| {
+                InternalError::unknown(&format!("Could not invalidate entries: {e}"), None)
+            })
+    }
+}
+
 type ConnectionModelSchemaKey = (Arc<str>, Arc<str>);
 // type ConnectionHeaderKey = (Arc<str>, HeaderValue);
 type ConnectionKey = Arc<str>;
@@ -147,6 +235,8 @@ type ConnectionKey = Arc<str>;
 pub type EventAccessCache = GenericCache<HeaderValue, EventAccess>;
 pub type SecretCache = GenericCache<Connection, Secret>;
 pub type ConnectionOAuthDefinitionCache = GenericCache<Id, ConnectionOAuthDefinition>;
+/// Refreshed connections keyed by connection id and the secret id they were refreshed from
+pub type OAuthRefreshCache = GenericCache<(Id, String), Connection>;
 pub type ConnectionModelSchemaCache = GenericCache<ConnectionModelSchemaKey, ConnectionModelSchema>;
 pub type ConnectionModelDefinitionDestinationCache =
     GenericCache<Destination, ConnectionModelDefinition>;
@@ -167,3 +257,49 @@ pub struct ConnectionHeaderKey {
 pub type ConnectionHeaderCache = GenericCache<ConnectionHeaderKey, Connection>;
 pub type ConnectionCache = GenericCache<ConnectionKey, Connection>;
 pub type ConnectionModelDefinitionCacheStringKey = GenericCache<String, Option<SparseCMD>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osentities::InternalError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_get_or_try_insert_coalesced() {
+        let cache = GenericCache::<String, String>::new(10, 60);
+        let evaluations = AtomicUsize::new(0);
+
+        let refresh = || async {
+            evaluations.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok("refreshed".to_string())
+        };
+
+        let key = "connection".to_string();
+        let values = futures::future::join_all(
+            (0..10).map(|_| cache.get_or_try_insert_coalesced(&key, refresh())),
+        )
+        .await;
+
+        assert!(values.iter().all(|v| v.as_deref() == Ok("refreshed")));
+        assert_eq!(evaluations.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_or_try_insert_coalesced_does_not_keep_errors() {
+        let cache = GenericCache::<String, String>::new(10, 60);
+        let key = "connection".to_string();
+
+        let failed = cache
+            .get_or_try_insert_coalesced(&key, async {
+                Err(InternalError::connection_error("refresh failed", None))
+            })
+            .await;
+        assert!(failed.is_err());
+
+        let value = cache
+            .get_or_try_insert_coalesced(&key, async { Ok("refreshed".to_string()) })
+            .await;
+        assert_eq!(value.as_deref(), Ok("refreshed"));
+    }
+}
@@ -1,7 +1,12 @@
-use osentities::{cache::CacheConfig, InternalError, PicaError};
-use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use osentities::{cache::CacheConfig, InternalError, PicaError, Unit};
+use redis::{
+    aio::{ConnectionManager, ConnectionManagerConfig},
+    AsyncCommands,
+};
+use serde::{de::DeserializeOwned, Serialize};
 use std::time::Duration;
 
+#[derive(Clone)]
 pub struct RedisCache {
     pub inner: ConnectionManager,
 }
@@ -27,4 +32,76 @@ impl RedisCache {
 
         Ok(Self { inner })
     }
+
+    pub async fn get_json<V: DeserializeOwned>(&self, key: &str) -> Result<Option<V>, PicaError> {
+        let value: Option<String> = self.inner.clone().get(key).await.map_err(|e| {
+            InternalError::io_err(&format!("Could not get {key} from redis: {e}"), None)
+        })?;
+
+        value
+            .map(|value| {
+                serde_json::from_str(&value).map_err(|e| {
+                    InternalError::deserialize_error(
+                        &format!("Could not deserialize {key}: {e}"),
+                        None,
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Stores `value` as json under `key`, expiring after `ttl`
+    pub async fn set_json<V: Serialize>(
+        &self,
+        key: &str,
+        value: &V,
+        ttl: Duration,
+    ) -> Result<Unit, PicaError> {
+        let value = serde_json::to_string(value).map_err(|e| {
+            InternalError::serialize_error(&format!("Could not serialize {key}: {e}"), None)
+        })?;
+
+        self.inner
+            .clone()
+            .set_ex(key, value, ttl.as_secs().max(1))
+            .await
+            .map_err(|e| InternalError::io_err(&format!("Could not set {key} in redis: {e}"), None))
+    }
+
+    /// Current value of the counter under `key`, 0 if it was never incremented
+    pub async fn counter(&self, key: &str) -> Result<u64, PicaError> {
+        let value: Option<u64> = self.inner.clone().get(key).await.map_err(|e| {
+            InternalError::io_err(&format!("Could not get {key} from redis: {e}"), None)
+        })?;
+
+        Ok(value.unwrap_or_default())
+    }
+
+    /// Increments the counter under `key`, which then expires after `ttl` unless it is
+    /// incremented or touched again
+    pub async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, PicaError> {
+        let (value,): (u64,) = redis::pipe()
+            .atomic()
+            .incr(key, 1)
+            .expire(key, ttl.as_secs().max(1) as i64)
+            .ignore()
+            .query_async(&mut self.inner.clone())
+            .await
+            .map_err(|e| {
+                InternalError::io_err(&format!("Could not increment {key} in redis: {e}"), None)
+            })?;
+
+        Ok(value)
+    }
+
+    /// Resets the time to live of `key` to `ttl`, if it exists
+    pub async fn touch(&self, key: &str, ttl: Duration) -> Result<Unit, PicaError> {
+        self.inner
+            .clone()
+            .expire(key, ttl.as_secs().max(1) as i64)
+            .await
+            .map_err(|e| {
+                InternalError::io_err(&format!("Could not touch {key} in redis: {e}"), None)
+            })
+    }
 }
@@ -0,0 +1,269 @@
+use crate::remote::RedisCache;
+use osentities::{InternalError, PicaError, Unit};
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+/// Drops the requests that left each window, then counts the request in every window if all of
+/// them allow it, otherwise in none. Returns whether it was allowed followed by, for each window,
+/// the number of requests in it and the milliseconds until one of them leaves it and frees up a
+/// slot.
+const SLIDING_WINDOW_SCRIPT: &str = r"
+local now, member = tonumber(ARGV[1]), ARGV[2]
+local counts = {}
+local allowed = 1
+
+for i, key in ipairs(KEYS) do
+    local window, limit = tonumber(ARGV[2 * i + 1]), tonumber(ARGV[2 * i + 2])
+
+    redis.call('ZREMRANGEBYSCORE', key, '-inf', now - window)
+    counts[i] = redis.call('ZCARD', key)
+    if counts[i] >= limit then
+        allowed = 0
+    end
+end
+
+local result = { allowed }
+
+for i, key in ipairs(KEYS) do
+    local window, limit = tonumber(ARGV[2 * i + 1]), tonumber(ARGV[2 * i + 2])
+
+    if allowed == 1 then
+        redis.call('ZADD', key, now, member)
+        counts[i] = counts[i] + 1
+    end
+    redis.call('PEXPIRE', key, window)
+
+    local reset = 0
+    local index = math.max(counts[i] - limit, 0)
+    local freed = redis.call('ZRANGE', key, index, index, 'WITHSCORES')
+    if freed[2] then
+        reset = tonumber(freed[2]) + window - now
+    end
+
+    table.insert(result, counts[i])
+    table.insert(result, reset)
+end
+
+return result
+";
+
+/// Moves the time `KEYS[1]` is blocked until forward, never back
+const BLOCK_SCRIPT: &str = r"
+local current = tonumber(redis.call('GET', KEYS[1]) or '0')
+
+if tonumber(ARGV[1]) > current then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+end
+
+return 0
+";
+
+/// A limit of requests over a sliding window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlidingWindow {
+    pub key: String,
+    pub limit: u64,
+    pub window: Duration,
+}
+
+/// Requests in a window once a request was checked against it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowCount {
+    pub count: u64,
+    /// Milliseconds until a request leaves the window and frees up a slot
+    pub reset_ms: i64,
+}
+
+/// Sliding windows shared by every process through redis
+#[derive(Clone)]
+pub struct SlidingWindows {
+    redis: ConnectionManager,
+    script: Arc<Script>,
+    block_script: Arc<Script>,
+}
+
+impl std::fmt::Debug for SlidingWindows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SlidingWindows").finish_non_exhaustive()
+    }
+}
+
+impl SlidingWindows {
+    pub fn new(redis: RedisCache) -> Self {
+        Self {
+            redis: redis.inner,
+            script: Arc::new(Script::new(SLIDING_WINDOW_SCRIPT)),
+            block_script: Arc::new(Script::new(BLOCK_SCRIPT)),
+        }
+    }
+
+    /// Counts a request made at `now_ms` in every window if all of them allow it, otherwise in
+    /// none. Returns whether it was allowed along with the requests in each window.
+    pub async fn check(
+        &self,
+        windows: &[SlidingWindow],
+        now_ms: i64,
+    ) -> Result<(bool, Vec<WindowCount>), PicaError> {
+        if windows.is_empty() {
+            return Ok((true, vec![]));
+        }
+
+        let mut invocation = self.script.prepare_invoke();
+        invocation.arg(now_ms).arg(Uuid::new_v4().to_string());
+
+        for window in windows {
+            invocation
+                .key(&window.key)
+                .arg(window.window.as_millis() as u64)
+                .arg(window.limit);
+        }
+
+        let result: Vec<i64> = invocation
+            .invoke_async(&mut self.redis.clone())
+            .await
+            .map_err(|e| {
+                InternalError::io_err(&format!("Could not check sliding windows: {e}"), None)
+            })?;
+
+        let Some((allowed, counts)) = result.split_first() else {
+            return Err(InternalError::io_err(
+                "Sliding window script returned nothing",
+                None,
+            ));
+        };
+
+        let counts = counts
+            .chunks_exact(2)
+            .map(|count| WindowCount {
+                count: count[0].max(0) as u64,
+                reset_ms: count[1],
+            })
+            .collect();
+
+        Ok((*allowed == 1, counts))
+    }
+
+    /// Blocks `key` until `until_ms`, unless it already is for longer
+    pub async fn block(&self, key: &str, until_ms: i64, now_ms: i64) -> Result<Unit, PicaError> {
+        let _: i64 = self
+            .block_script
+            .key(key)
+            .arg(until_ms)
+            .arg((until_ms - now_ms).max(1))
+            .invoke_async(&mut self.redis.clone())
+            .await
+            .map_err(|e| InternalError::io_err(&format!("Could not block {key}: {e}"), None))?;
+
+        Ok(())
+    }
+
+    /// Time `key` is blocked until, if it is
+    pub async fn blocked_until(&self, key: &str) -> Result<Option<i64>, PicaError> {
+        self.redis
+            .clone()
+            .get(key)
+            .await
+            .map_err(|e| InternalError::io_err(&format!("Could not get {key}: {e}"), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osentities::cache::CacheConfig;
+    use testcontainers_modules::{redis::Redis, testcontainers::clients::Cli as Docker};
+
+    fn window(key: &str, limit: u64) -> SlidingWindow {
+        SlidingWindow {
+            key: key.to_string(),
+            limit,
+            window: Duration::from_secs(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sliding_windows() {
+        let docker = Docker::default();
+        let node = docker.run(Redis);
+        let config = CacheConfig {
+            url: format!("redis://127.0.0.1:{}", node.get_host_port_ipv4(6379)),
+            ..Default::default()
+        };
+        let windows = SlidingWindows::new(
+            RedisCache::new(&config)
+                .await
+                .expect("Failed to connect to redis"),
+        );
+
+        let access_key = window("access-key", 3);
+        let connection = window("connection", 1);
+        let both = [access_key.clone(), connection.clone()];
+
+        let (allowed, counts) = windows.check(&both, 0).await.expect("Failed to check");
+        assert!(allowed);
+        assert_eq!(
+            counts,
+            vec![
+                WindowCount {
+                    count: 1,
+                    reset_ms: 1_000
+                },
+                WindowCount {
+                    count: 1,
+                    reset_ms: 1_000
+                }
+            ]
+        );
+
+        // A request rejected by one window is not counted in the others
+        let (allowed, counts) = windows.check(&both, 200).await.expect("Failed to check");
+        assert!(!allowed);
+        assert_eq!(counts[0].count, 1);
+        assert_eq!(counts[1].count, 1);
+        assert_eq!(counts[1].reset_ms, 800);
+
+        let (allowed, counts) = windows
+            .check(std::slice::from_ref(&access_key), 300)
+            .await
+            .expect("Failed to check");
+        assert!(allowed);
+        assert_eq!(counts[0].count, 2);
+
+        // Requests leave the window once it passed them
+        let (allowed, counts) = windows.check(&both, 1_000).await.expect("Failed to check");
+        assert!(allowed);
+        assert_eq!(counts[0].count, 2);
+        assert_eq!(counts[1].count, 1);
+
+        assert_eq!(windows.check(&[], 0).await, Ok((true, vec![])));
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let docker = Docker::default();
+        let node = docker.run(Redis);
+        let config = CacheConfig {
+            url: format!("redis://127.0.0.1:{}", node.get_host_port_ipv4(6379)),
+            ..Default::default()
+        };
+        let windows = SlidingWindows::new(
+            RedisCache::new(&config)
+                .await
+                .expect("Failed to connect to redis"),
+        );
+
+        assert_eq!(windows.blocked_until("blocked").await, Ok(None));
+
+        windows
+            .block("blocked", 60_000, 0)
+            .await
+            .expect("Failed to block");
+        windows
+            .block("blocked", 30_000, 0)
+            .await
+            .expect("Failed to block");
+
+        assert_eq!(windows.blocked_until("blocked").await, Ok(Some(60_000)));
+    }
+}
@@ -3,6 +3,7 @@ mod hash;
 mod json;
 mod oauth;
 mod pipeline;
+mod refresh;
 mod secret;
 mod store;
 mod string;
@@ -14,6 +15,7 @@ pub use hash::*;
 pub use json::*;
 pub use oauth::*;
 pub use pipeline::*;
+pub use refresh::*;
 pub use secret::*;
 pub use store::*;
 pub use string::*;
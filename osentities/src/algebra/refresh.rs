@@ -0,0 +1,708 @@
+use super::{DefaultTemplate, MongoStore, SecretExt, TemplateExt};
+use crate::{
+    api_model_config::{ApiModelConfig, ContentType},
+    connection_oauth_definition::{Computation, ConnectionOAuthDefinition, OAuthResponse},
+    environment::Environment,
+    oauth_secret::OAuthSecret,
+    ApplicationError, Connection, ErrorMeta, InternalError, OAuth, PicaError,
+};
+use bson::{doc, Document};
+use chrono::{Duration, Utc};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use mongodb::options::ReturnDocument;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::{debug, error};
+
+/// Seconds subtracted from the provider's `expires_in` so that tokens are
+/// considered expired slightly before the platform rejects them.
+pub const OAUTH_EXPIRY_BUFFER_SECS: i64 = 120;
+
+/// Computes the unix timestamp (in seconds) at which a token issued now with
+/// the given `expires_in` should be treated as expired.
+pub fn oauth_expires_at(expires_in: i32) -> i64 {
+    Utc::now()
+        .checked_add_signed(Duration::seconds(expires_in as i64))
+        .unwrap_or_else(Utc::now)
+        .checked_sub_signed(Duration::seconds(OAUTH_EXPIRY_BUFFER_SECS))
+        .unwrap_or_else(Utc::now)
+        .timestamp()
+}
+
+/// Seconds a refresher holds its claim over a connection. Other refreshers leave the connection
+/// alone until the claim is released or lapses.
+pub const OAUTH_REFRESH_LEASE_SECS: i64 = 60;
+
+/// Subtype of the errors returned when the platform fails a refresh request
+const OAUTH_REFRESH_SUBTYPE: &str = "OAuthRefresh";
+
+/// How often a caller waiting on another refresher checks whether it finished
+const OAUTH_REFRESH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How long a caller waits on another refresher before giving up with a retryable error, well
+/// short of the lease so that requests waiting on a slow refresh don't hang with it
+const OAUTH_REFRESH_MAX_WAIT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Runs the `refresh` half of a `ConnectionOAuthDefinition` against the
+/// platform and persists the resulting secret on the connection.
+///
+/// Connections are claimed through their `refreshingUntil` field before they
+/// are refreshed, so that replicas of the watchdog and the API never refresh
+/// the same token at the same time.
+#[derive(Clone)]
+pub struct OAuthRefresher {
+    connections: MongoStore<Connection>,
+    definitions: MongoStore<ConnectionOAuthDefinition>,
+    secrets_client: Arc<dyn SecretExt>,
+    http_client: reqwest::Client,
+    template: DefaultTemplate,
+}
+
+impl OAuthRefresher {
+    pub fn new(
+        connections: MongoStore<Connection>,
+        definitions: MongoStore<ConnectionOAuthDefinition>,
+        secrets_client: Arc<dyn SecretExt>,
+        http_client: reqwest::Client,
+    ) -> Self {
+        Self {
+            connections,
+            definitions,
+            secrets_client,
+            http_client,
+            template: DefaultTemplate::default(),
+        }
+    }
+
+    /// Refreshes the access token of an OAuth connection, stores the new
+    /// secret and updates `secretsServiceId` and the expiry of the connection.
+    /// Returns the updated connection.
+    ///
+    /// When another refresher holds the connection, waits for it to finish and
+    /// returns the connection it refreshed instead.
+    pub async fn refresh(&self, connection: &Connection) -> Result<Connection, PicaError> {
+        let now = Utc::now().timestamp();
+
+        let claimed = self
+            .claim(doc! { "_id": connection.id.to_string() }, now)
+            .await?;
+
+        match claimed {
+            // Refreshed by someone else since the caller read the connection
+            Some(claimed) if claimed.secrets_service_id != connection.secrets_service_id => {
+                self.release(&claimed).await?;
+                Ok(claimed)
+            }
+            Some(claimed) => self.refresh_claimed(&claimed).await,
+            None => self.wait_for_refresh(connection).await,
+        }
+    }
+
+    /// Atomically claims the OAuth connection expiring first among those that
+    /// expire before `before`, leaving out the connections in `skip`. Claimed
+    /// connections are refreshed with [`OAuthRefresher::refresh_claimed`].
+    pub async fn claim_expiring(
+        &self,
+        before: i64,
+        skip: &[String],
+    ) -> Result<Option<Connection>, PicaError> {
+        self.claim(
+            doc! {
+                "_id": { "$nin": skip },
+                "oauth.enabled.expires_at": { "$lte": before },
+                "hasError": { "$ne": true },
+                "active": true,
+                "deleted": false,
+            },
+            Utc::now().timestamp(),
+        )
+        .await
+    }
+
+    /// Refreshes a connection claimed by this refresher and releases its claim
+    pub async fn refresh_claimed(&self, connection: &Connection) -> Result<Connection, PicaError> {
+        let Some(OAuth::Enabled {
+            connection_oauth_definition_id,
+            ..
+        }) = &connection.oauth
+        else {
+            self.release(connection).await?;
+
+            return Err(ApplicationError::bad_request(
+                &format!("Connection {} does not use OAuth", connection.id),
+                None,
+            ));
+        };
+
+        let refreshed = async {
+            let definition = self
+                .definitions
+                .get_one_by_id(&connection_oauth_definition_id.to_string())
+                .await?
+                .ok_or_else(|| {
+                    ApplicationError::not_found(
+                        &format!(
+                            "Connection OAuth definition {connection_oauth_definition_id} not found"
+                        ),
+                        None,
+                    )
+                })?;
+
+            let secret: OAuthSecret = self
+                .secrets_client
+                .get(&connection.secrets_service_id, &connection.ownership.id)
+                .await?
+                .decode()?;
+
+            let oauth_secret = exchange(
+                &self.http_client,
+                &self.template,
+                &definition,
+                connection.environment,
+                &secret,
+            )
+            .await?;
+
+            let new_secret = self
+                .secrets_client
+                .create(&oauth_secret.as_json(), &connection.ownership.id)
+                .await
+                .map_err(|e| {
+                    error!("Failed to create refreshed oauth secret: {e}");
+                    InternalError::encryption_error(e.message().as_ref(), None)
+                })?;
+
+            Ok::<_, PicaError>((oauth_secret, new_secret))
+        }
+        .await;
+
+        let (oauth_secret, new_secret) = match refreshed {
+            Ok(refreshed) => refreshed,
+            Err(e) => {
+                if let Err(e) = self.release(connection).await {
+                    error!("Failed to release refresh claim of {}: {e}", connection.id);
+                }
+                return Err(e);
+            }
+        };
+
+        let expires_at = oauth_expires_at(oauth_secret.expires_in);
+
+        let mut updated = connection.clone();
+        updated.secrets_service_id = new_secret.id();
+        updated.oauth = Some(OAuth::Enabled {
+            connection_oauth_definition_id: *connection_oauth_definition_id,
+            expires_in: Some(oauth_secret.expires_in),
+            expires_at: Some(expires_at),
+        });
+        updated.record_metadata.mark_updated("system");
+
+        self.connections
+            .update_one(
+                &connection.id.to_string(),
+                doc! {
+                    "$set": {
+                        "secretsServiceId": &updated.secrets_service_id,
+                        "oauth.enabled.expires_in": oauth_secret.expires_in,
+                        "oauth.enabled.expires_at": expires_at,
+                        "updatedAt": updated.record_metadata.updated_at,
+                        "updated": true,
+                    },
+                    "$unset": { "refreshingUntil": "" }
+                },
+            )
+            .await?;
+
+        debug!("Refreshed oauth secret for connection {}", connection.id);
+
+        Ok(updated)
+    }
+
+    /// Flags a connection whose token could not be refreshed so that it is
+    /// no longer picked up and its owner can re-authenticate. Only meant for
+    /// refreshes the platform rejected, see [`is_refresh_rejected`].
+    pub async fn mark_failed(
+        &self,
+        connection: &mut Connection,
+        error: &PicaError,
+    ) -> Result<(), PicaError> {
+        connection.mark_error(&format!("Failed to refresh OAuth token: {error}"));
+
+        self.connections
+            .update_one(
+                &connection.id.to_string(),
+                doc! {
+                    "$set": {
+                        "hasError": connection.has_error,
+                        "error": connection.error.as_deref(),
+                    }
+                },
+            )
+            .await
+    }
+
+    /// Claims a connection matching `filter` that no other refresher holds
+    async fn claim(&self, mut filter: Document, now: i64) -> Result<Option<Connection>, PicaError> {
+        filter.insert(
+            "$or",
+            vec![
+                doc! { "refreshingUntil": { "$exists": false } },
+                doc! { "refreshingUntil": { "$lt": now } },
+            ],
+        );
+
+        let connection = self
+            .connections
+            .collection
+            .find_one_and_update(
+                filter,
+                doc! { "$set": { "refreshingUntil": now + OAUTH_REFRESH_LEASE_SECS } },
+            )
+            .sort(doc! { "oauth.enabled.expires_at": 1 })
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        Ok(connection)
+    }
+
+    async fn release(&self, connection: &Connection) -> Result<(), PicaError> {
+        self.connections
+            .update_one(
+                &connection.id.to_string(),
+                doc! { "$unset": { "refreshingUntil": "" } },
+            )
+            .await
+    }
+
+    /// Waits for the refresher holding a connection to release it and returns
+    /// the connection once its secret changed. Gives up after
+    /// `OAUTH_REFRESH_MAX_WAIT` with a service unavailable error.
+    async fn wait_for_refresh(&self, connection: &Connection) -> Result<Connection, PicaError> {
+        let deadline = tokio::time::Instant::now() + OAUTH_REFRESH_MAX_WAIT;
+
+        loop {
+            if tokio::time::Instant::now() >= deadline {
+                return Err(ApplicationError::service_unavailable(
+                    &format!(
+                        "OAuth token of connection {} is still being refreshed",
+                        connection.id
+                    ),
+                    None,
+                ));
+            }
+
+            tokio::time::sleep(OAUTH_REFRESH_POLL_INTERVAL).await;
+
+            let latest = self
+                .connections
+                .get_one_by_id(&connection.id.to_string())
+                .await?
+                .ok_or_else(|| {
+                    ApplicationError::not_found(
+                        &format!("Connection {} not found", connection.id),
+                        None,
+                    )
+                })?;
+
+            if latest.secrets_service_id != connection.secrets_service_id {
+                return Ok(latest);
+            }
+
+            let held = self
+                .connections
+                .count(
+                    doc! {
+                        "_id": connection.id.to_string(),
+                        "refreshingUntil": { "$gte": Utc::now().timestamp() }
+                    },
+                    Some(1),
+                )
+                .await?;
+
+            if held == 0 {
+                return Err(ApplicationError::service_unavailable(
+                    &format!(
+                        "OAuth token of connection {} could not be refreshed",
+                        connection.id
+                    ),
+                    None,
+                ));
+            }
+        }
+    }
+}
+
+/// Whether the platform rejected a refresh for good, such as for a revoked or
+/// expired refresh token. Other failures, like timeouts or errors and rate
+/// limits of the platform, may not happen again on a later attempt.
+pub fn is_refresh_rejected(error: &PicaError) -> bool {
+    let from_platform = error
+        .key()
+        .to_string()
+        .ends_with(&format!("::{OAUTH_REFRESH_SUBTYPE}"));
+
+    from_platform
+        && (matches!(error.status(), 400 | 401)
+            || error.message().as_ref().contains("invalid_grant"))
+}
+
+/// Data the templates and computations of a refresh are rendered with. The
+/// stored secret is extended with the context the definition is rendered with
+/// when the connection is created.
+fn refresh_payload(environment: Environment, secret: &OAuthSecret) -> Value {
+    let mut metadata = secret.request_payload.clone().unwrap_or(Value::Null);
+
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.insert(
+            "environment".to_string(),
+            Value::String(environment.to_string()),
+        );
+    }
+
+    let mut payload = secret.as_json();
+
+    if let Some(payload) = payload.as_object_mut() {
+        payload.insert(
+            "clientId".to_string(),
+            Value::String(secret.client_id.clone()),
+        );
+        payload.insert(
+            "clientSecret".to_string(),
+            Value::String(secret.client_secret.clone()),
+        );
+        payload.insert("metadata".to_string(), metadata);
+    }
+
+    payload
+}
+
+/// Requests a new token from the platform and returns the secret built from it
+async fn exchange(
+    http_client: &reqwest::Client,
+    template: &DefaultTemplate,
+    definition: &ConnectionOAuthDefinition,
+    environment: Environment,
+    secret: &OAuthSecret,
+) -> Result<OAuthSecret, PicaError> {
+    let payload = refresh_payload(environment, secret);
+
+    let rendered;
+    let definition = if definition.is_full_template_enabled {
+        rendered = template.render_as(definition, Some(&payload))?;
+        &rendered
+    } else {
+        definition
+    };
+
+    let response = execute(http_client, template, definition, &payload).await?;
+
+    let decoded: OAuthResponse = definition.compute.refresh.response.compute(&response)?;
+
+    Ok(secret.from_refresh(decoded, None, None, response))
+}
+
+async fn execute(
+    http_client: &reqwest::Client,
+    template: &DefaultTemplate,
+    definition: &ConnectionOAuthDefinition,
+    payload: &Value,
+) -> Result<Value, PicaError> {
+    let config = &definition.configuration.refresh;
+
+    let computation: Option<Computation> = definition
+        .compute
+        .refresh
+        .computation
+        .as_ref()
+        .map(|f| f.compute(payload))
+        .transpose()?;
+
+    let headers = render_headers(
+        template,
+        config,
+        computation.as_ref().and_then(|c| c.headers.as_ref()),
+    )?;
+    let query_params = render_query_params(
+        template,
+        config,
+        computation.as_ref().and_then(|c| c.query_params.as_ref()),
+    )?;
+    let body = computation
+        .as_ref()
+        .and_then(|c| c.body.as_ref())
+        .map(|body| template.render_as(body, Some(payload)))
+        .transpose()?;
+
+    let mut request = http_client
+        .post(config.uri())
+        .headers(headers)
+        .query(&query_params);
+
+    if let Some(body) = body {
+        request = match config.content {
+            Some(ContentType::Form) => request.form(&body),
+            _ => request.json(&body),
+        };
+    }
+
+    let response = request.send().await?;
+    let status = response.status();
+
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        error!(
+            "OAuth refresh request for {} failed with {status}: {message}",
+            definition.connection_platform
+        );
+
+        return Err(PicaError::from_err_code(
+            status,
+            &message,
+            Some(OAUTH_REFRESH_SUBTYPE),
+        ));
+    }
+
+    response.json::<Value>().await.map_err(|e| {
+        error!("Failed to decode oauth refresh response: {e}");
+        InternalError::deserialize_error(&e.to_string(), None)
+    })
+}
+
+fn render_headers(
+    template: &impl TemplateExt,
+    config: &ApiModelConfig,
+    data: Option<&Value>,
+) -> Result<HeaderMap, PicaError> {
+    let mut headers = HeaderMap::new();
+
+    for (name, value) in config.headers.iter().flatten() {
+        let value = value.to_str().map_err(|e| {
+            InternalError::invalid_argument(&e.to_string(), Some("Invalid header value"))
+        })?;
+        let rendered = template.render(value, data)?;
+
+        let value = HeaderValue::from_str(&rendered).map_err(|e| {
+            InternalError::invalid_argument(&e.to_string(), Some("Invalid header value"))
+        })?;
+
+        headers.append(HeaderName::clone(name), value);
+    }
+
+    Ok(headers)
+}
+
+fn render_query_params(
+    template: &impl TemplateExt,
+    config: &ApiModelConfig,
+    data: Option<&Value>,
+) -> Result<BTreeMap<String, String>, PicaError> {
+    config
+        .query_params
+        .iter()
+        .flatten()
+        .map(|(key, value)| Ok((key.clone(), template.render(value, data)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::{prefix::IdPrefix, Id};
+    use serde_json::json;
+
+    fn config() -> ApiModelConfig {
+        serde_json::from_value(json!({
+            "baseUrl": "https://example.com",
+            "path": "/oauth/token",
+            "authMethod": { "type": "None" },
+            "headers": {
+                "authorization": "Basic {{{credentials}}}",
+                "accept": "application/json"
+            },
+            "queryParams": {
+                "grant_type": "refresh_token",
+                "refresh_token": "{{refreshToken}}"
+            },
+            "schemas": {},
+            "samples": {},
+            "responses": []
+        }))
+        .expect("Failed to deserialize api model config")
+    }
+
+    #[test]
+    fn test_render_headers() {
+        let headers = render_headers(
+            &DefaultTemplate::default(),
+            &config(),
+            Some(&json!({ "credentials": "Zm9vOmJhcg==" })),
+        )
+        .expect("Failed to render headers");
+
+        assert_eq!(headers.get("authorization").unwrap(), "Basic Zm9vOmJhcg==");
+        assert_eq!(headers.get("accept").unwrap(), "application/json");
+    }
+
+    #[test]
+    fn test_render_query_params() {
+        let query_params = render_query_params(
+            &DefaultTemplate::default(),
+            &config(),
+            Some(&json!({ "refreshToken": "abc" })),
+        )
+        .expect("Failed to render query params");
+
+        assert_eq!(query_params.get("grant_type").unwrap(), "refresh_token");
+        assert_eq!(query_params.get("refresh_token").unwrap(), "abc");
+    }
+
+    #[test]
+    fn test_refresh_payload() {
+        let secret = OAuthSecret {
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            access_token: "access".to_string(),
+            token_type: None,
+            refresh_token: Some("refresh".to_string()),
+            expires_in: 3600,
+            metadata: json!({ "access_token": "access" }),
+            request_payload: Some(json!({ "shop": "acme" })),
+        };
+
+        let payload = refresh_payload(Environment::Live, &secret);
+
+        assert_eq!(payload["OAUTH_REFRESH_TOKEN"], "refresh");
+        assert_eq!(payload["clientId"], "client");
+        assert_eq!(payload["clientSecret"], "secret");
+        assert_eq!(
+            payload["metadata"],
+            json!({ "shop": "acme", "environment": "live" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exchange() {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("POST", "/acme/oauth/token")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "refresh_token".to_string(),
+                "refresh".to_string(),
+            ))
+            .match_header("authorization", "Basic client:secret")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"access_token":"new","expires_in":1800}"#)
+            .create_async()
+            .await;
+
+        let definition: ConnectionOAuthDefinition = serde_json::from_value(json!({
+            "_id": Id::now(IdPrefix::ConnectionOAuthDefinition),
+            "connectionPlatform": "acme",
+            "isFullTemplateEnabled": true,
+            "configuration": {
+                "init": config(),
+                "refresh": {
+                    "baseUrl": format!("{}/{{{{metadata.shop}}}}", server.url()),
+                    "path": "/oauth/token",
+                    "authMethod": { "type": "None" },
+                    "headers": {
+                        "authorization": "Basic {{clientId}}:{{clientSecret}}"
+                    },
+                    "queryParams": {
+                        "refresh_token": "{{OAUTH_REFRESH_TOKEN}}"
+                    },
+                    "schemas": {},
+                    "samples": {},
+                    "responses": []
+                }
+            },
+            "compute": {
+                "init": {
+                    "response": { "entry": "compute", "function": "", "language": "javascript" }
+                },
+                "refresh": {
+                    "response": {
+                        "entry": "compute",
+                        "function": "function compute(payload) { return { accessToken: payload.access_token, expiresIn: payload.expires_in }; }",
+                        "language": "javascript"
+                    }
+                }
+            },
+            "frontend": {
+                "platformRedirectUri": "https://acme.com/oauth",
+                "scopes": "",
+                "iosRedirectUri": "https://acme.com/oauth"
+            }
+        }))
+        .expect("Failed to deserialize connection oauth definition");
+
+        let secret = OAuthSecret {
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            access_token: "old".to_string(),
+            token_type: Some("Bearer".to_string()),
+            refresh_token: Some("refresh".to_string()),
+            expires_in: 3600,
+            metadata: Value::Null,
+            request_payload: Some(json!({ "shop": "acme" })),
+        };
+
+        let refreshed = exchange(
+            &reqwest::Client::new(),
+            &DefaultTemplate::default(),
+            &definition,
+            Environment::Live,
+            &secret,
+        )
+        .await
+        .expect("Failed to refresh token");
+
+        mock.assert_async().await;
+
+        assert_eq!(refreshed.access_token, "new");
+        assert_eq!(refreshed.expires_in, 1800);
+        assert_eq!(refreshed.refresh_token.as_deref(), Some("refresh"));
+        assert_eq!(
+            refreshed.metadata,
+            json!({ "access_token": "new", "expires_in": 1800 })
+        );
+    }
+
+    #[test]
+    fn test_is_refresh_rejected() {
+        let rejected = |status: u16, message: &str| {
+            is_refresh_rejected(&PicaError::from_err_code(
+                http::StatusCode::from_u16(status).expect("Invalid status"),
+                message,
+                Some(OAUTH_REFRESH_SUBTYPE),
+            ))
+        };
+
+        assert!(rejected(400, r#"{"error":"invalid_grant"}"#));
+        assert!(rejected(401, "Unauthorized"));
+        assert!(rejected(403, r#"{"error":"invalid_grant"}"#));
+
+        assert!(!rejected(429, "Too many requests"));
+        assert!(!rejected(500, "Internal server error"));
+        assert!(!rejected(503, "Service unavailable"));
+        assert!(!is_refresh_rejected(&InternalError::io_err(
+            "Connection reset",
+            None
+        )));
+        assert!(!is_refresh_rejected(&ApplicationError::bad_request(
+            "Invalid secret",
+            None
+        )));
+    }
+
+    #[test]
+    fn test_oauth_expires_at() {
+        let now = Utc::now().timestamp();
+        let expires_at = oauth_expires_at(3600);
+
+        assert!(expires_at >= now + 3600 - OAUTH_EXPIRY_BUFFER_SECS);
+        assert!(expires_at <= now + 3600);
+    }
+}
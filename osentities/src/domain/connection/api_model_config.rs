@@ -2,7 +2,7 @@ use http::HeaderMap;
 use js_sandbox_ios::Script;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, time::Duration};
 
 use crate::{prelude::schema::json_schema::JsonSchema, InternalError, PicaError};
 
@@ -23,11 +23,17 @@ pub struct ApiModelConfig {
     pub query_params: Option<BTreeMap<String, String>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub content: Option<ContentType>,
+    /// Format of the responses of the platform, detected from their `Content-Type` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_content: Option<ResponseContent>,
     pub schemas: SchemasInput,
     pub samples: SamplesInput,
     pub responses: Vec<ResponseBody>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paths: Option<ModelPaths>,
+    /// Requests are sent once when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_policy: Option<RetryPolicy>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
@@ -37,10 +43,126 @@ pub struct ApiModelConfig {
 pub enum ContentType {
     Json,
     Form,
+    /// JSON bodies are sent as `multipart/form-data`, their file parts given as base64 objects
+    Multipart,
     #[default]
     Other,
 }
 
+/// Format of the body of a platform response, bodies that are not JSON are converted to JSON
+/// before they are mapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum ResponseContent {
+    Json,
+    /// Elements become objects, with their attributes prefixed by `@` and their text under
+    /// `#text` when they also have attributes or children
+    Xml,
+    /// Rows become objects keyed by the header row
+    Csv,
+    /// `application/x-www-form-urlencoded`, repeated keys become arrays
+    Form,
+    /// Returned base64 encoded along with its content type
+    Binary,
+}
+
+/// How requests that failed with a transient error are retried
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Randomizes half of each backoff so clients don't retry in lockstep
+    #[serde(default = "default_jitter")]
+    pub jitter: bool,
+    #[serde(default = "default_retryable_status_codes")]
+    pub retryable_status_codes: Vec<u16>,
+    /// Also retries POST and PATCH requests, which the platform may apply twice
+    #[serde(default)]
+    pub retry_non_idempotent: bool,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    200
+}
+
+fn default_max_backoff_ms() -> u64 {
+    5_000
+}
+
+fn default_jitter() -> bool {
+    true
+}
+
+fn default_retryable_status_codes() -> Vec<u16> {
+    vec![408, 429, 500, 502, 503, 504]
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            jitter: default_jitter(),
+            retryable_status_codes: default_retryable_status_codes(),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether requests with `method` can be retried
+    pub fn allows(&self, method: &http::Method) -> bool {
+        self.retry_non_idempotent
+            || matches!(
+                *method,
+                http::Method::GET
+                    | http::Method::HEAD
+                    | http::Method::OPTIONS
+                    | http::Method::PUT
+                    | http::Method::DELETE
+            )
+    }
+
+    pub fn is_retryable(&self, status: http::StatusCode) -> bool {
+        self.retryable_status_codes.contains(&status.as_u16())
+    }
+
+    /// Delay before the `retry`th retry, doubling from the initial backoff. With jitter, half
+    /// of it is scaled by `random`, a number between 0 and 1.
+    pub fn backoff(&self, retry: u32, random: f64) -> Duration {
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(2u64.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff_ms);
+
+        let backoff = if self.jitter {
+            let half = backoff / 2;
+            half + (half as f64 * random.clamp(0.0, 1.0)) as u64
+        } else {
+            backoff
+        };
+
+        Duration::from_millis(backoff)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
+    }
+}
+
 impl ApiModelConfig {
     /// Returns the full path of the API endpoint
     /// e.g. https://api.example.com/v1/users
@@ -209,3 +331,52 @@ pub enum Lang {
     TypeScript,
     Rust,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_policy_defaults() {
+        let policy: RetryPolicy = serde_json::from_value(serde_json::json!({ "maxAttempts": 5 }))
+            .expect("Failed to deserialize retry policy");
+
+        assert_eq!(
+            policy,
+            RetryPolicy {
+                max_attempts: 5,
+                ..Default::default()
+            }
+        );
+        assert!(policy.allows(&http::Method::GET));
+        assert!(policy.allows(&http::Method::DELETE));
+        assert!(!policy.allows(&http::Method::POST));
+        assert!(policy.is_retryable(http::StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!policy.is_retryable(http::StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = RetryPolicy {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            jitter: false,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.backoff(1, 0.5), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2, 0.5), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4, 0.5), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5, 0.5), Duration::from_millis(1_000));
+        assert_eq!(policy.backoff(100, 0.5), Duration::from_millis(1_000));
+
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+
+        assert_eq!(policy.backoff(2, 0.0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2, 0.5), Duration::from_millis(150));
+        assert_eq!(policy.backoff(2, 1.0), Duration::from_millis(200));
+    }
+}
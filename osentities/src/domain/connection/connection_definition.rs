@@ -1,4 +1,4 @@
-use super::{api_model_config::AuthMethod, ConnectionType};
+use super::{api_model_config::AuthMethod, rate_limit::ProviderRateLimit, ConnectionType};
 use crate::id::Id;
 use crate::prelude::shared::{record_metadata::RecordMetadata, settings::Settings};
 use serde::{Deserialize, Serialize};
@@ -44,6 +44,10 @@ pub struct ConnectionDefinition {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[tabled(skip)]
     pub test_delay_in_millis: Option<i16>,
+    /// Limits of the provider on every call made to its API
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[tabled(skip)]
+    pub rate_limits: Vec<ProviderRateLimit>,
     #[serde(flatten, default)]
     #[tabled(skip)]
     pub record_metadata: RecordMetadata,
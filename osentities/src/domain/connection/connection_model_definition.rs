@@ -1,4 +1,4 @@
-use super::api_model_config::ApiModelConfig;
+use super::{api_model_config::ApiModelConfig, rate_limit::ProviderRateLimit};
 use crate::{
     id::Id,
     prelude::{schema::common_model::CommonModel, shared::record_metadata::RecordMetadata},
@@ -54,6 +54,14 @@ pub struct ConnectionModelDefinition {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub knowledge: Option<String>,
 
+    /// Limits of the provider on this action, on top of the ones of its connection definition
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rate_limits: Vec<ProviderRateLimit>,
+
+    /// How long responses of reads through this action are cached for, not cached if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_ttl_secs: Option<u64>,
+
     #[serde(flatten, default)]
     pub record_metadata: RecordMetadata,
 }
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Limit a provider enforces on the calls made to its API, such as 10 requests per second
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderRateLimit {
+    pub requests: u64,
+    pub period_ms: u64,
+    #[serde(default)]
+    pub scope: RateLimitScope,
+}
+
+impl ProviderRateLimit {
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(self.period_ms)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum RateLimitScope {
+    /// Every connection has its own allowance, such as a limit per access token
+    #[default]
+    Connection,
+    /// Every connection to the platform shares the allowance, such as a limit per app
+    Platform,
+}
@@ -37,6 +37,10 @@ pub const JWT_SECRET_REF_NAME: &str = "event-secrets";
 
 // Header constants
 pub const PICA_PASSTHROUGH_HEADER: &str = "x-pica-passthrough";
+/// Set to `no-cache` to skip the cached responses of unified reads
+pub const PICA_CACHE_CONTROL_HEADER: &str = "x-pica-cache-control";
+pub const PICA_SIGNATURE_HEADER: &str = "x-pica-signature";
+pub const PICA_TIMESTAMP_HEADER: &str = "x-pica-timestamp";
 
 // Encryption constants
 pub const HASH_LENGTH: usize = 32;
@@ -103,6 +107,10 @@ pub const PASSTHROUGH_KEY: &str = "passthrough";
 pub const LIMIT_KEY: &str = "limit";
 pub const PAGE_SIZE_KEY: &str = "pageSize";
 pub const PAGINATION_KEY: &str = "pagination";
+pub const FETCH_ALL_KEY: &str = "fetchAll";
+pub const MAX_RECORDS_KEY: &str = "maxRecords";
+pub const MAX_PAGES_KEY: &str = "maxPages";
+pub const PAGES_KEY: &str = "pages";
 pub const STATUS_HEADER_KEY: &str = "response-status";
 pub const META_KEY: &str = "meta";
 pub const ACTION_KEY: &str = "action";
@@ -13,7 +13,7 @@ impl IntoResponse for PicaError {
 
 impl IntoResponse for &PicaError {
     fn into_response(self) -> Response {
-        let body = self.to_owned().as_application().as_json();
+        let body = self.as_response_json();
 
         let status: StatusCode = self.into();
 
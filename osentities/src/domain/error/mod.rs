@@ -570,6 +570,20 @@ pub enum ApplicationError {
         subtype: Option<String>,
         meta: Option<Box<Value>>,
     },
+    /// The platform failed too many times in a row and calls to it are rejected for a while
+    #[error("Circuit Open: {}", .message)]
+    CircuitOpen {
+        message: String,
+        subtype: Option<String>,
+        meta: Option<Box<Value>>,
+    },
+    /// A mapping script ran for longer than it is allowed to
+    #[error("Script Timeout: {}", .message)]
+    ScriptTimeout {
+        message: String,
+        subtype: Option<String>,
+        meta: Option<Box<Value>>,
+    },
 }
 
 impl From<anyhow::Error> for ApplicationError {
@@ -682,6 +696,22 @@ impl ApplicationError {
         })
     }
 
+    pub fn circuit_open(message: &str, subtype: Option<&str>) -> PicaError {
+        PicaError::application(ApplicationError::CircuitOpen {
+            message: message.to_string(),
+            subtype: subtype.map(|s| s.to_string().snake_case()),
+            meta: None,
+        })
+    }
+
+    pub fn script_timeout(message: &str, subtype: Option<&str>) -> PicaError {
+        PicaError::application(ApplicationError::ScriptTimeout {
+            message: message.to_string(),
+            subtype: subtype.map(|s| s.to_string().snake_case()),
+            meta: None,
+        })
+    }
+
     fn set_meta(self, meta: Box<Value>) -> Self {
         match self {
             ApplicationError::BadRequest {
@@ -768,6 +798,20 @@ impl ApplicationError {
                 subtype: subtype.clone(),
                 meta: Some(meta),
             },
+            ApplicationError::CircuitOpen {
+                message, subtype, ..
+            } => ApplicationError::CircuitOpen {
+                message: message.clone(),
+                subtype: subtype.clone(),
+                meta: Some(meta),
+            },
+            ApplicationError::ScriptTimeout {
+                message, subtype, ..
+            } => ApplicationError::ScriptTimeout {
+                message: message.clone(),
+                subtype: subtype.clone(),
+                meta: Some(meta),
+            },
         }
     }
 }
@@ -787,6 +831,8 @@ impl ErrorMeta for ApplicationError {
             ApplicationError::TooManyRequests { .. } => ErrorCode(2009),
             ApplicationError::Unauthorized { .. } => ErrorCode(2010),
             ApplicationError::UnprocessableEntity { .. } => ErrorCode(2011),
+            ApplicationError::CircuitOpen { .. } => ErrorCode(2012),
+            ApplicationError::ScriptTimeout { .. } => ErrorCode(2013),
         }
     }
 
@@ -828,6 +874,12 @@ impl ErrorMeta for ApplicationError {
             ApplicationError::UnprocessableEntity { subtype, .. } => {
                 ErrorKey::application("unprocessable_entity", subtype.as_deref())
             }
+            ApplicationError::CircuitOpen { subtype, .. } => {
+                ErrorKey::application("circuit_open", subtype.as_deref())
+            }
+            ApplicationError::ScriptTimeout { subtype, .. } => {
+                ErrorKey::application("script_timeout", subtype.as_deref())
+            }
         }
     }
 
@@ -851,6 +903,8 @@ impl ErrorMeta for ApplicationError {
             ApplicationError::UnprocessableEntity { message, .. } => {
                 ErrorMessage(message.to_string())
             }
+            ApplicationError::CircuitOpen { message, .. } => ErrorMessage(message.to_string()),
+            ApplicationError::ScriptTimeout { message, .. } => ErrorMessage(message.to_string()),
         }
     }
 
@@ -868,6 +922,8 @@ impl ErrorMeta for ApplicationError {
             ApplicationError::TooManyRequests { meta, .. } => meta.clone(),
             ApplicationError::Unauthorized { meta, .. } => meta.clone(),
             ApplicationError::UnprocessableEntity { meta, .. } => meta.clone(),
+            ApplicationError::CircuitOpen { meta, .. } => meta.clone(),
+            ApplicationError::ScriptTimeout { meta, .. } => meta.clone(),
         }
     }
 }
@@ -1023,6 +1079,8 @@ impl<'a> From<&'a PicaError> for StatusCode {
                 ApplicationError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
                 ApplicationError::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
                 ApplicationError::UnprocessableEntity { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+                ApplicationError::CircuitOpen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+                ApplicationError::ScriptTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
             },
         }
     }
@@ -1289,6 +1347,11 @@ impl PicaError {
         })
     }
 
+    /// Body of the responses failing with this error
+    pub fn as_response_json(&self) -> serde_json::Value {
+        self.as_application().as_json()
+    }
+
     pub fn set_meta(self, meta: &Value) -> Self {
         match self {
             PicaError::Internal(e) => PicaError::internal(e.set_meta(Box::new(meta.clone()))),
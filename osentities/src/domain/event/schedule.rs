@@ -0,0 +1,284 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+/// How often a recurring task runs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskSchedule {
+    Cron(CronExpression),
+    /// Runs again this many seconds after the previous run ended
+    IntervalSecs(u64),
+}
+
+impl TaskSchedule {
+    /// Start of the first run after `after`, `None` if the schedule never runs again
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            TaskSchedule::Cron(expression) => expression.next_after(after),
+            TaskSchedule::IntervalSecs(0) => None,
+            TaskSchedule::IntervalSecs(secs) => {
+                Some(after + Duration::seconds(i64::try_from(*secs).ok()?))
+            }
+        }
+    }
+}
+
+/// Five fields cron expression, `minute hour day-of-month month day-of-week`, evaluated in UTC.
+/// Fields accept `*`, values, ranges, lists and steps such as `*/15` or `1-5`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronExpression {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and day of week fields are `*`
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronExpression {
+    /// First minute strictly after `after` that matches the expression
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        // Impossible expressions such as `0 0 30 2 *` never match
+        let limit = after + Duration::days(366 * 5);
+
+        while next <= limit {
+            if !contains(self.months, next.month()) {
+                let (year, month) = match next.month() {
+                    12 => (next.year() + 1, 1),
+                    month => (next.year(), month + 1),
+                };
+                next = midnight(NaiveDate::from_ymd_opt(year, month, 1)?);
+            } else if !self.matches_day(next) {
+                next = midnight(next.date_naive().succ_opt()?);
+            } else if !contains(self.hours, next.hour()) {
+                next = next.with_minute(0)? + Duration::hours(1);
+            } else if !contains(self.minutes, next.minute()) {
+                next += Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+
+        None
+    }
+
+    fn matches_day(&self, date: DateTime<Utc>) -> bool {
+        let day = contains(self.days, date.day());
+        let weekday = contains(self.weekdays, date.weekday().num_days_from_sunday());
+
+        // As in cron, a day matches either field when both are restricted
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            (false, true) => day,
+            (true, false) => weekday,
+            (true, true) => true,
+        }
+    }
+}
+
+impl FromStr for CronExpression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days, months, weekdays] = fields.as_slice() else {
+            return Err(format!(
+                "Invalid cron expression {s}: expected 5 fields, found {}",
+                fields.len()
+            ));
+        };
+
+        let field = |value: &str, min: u32, max: u32| {
+            parse_field(value, min, max)
+                .map_err(|e| format!("Invalid cron expression {s}: field {value} {e}"))
+        };
+
+        // Sunday can be written as 0 or 7
+        let mut weekdays_mask = field(weekdays, 0, 7)?;
+        if contains(weekdays_mask, 7) {
+            weekdays_mask |= 1;
+        }
+
+        Ok(CronExpression {
+            expression: s.to_string(),
+            minutes: field(minutes, 0, 59)?,
+            hours: field(hours, 0, 23)?,
+            days: field(days, 1, 31)?,
+            months: field(months, 1, 12)?,
+            weekdays: weekdays_mask,
+            any_day: *days == "*",
+            any_weekday: *weekdays == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for CronExpression {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<CronExpression> for String {
+    fn from(value: CronExpression) -> Self {
+        value.expression
+    }
+}
+
+impl Display for CronExpression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+fn midnight(date: NaiveDate) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&date.and_time(Default::default()))
+}
+
+fn contains(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+/// Bitmask of the values between `min` and `max` matched by a cron field
+fn parse_field(value: &str, min: u32, max: u32) -> Result<u64, String> {
+    let number = |n: &str| {
+        n.parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(|| format!("has a value outside of {min}-{max}"))
+    };
+
+    let mut mask = 0;
+
+    for item in value.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err("has an invalid step".to_string()),
+            },
+            None => (item, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/10` starts at 5 and runs to the end of the range
+                None if step > 1 => (number(range)?, max),
+                None => {
+                    let value = number(range)?;
+                    (value, value)
+                }
+            },
+        };
+
+        if start > end {
+            return Err("has a decreasing range".to_string());
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .expect("Failed to parse date")
+            .with_timezone(&Utc)
+    }
+
+    fn next(expression: &str, after: &str) -> Option<DateTime<Utc>> {
+        CronExpression::from_str(expression)
+            .expect("Failed to parse cron expression")
+            .next_after(at(after))
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        assert_eq!(
+            next("* * * * *", "2024-01-01T10:00:30Z"),
+            Some(at("2024-01-01T10:01:00Z"))
+        );
+        assert_eq!(
+            next("*/15 * * * *", "2024-01-01T10:01:00Z"),
+            Some(at("2024-01-01T10:15:00Z"))
+        );
+        assert_eq!(
+            next("30 2 * * *", "2024-01-01T10:00:00Z"),
+            Some(at("2024-01-02T02:30:00Z"))
+        );
+        assert_eq!(
+            next("0 0 1 * *", "2024-12-15T00:00:00Z"),
+            Some(at("2025-01-01T00:00:00Z"))
+        );
+        // 2024-01-06 is a Saturday
+        assert_eq!(
+            next("0 9 * * 1-5", "2024-01-05T10:00:00Z"),
+            Some(at("2024-01-08T09:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 * * 7", "2024-01-01T00:00:00Z"),
+            Some(at("2024-01-07T00:00:00Z"))
+        );
+        // Either the day of month or the day of week matches when both are restricted
+        assert_eq!(
+            next("0 0 15 * 0", "2024-01-01T00:00:00Z"),
+            Some(at("2024-01-07T00:00:00Z"))
+        );
+        assert_eq!(next("0 0 30 2 *", "2024-01-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn test_invalid_cron_expressions() {
+        for expression in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(
+                CronExpression::from_str(expression).is_err(),
+                "{expression} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn test_task_schedule_serde() {
+        let schedule: TaskSchedule =
+            serde_json::from_value(serde_json::json!({ "cron": "0 * * * *" }))
+                .expect("Failed to deserialize schedule");
+        assert_eq!(
+            serde_json::to_value(&schedule).expect("Failed to serialize schedule"),
+            serde_json::json!({ "cron": "0 * * * *" })
+        );
+        assert!(
+            serde_json::from_value::<TaskSchedule>(serde_json::json!({ "cron": "0 *" })).is_err()
+        );
+
+        let schedule = TaskSchedule::IntervalSecs(60);
+        assert_eq!(
+            schedule.next_after(at("2024-01-01T00:00:00Z")),
+            Some(at("2024-01-01T00:01:00Z"))
+        );
+        assert_eq!(TaskSchedule::IntervalSecs(0).next_after(Utc::now()), None);
+    }
+}
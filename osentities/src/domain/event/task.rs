@@ -1,7 +1,17 @@
-use crate::{record_metadata::RecordMetadata, Id};
+use super::schedule::TaskSchedule;
+use crate::{
+    connection_model_definition::CrudAction, destination::Action, environment::Environment,
+    ownership::Ownership, prefix::IdPrefix, record_metadata::RecordMetadata, Id, InternalError,
+    PicaError,
+};
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, Method};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha2::Sha256;
+use std::{collections::BTreeMap, time::Duration};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -13,9 +23,465 @@ pub struct Task {
     pub end_time: Option<i64>,
     pub payload: Value,
     pub endpoint: String,
+    /// Method used to call the endpoint, or the platform API for passthrough targets
+    #[serde(with = "http_serde_ext_ios::method", default = "default_method")]
+    pub method: Method,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub query_params: BTreeMap<String, String>,
+    /// Connection called instead of the endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<TaskTarget>,
+    /// Notified once the task is completed or dead-lettered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback: Option<TaskCallback>,
     pub status: Option<String>,
     pub r#await: bool,
     pub log_trail: Vec<Bytes>,
+    /// Recurring tasks are scheduled again after each run instead of completing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<TaskSchedule>,
+    #[serde(default)]
+    pub retry_policy: TaskRetryPolicy,
+    /// Number of times the task has been executed
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub state: TaskState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Worker executing the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claimed_by: Option<String>,
+    /// The task is executed again by another worker if its lease is not renewed by then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease_expires_at: Option<i64>,
+    /// Owner of the access key that created the task, the task and its runs are only visible to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ownership: Option<Ownership>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
     #[serde(flatten)]
     pub metadata: RecordMetadata,
 }
+
+impl Task {
+    /// Start time of the next run of a recurring task, once the current one ended at `now`
+    pub fn next_start_time(&self, now: DateTime<Utc>) -> Option<i64> {
+        self.schedule
+            .as_ref()?
+            .next_after(now)
+            .map(|next| next.timestamp_millis())
+    }
+
+    /// The task as returned by the API, without the key its callback is signed with
+    pub fn to_public(&self) -> Value {
+        let mut task = serde_json::to_value(self).unwrap_or_default();
+
+        if let Some(callback) = task.get_mut("callback").and_then(Value::as_object_mut) {
+            callback.remove("secret");
+        }
+
+        task
+    }
+
+    /// Schedules a dead-lettered task to be executed again with a fresh set of attempts
+    pub fn replay(&mut self) {
+        self.worker_id = 0;
+        self.start_time = Utc::now().timestamp_millis();
+        self.end_time = None;
+        self.attempts = 0;
+        self.state = TaskState::Pending;
+        self.last_error = None;
+        self.claimed_by = None;
+        self.lease_expires_at = None;
+        self.metadata.active = true;
+        self.metadata.mark_updated("system");
+    }
+}
+
+fn default_method() -> Method {
+    Method::POST
+}
+
+/// Pica connection called by a task, the payload is sent as the request body
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskTarget {
+    pub connection_key: String,
+    /// The connection must belong to this owner, set from the access key that created the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ownership_id: Option<String>,
+    pub action: TaskAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum TaskAction {
+    /// Action of a unified model, such as `{"type":"unified","model":"Contacts","action":"create"}`
+    Unified {
+        model: String,
+        action: CrudAction,
+        #[serde(default)]
+        id: Option<String>,
+    },
+    /// Path of the platform API, called with the method of the task
+    Passthrough { path: String },
+}
+
+impl TaskTarget {
+    pub fn action(&self, method: &Method) -> Action {
+        match &self.action {
+            TaskAction::Unified { model, action, id } => Action::Unified {
+                name: model.as_str().into(),
+                action: action.clone(),
+                id: id.as_deref().map(Into::into),
+                passthrough: false,
+            },
+            TaskAction::Passthrough { path } => Action::Passthrough {
+                method: method.clone(),
+                path: path.as_str().into(),
+                id: None,
+            },
+        }
+    }
+}
+
+/// Endpoint notified with the outcome of a task
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskCallback {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent along with the callback
+    pub secret: String,
+}
+
+impl TaskCallback {
+    /// Hex encoded HMAC-SHA256 of `{timestamp}.{body}`, so that receivers can
+    /// check the callback was sent by Pica and reject replayed ones
+    pub fn sign(&self, timestamp: i64, body: &[u8]) -> Result<String, PicaError> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).map_err(|e| {
+            InternalError::invalid_argument(&format!("Invalid callback secret: {e}"), None)
+        })?;
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// A single execution of a task
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskRun {
+    #[serde(rename = "_id")]
+    pub id: Id,
+    pub task_id: Id,
+    pub attempt: u32,
+    /// When the run was due, it can start later than that
+    pub scheduled_at: i64,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub status: Option<String>,
+    pub error: Option<String>,
+    /// State of the task once the run ended
+    pub state: TaskState,
+    /// Absent when the target could not be reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<TaskResponse>,
+    #[serde(flatten)]
+    pub metadata: RecordMetadata,
+}
+
+impl TaskRun {
+    pub fn new(task: &Task, started_at: DateTime<Utc>) -> Self {
+        Self {
+            id: Id::now(IdPrefix::TaskRun),
+            task_id: task.id,
+            attempt: task.attempts + 1,
+            scheduled_at: task.start_time,
+            started_at: started_at.timestamp_millis(),
+            ended_at: started_at.timestamp_millis(),
+            status: None,
+            error: None,
+            state: TaskState::Pending,
+            response: None,
+            metadata: RecordMetadata::default(),
+        }
+    }
+}
+
+/// Response received by a run, with a body bounded in size so that runs fit in a document
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskResponse {
+    pub headers: BTreeMap<String, String>,
+    /// Body decoded as UTF-8, invalid sequences are replaced
+    pub body: String,
+    /// Size of the whole body, in bytes
+    pub size: u64,
+    pub truncated: bool,
+    /// Location of the body when it was truncated and stored in a blob store
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    /// Whether the stored blob is itself cut at the size limit of the blob store
+    #[serde(default)]
+    pub blob_truncated: bool,
+}
+
+impl TaskResponse {
+    /// Keeps the first `limit` bytes of `body`, which starts a body of `size` bytes
+    pub fn new(headers: &HeaderMap, body: &[u8], size: u64, limit: usize) -> Self {
+        let mut kept = &body[..body.len().min(limit)];
+
+        // Characters cut by the limit are dropped rather than replaced
+        if (kept.len() as u64) < size {
+            if let Err(e) = std::str::from_utf8(kept) {
+                if e.error_len().is_none() {
+                    kept = &kept[..e.valid_up_to()];
+                }
+            }
+        }
+
+        let mut recorded = BTreeMap::<String, String>::new();
+        for (name, value) in headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            recorded
+                .entry(name.to_string())
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(&value);
+                })
+                .or_insert_with(|| value.into_owned());
+        }
+
+        Self {
+            headers: recorded,
+            body: String::from_utf8_lossy(kept).into_owned(),
+            size,
+            truncated: (kept.len() as u64) < size,
+            blob: None,
+            blob_truncated: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskState {
+    #[default]
+    Pending,
+    Completed,
+    /// Every attempt failed, the task is only executed again if it is replayed.
+    /// Recurring tasks are scheduled again instead.
+    DeadLettered,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TaskRetryPolicy {
+    /// Total number of attempts, the first one included
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Response status codes that are retried, network errors are always retried
+    pub retry_on: Vec<u16>,
+}
+
+impl Default for TaskRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 300_000,
+            retry_on: vec![408, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl TaskRetryPolicy {
+    pub fn retries(&self, status: u16) -> bool {
+        self.retry_on.contains(&status)
+    }
+
+    /// Whether another attempt is allowed after `attempts` attempts
+    pub fn allows_attempt(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// Delay before the attempt following the `attempt`th one, doubling after each attempt
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(factor)
+            .min(self.max_backoff_ms);
+
+        Duration::from_millis(backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = TaskRetryPolicy {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 500,
+            retry_on: vec![503],
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+
+        assert!(policy.retries(503));
+        assert!(!policy.retries(400));
+        assert!(policy.allows_attempt(4));
+        assert!(!policy.allows_attempt(5));
+    }
+
+    #[test]
+    fn test_task_defaults_for_existing_documents() {
+        let task: Task = serde_json::from_value(serde_json::json!({
+            "_id": Id::now(IdPrefix::Task).to_string(),
+            "workerId": 0,
+            "startTime": 0,
+            "endTime": null,
+            "payload": {},
+            "endpoint": "http://localhost",
+            "status": null,
+            "await": false,
+            "logTrail": []
+        }))
+        .expect("Failed to deserialize task");
+
+        assert_eq!(task.attempts, 0);
+        assert_eq!(task.state, TaskState::Pending);
+        assert_eq!(task.retry_policy, TaskRetryPolicy::default());
+        assert_eq!(task.schedule, None);
+        assert_eq!(task.next_start_time(Utc::now()), None);
+        assert_eq!(task.method, Method::POST);
+        assert!(task.headers.is_empty());
+        assert_eq!(task.target, None);
+        assert_eq!(task.callback, None);
+    }
+
+    #[test]
+    fn test_public_task_hides_callback_secret() {
+        let task: Task = serde_json::from_value(serde_json::json!({
+            "_id": Id::now(IdPrefix::Task).to_string(),
+            "workerId": 0,
+            "startTime": 0,
+            "endTime": null,
+            "payload": {},
+            "endpoint": "http://localhost",
+            "status": null,
+            "await": false,
+            "logTrail": [],
+            "callback": { "url": "https://example.com/callback", "secret": "key" }
+        }))
+        .expect("Failed to deserialize task");
+
+        let public = task.to_public();
+
+        assert_eq!(
+            public["callback"],
+            serde_json::json!({ "url": "https://example.com/callback" })
+        );
+        assert_eq!(task.callback.map(|c| c.secret).as_deref(), Some("key"));
+    }
+
+    #[test]
+    fn test_task_response_truncation() {
+        let mut headers = HeaderMap::new();
+        headers.append("set-cookie", "a=1".parse().expect("Invalid header"));
+        headers.append("set-cookie", "b=2".parse().expect("Invalid header"));
+        headers.insert(
+            "content-type",
+            "text/plain".parse().expect("Invalid header"),
+        );
+
+        let response = TaskResponse::new(&headers, b"hello", 5, 10);
+        assert_eq!(response.body, "hello");
+        assert!(!response.truncated);
+        assert_eq!(response.headers["set-cookie"], "a=1, b=2");
+        assert_eq!(response.headers["content-type"], "text/plain");
+
+        let response = TaskResponse::new(&headers, b"hello", 5, 3);
+        assert_eq!(response.body, "hel");
+        assert_eq!(response.size, 5);
+        assert!(response.truncated);
+
+        // Only part of the body was read
+        let response = TaskResponse::new(&headers, b"hel", 5, 3);
+        assert_eq!(response.body, "hel");
+        assert!(response.truncated);
+
+        // "é" is two bytes long
+        let response = TaskResponse::new(&headers, "aé".as_bytes(), 3, 2);
+        assert_eq!(response.body, "a");
+        assert!(response.truncated);
+
+        let response = TaskResponse::new(&headers, &"aé".as_bytes()[..2], 3, 2);
+        assert_eq!(response.body, "a");
+
+        let response = TaskResponse::new(&headers, &[0xff, b'a'], 2, 10);
+        assert_eq!(response.body, "\u{fffd}a");
+    }
+
+    #[test]
+    fn test_task_target_action() {
+        let target: TaskTarget = serde_json::from_value(serde_json::json!({
+            "connectionKey": "live::hubspot::default::abc",
+            "action": { "type": "unified", "model": "Contacts", "action": "getOne", "id": "1" }
+        }))
+        .expect("Failed to deserialize target");
+
+        assert_eq!(
+            target.action(&Method::POST),
+            Action::Unified {
+                name: "Contacts".into(),
+                action: CrudAction::GetOne,
+                id: Some("1".into()),
+                passthrough: false,
+            }
+        );
+
+        let target = TaskTarget {
+            action: TaskAction::Passthrough {
+                path: "/crm/v3/objects/contacts".to_string(),
+            },
+            ..target
+        };
+
+        assert_eq!(
+            target.action(&Method::PATCH),
+            Action::Passthrough {
+                method: Method::PATCH,
+                path: "/crm/v3/objects/contacts".into(),
+                id: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_callback_signature() {
+        let callback = TaskCallback {
+            url: "http://localhost".to_string(),
+            secret: "secret".to_string(),
+        };
+
+        assert_eq!(
+            callback.sign(1700000000000, b"{}").ok().as_deref(),
+            Some("8399216d111287e3bb28e25c0f4f31dffdf831c68c9ee2b96c2f67c9b81d341b")
+        );
+    }
+}
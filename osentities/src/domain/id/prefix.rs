@@ -35,6 +35,7 @@ pub enum IdPrefix {
     UnitTest,
     EarlyAccess,
     Task,
+    TaskRun,
 }
 
 impl Display for IdPrefix {
@@ -71,6 +72,7 @@ impl Display for IdPrefix {
             IdPrefix::UnitTest => write!(f, "ut"),
             IdPrefix::EarlyAccess => write!(f, "ea"),
             IdPrefix::Task => write!(f, "task"),
+            IdPrefix::TaskRun => write!(f, "task_run"),
         }
     }
 }
@@ -111,6 +113,7 @@ impl TryFrom<&str> for IdPrefix {
             "ut" => Ok(IdPrefix::UnitTest),
             "ea" => Ok(IdPrefix::EarlyAccess),
             "task" => Ok(IdPrefix::Task),
+            "task_run" => Ok(IdPrefix::TaskRun),
             _ => Err(InternalError::invalid_argument(
                 &format!("Invalid ID prefix: {}", s),
                 None,
@@ -153,6 +156,7 @@ impl From<IdPrefix> for String {
             IdPrefix::UnitTest => "ut".to_string(),
             IdPrefix::EarlyAccess => "ea".to_string(),
             IdPrefix::Task => "task".to_string(),
+            IdPrefix::TaskRun => "task_run".to_string(),
         }
     }
 }
@@ -225,6 +229,7 @@ mod test {
         assert_eq!(IdPrefix::try_from("ut").unwrap(), IdPrefix::UnitTest);
         assert_eq!(IdPrefix::try_from("ea").unwrap(), IdPrefix::EarlyAccess);
         assert_eq!(IdPrefix::try_from("task").unwrap(), IdPrefix::Task);
+        assert_eq!(IdPrefix::try_from("task_run").unwrap(), IdPrefix::TaskRun);
     }
 
     #[test]
@@ -269,5 +274,6 @@ mod test {
         assert_eq!(format!("{}", IdPrefix::UnitTest), "ut");
         assert_eq!(format!("{}", IdPrefix::EarlyAccess), "ea");
         assert_eq!(format!("{}", IdPrefix::Task), "task");
+        assert_eq!(format!("{}", IdPrefix::TaskRun), "task_run");
     }
 }
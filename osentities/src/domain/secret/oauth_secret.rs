@@ -56,7 +56,10 @@ impl OAuthSecret {
             client_secret: client_secret.unwrap_or(self.client_secret.clone()),
             access_token: oauth_response.access_token,
             token_type: oauth_response.token_type,
-            refresh_token: oauth_response.refresh_token,
+            // Platforms that do not rotate refresh tokens leave them out of refresh responses
+            refresh_token: oauth_response
+                .refresh_token
+                .or_else(|| self.refresh_token.clone()),
             expires_in: oauth_response.expires_in,
             metadata,
             request_payload: self.request_payload.clone(),
@@ -80,3 +83,47 @@ pub struct OAuthLegacySecret {
     #[serde(rename = "ACCESS_TOKEN_SECRET")]
     pub access_token_secret: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn secret() -> OAuthSecret {
+        OAuthSecret {
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            access_token: "old".to_string(),
+            token_type: Some("Bearer".to_string()),
+            refresh_token: Some("refresh".to_string()),
+            expires_in: 3600,
+            metadata: Value::Null,
+            request_payload: None,
+        }
+    }
+
+    fn response(refresh_token: Option<&str>) -> OAuthResponse {
+        OAuthResponse {
+            access_token: "new".to_string(),
+            expires_in: 1800,
+            refresh_token: refresh_token.map(ToString::to_string),
+            token_type: None,
+        }
+    }
+
+    #[test]
+    fn test_from_refresh_keeps_refresh_token() {
+        let refreshed = secret().from_refresh(response(None), None, None, json!({}));
+
+        assert_eq!(refreshed.access_token, "new");
+        assert_eq!(refreshed.expires_in, 1800);
+        assert_eq!(refreshed.refresh_token.as_deref(), Some("refresh"));
+    }
+
+    #[test]
+    fn test_from_refresh_rotates_refresh_token() {
+        let refreshed = secret().from_refresh(response(Some("rotated")), None, None, json!({}));
+
+        assert_eq!(refreshed.refresh_token.as_deref(), Some("rotated"));
+    }
+}
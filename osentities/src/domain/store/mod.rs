@@ -79,6 +79,8 @@ generate_stores!(
     "settings",
     Tasks,
     "tasks",
+    TaskRuns,
+    "task-runs",
     EmbedTokens,
     "embed-tokens",
     Sessions,
@@ -1,19 +1,69 @@
-use js_sandbox_ios::Script;
+use deno_core::{serde_v8, v8, JsRuntime, RuntimeOptions};
+use lru::LruCache;
 use osentities::{ApplicationError, PicaError};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::cell::RefCell;
-use std::fmt::Debug;
+use serde_json::{Number, Value};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::Debug,
+    num::NonZeroUsize,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const SCRIPT_NAME: &str = "mapping.js";
+/// Heap granted past the limit to a script terminated for going over it, so it can unwind
+const TERMINATION_HEAP_HEADROOM: usize = 32 * 1024 * 1024;
+
+static WATCHDOG: OnceLock<Watchdog> = OnceLock::new();
+static NEXT_SANDBOX_ID: AtomicU64 = AtomicU64::new(0);
 
 thread_local! {
-    static JS_RUNTIME: RefCell<Script> = RefCell::new(Script::new());
+    static JS_RUNTIME: RefCell<Option<Sandbox>> = const { RefCell::new(None) };
+}
+
+/// Limits of the mapping scripts run by [`JSRuntimeImpl`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JSRuntimeConfig {
+    /// How long a script may run when it is loaded or called
+    pub timeout: Duration,
+    /// Heap of the isolate of each thread, the isolate is recreated once a script goes over it
+    pub max_heap_bytes: usize,
+    /// Compiled scripts kept by each thread, the least recently used are evicted first
+    pub max_scripts: usize,
 }
 
-#[derive(Default, Clone, Copy)]
-pub struct JSRuntimeImpl;
+impl Default for JSRuntimeConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            max_heap_bytes: 128 * 1024 * 1024,
+            max_scripts: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JSRuntimeImpl {
+    config: JSRuntimeConfig,
+}
 
 impl JSRuntimeImpl {
-    /// Adds a JavaScript script to the runtime environment under a specific namespace.
+    pub fn new(config: JSRuntimeConfig) -> Self {
+        Self { config }
+    }
+
+    /// Compiles a JavaScript function in the runtime environment under a specific namespace.
+    /// Namespaces are expected to change with the code, compiled functions are reused until
+    /// they are evicted. Scripts are compiled and run on the blocking threads of tokio, never
+    /// on its workers.
     ///
     /// # Parameters
     ///
@@ -24,38 +74,67 @@ impl JSRuntimeImpl {
     /// # Returns
     ///
     /// A `Result` containing:
-    /// - `Self`: On success, the instance of the struct.
+    /// - `JSScript`: On success, the function to run.
     /// - `PicaError`: On failure, encapsulates the error details.
     ///
     /// # Errors
     ///
-    /// Returns an error if adding the script to the runtime fails, logging the error
-    /// and returning a `bad_request` application error.
-    pub fn create(&self, fn_name: &str, namespace: &str, code: &str) -> Result<Self, PicaError> {
-        JS_RUNTIME
-            .with_borrow_mut(|script| script.add_script(namespace, fn_name, code))
-            .map_err(|e| {
-                tracing::error!(
-                    "Failed to create javascript function in namespace {}. Error: {}",
-                    namespace,
-                    e
-                );
+    /// Returns a `script_timeout` application error if loading the script takes too long, and
+    /// a `bad_request` application error for any other failure.
+    pub async fn create(
+        &self,
+        fn_name: &str,
+        namespace: &str,
+        code: &str,
+    ) -> Result<JSScript, PicaError> {
+        let script = JSScript {
+            config: self.config,
+            fn_name: fn_name.to_string(),
+            namespace: namespace.to_string(),
+            code: code.to_string(),
+        };
 
-                ApplicationError::bad_request(
-                    &format!("Failed while creating request schema mapping script: {e}"),
-                    None,
-                )
-            })?;
+        let compiled = script.clone();
+        blocking(move || {
+            with_sandbox(compiled.config, |sandbox| {
+                sandbox.compile(&compiled).map(|_| ())
+            })
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                "Failed to create javascript function in namespace {}. Error: {}",
+                namespace,
+                e
+            );
 
-        Ok(*self)
+            e.into_error(
+                &script,
+                "Failed while creating request schema mapping script",
+            )
+        })?;
+
+        Ok(script)
     }
+}
+
+/// A function created by [`JSRuntimeImpl::create`]. Each thread keeps its own compiled copy,
+/// which is compiled again when the thread running it does not have it.
+#[derive(Debug, Clone)]
+pub struct JSScript {
+    config: JSRuntimeConfig,
+    fn_name: String,
+    namespace: String,
+    code: String,
+}
 
-    /// Executes a JavaScript function in the runtime associated with a specific namespace,
-    /// passing serialized input data and deserializing the output.
+impl JSScript {
+    /// Executes the JavaScript function, passing serialized input data and deserializing the
+    /// output.
     ///
     /// # Parameters
     ///
-    /// - `body`: The input data to be serialized and passed to the JavaScript function.
+    /// - `payload`: The input data to be serialized and passed to the JavaScript function.
     ///
     /// # Type Parameters
     ///
@@ -70,9 +149,10 @@ impl JSRuntimeImpl {
     ///
     /// # Errors
     ///
-    /// Returns an error if serialization of the input data fails or if the JavaScript
-    /// function fails to execute. Logs the error and returns a `bad_request` application error.
-    pub async fn run<P, R>(&self, payload: &P, namespace: &str) -> Result<R, PicaError>
+    /// Returns a `script_timeout` application error if the function runs for longer than the
+    /// timeout, and a `bad_request` application error if serialization fails or the function
+    /// throws or goes over the heap limit.
+    pub async fn run<P, R>(&self, payload: &P) -> Result<R, PicaError>
     where
         P: Serialize + Debug,
         R: DeserializeOwned + Debug,
@@ -86,19 +166,538 @@ impl JSRuntimeImpl {
             )
         })?;
 
-        let payload = JS_RUNTIME
-            .with_borrow_mut(|script| script.call_namespace(namespace, payload))
-            .map_err(|e| {
-                tracing::error!("Error running javascript function: {}", e);
+        let script = self.clone();
+        blocking(move || {
+            with_sandbox(script.config, |sandbox| {
+                let function = sandbox.compile(&script)?;
+                sandbox.call(&function, payload)
+            })
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Error running javascript function: {}", e);
 
+            e.into_error(self, "Failed while running request schema mapping script")
+        })
+        .and_then(|value| {
+            serde_json::from_value(sanitize_numbers(value)).map_err(|e| {
                 ApplicationError::bad_request(
                     &format!("Failed while running request schema mapping script: {e}"),
                     None,
                 )
-            });
+            })
+        })
+    }
+}
+
+#[derive(Debug)]
+enum ScriptError {
+    Timeout,
+    OutOfMemory,
+    Failed(String),
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptError::Timeout => write!(f, "Script timed out"),
+            ScriptError::OutOfMemory => write!(f, "Script exceeded the heap limit"),
+            ScriptError::Failed(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl ScriptError {
+    fn into_error(self, script: &JSScript, context: &str) -> PicaError {
+        match self {
+            ScriptError::Timeout => ApplicationError::script_timeout(
+                &format!(
+                    "{context}: {} did not complete within {}ms",
+                    script.fn_name,
+                    script.config.timeout.as_millis()
+                ),
+                None,
+            ),
+            ScriptError::OutOfMemory => ApplicationError::bad_request(
+                &format!(
+                    "{context}: {} exceeded the heap limit of {} bytes",
+                    script.fn_name, script.config.max_heap_bytes
+                ),
+                None,
+            ),
+            ScriptError::Failed(message) => {
+                ApplicationError::bad_request(&format!("{context}: {message}"), None)
+            }
+        }
+    }
+}
+
+/// Runs `f` on a blocking thread, so scripts don't hold up the tasks of the tokio workers
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, ScriptError> + Send + 'static,
+) -> Result<T, ScriptError> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| Err(ScriptError::Failed(format!("Script panicked: {e}"))))
+}
+
+/// Runs `f` with the sandbox of the current thread, recreating it when it was configured
+/// differently or the last script ran out of memory
+fn with_sandbox<T>(
+    config: JSRuntimeConfig,
+    f: impl FnOnce(&mut Sandbox) -> Result<T, ScriptError>,
+) -> Result<T, ScriptError> {
+    JS_RUNTIME.with_borrow_mut(|current| {
+        let mut sandbox = match current.take() {
+            Some(sandbox) if sandbox.config == config => sandbox,
+            _ => Sandbox::new(config),
+        };
+
+        let result = f(&mut sandbox);
+
+        if !sandbox.out_of_memory.get() {
+            *current = Some(sandbox);
+        }
+
+        result
+    })
+}
+
+/// Script being run by a sandbox
+struct Watch {
+    deadline: Instant,
+    isolate: v8::IsolateHandle,
+    terminated: bool,
+}
+
+/// Terminates the scripts running past their deadline. A single thread watches the scripts of
+/// every sandbox, it is started by the first script and runs for the lifetime of the process.
+struct Watchdog {
+    watches: Mutex<HashMap<u64, Watch>>,
+    changed: Condvar,
+}
+
+impl Watchdog {
+    fn get() -> &'static Self {
+        WATCHDOG.get_or_init(|| {
+            // The thread waits for the initialization to complete before it gets the watchdog
+            thread::Builder::new()
+                .name("js-runtime-watchdog".to_string())
+                .spawn(|| Self::get().watch())
+                .expect("Failed to start script watchdog");
+
+            Self {
+                watches: Mutex::new(HashMap::new()),
+                changed: Condvar::new(),
+            }
+        })
+    }
+
+    fn watch(&self) {
+        let mut watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+
+        loop {
+            let now = Instant::now();
+            for watch in watches.values_mut() {
+                if !watch.terminated && watch.deadline <= now {
+                    watch.isolate.terminate_execution();
+                    watch.terminated = true;
+                }
+            }
+
+            let next = watches
+                .values()
+                .filter(|watch| !watch.terminated)
+                .map(|watch| watch.deadline)
+                .min();
+
+            watches = match next {
+                Some(deadline) => {
+                    self.changed
+                        .wait_timeout(watches, deadline.saturating_duration_since(now))
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(watches)
+                    .unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+
+    fn start(&self, sandbox: u64, isolate: v8::IsolateHandle, deadline: Instant) {
+        let mut watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+        watches.insert(
+            sandbox,
+            Watch {
+                deadline,
+                isolate,
+                terminated: false,
+            },
+        );
+        self.changed.notify_one();
+    }
+
+    /// Whether the script was terminated for running past its deadline. Once it returns the
+    /// script can no longer be terminated.
+    fn finish(&self, sandbox: u64) -> bool {
+        let mut watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+        watches
+            .remove(&sandbox)
+            .is_some_and(|watch| watch.terminated)
+    }
+}
+
+/// Isolate of a thread with its compiled scripts
+struct Sandbox {
+    id: u64,
+    scripts: LruCache<String, v8::Global<v8::Function>>,
+    isolate: v8::IsolateHandle,
+    out_of_memory: Rc<Cell<bool>>,
+    runtime: JsRuntime,
+    config: JSRuntimeConfig,
+}
 
-        tokio::task::yield_now().await;
+impl Sandbox {
+    fn new(config: JSRuntimeConfig) -> Self {
+        let mut runtime = JsRuntime::new(RuntimeOptions {
+            create_params: Some(v8::CreateParams::default().heap_limits(0, config.max_heap_bytes)),
+            ..Default::default()
+        });
+
+        let isolate = runtime.v8_isolate().thread_safe_handle();
+        let out_of_memory = Rc::new(Cell::new(false));
+
+        // The script is terminated at the limit, which is only raised by a fixed headroom so the
+        // termination can unwind instead of aborting the process
+        runtime.add_near_heap_limit_callback({
+            let isolate = isolate.clone();
+            let out_of_memory = out_of_memory.clone();
+            move |current, initial| {
+                out_of_memory.set(true);
+                isolate.terminate_execution();
+                current.max(initial + TERMINATION_HEAP_HEADROOM)
+            }
+        });
+
+        let scripts =
+            LruCache::new(NonZeroUsize::new(config.max_scripts).unwrap_or(NonZeroUsize::MIN));
+
+        Self {
+            id: NEXT_SANDBOX_ID.fetch_add(1, Ordering::Relaxed),
+            scripts,
+            isolate,
+            out_of_memory,
+            runtime,
+            config,
+        }
+    }
+
+    fn compile(&mut self, script: &JSScript) -> Result<v8::Global<v8::Function>, ScriptError> {
+        if let Some(function) = self.scripts.get(&script.namespace) {
+            return Ok(function.clone());
+        }
+
+        let source = format!(
+            "(function() {{\n{}\nreturn {};\n}})()",
+            script.code, script.fn_name
+        );
+
+        let value = self
+            .guarded(|runtime| runtime.execute_script(SCRIPT_NAME, source))?
+            .map_err(|e| ScriptError::Failed(e.to_string()))?;
+
+        let scope = &mut self.runtime.handle_scope();
+        let value = v8::Local::new(scope, value);
+        let function = v8::Local::<v8::Function>::try_from(value)
+            .map_err(|_| ScriptError::Failed(format!("{} is not a function", script.fn_name)))?;
+        let function = v8::Global::new(scope, function);
+
+        self.scripts.put(script.namespace.clone(), function.clone());
+
+        Ok(function)
+    }
+
+    fn call(
+        &mut self,
+        function: &v8::Global<v8::Function>,
+        payload: Value,
+    ) -> Result<Value, ScriptError> {
+        self.guarded(|runtime| {
+            let scope = &mut runtime.handle_scope();
+            let scope = &mut v8::TryCatch::new(scope);
+
+            let input = serde_v8::to_v8(scope, payload)
+                .map_err(|e| ScriptError::Failed(format!("Could not serialize payload: {e}")))?;
+            let function = v8::Local::new(scope, function);
+            let receiver = v8::undefined(scope).into();
+
+            match function.call(scope, receiver, &[input]) {
+                Some(output) => serde_v8::from_v8::<Value>(scope, output)
+                    .map_err(|e| ScriptError::Failed(format!("Could not deserialize output: {e}"))),
+                None => Err(ScriptError::Failed(
+                    scope
+                        .exception()
+                        .map(|e| e.to_rust_string_lossy(scope))
+                        .unwrap_or_else(|| "Failed to call function".to_string()),
+                )),
+            }
+        })?
+    }
+
+    /// Runs `f` under the watchdog, surfacing terminations as timeouts or heap overflows
+    fn guarded<T>(&mut self, f: impl FnOnce(&mut JsRuntime) -> T) -> Result<T, ScriptError> {
+        let watchdog = Watchdog::get();
+        watchdog.start(
+            self.id,
+            self.isolate.clone(),
+            Instant::now() + self.config.timeout,
+        );
+        let result = f(&mut self.runtime);
+        let timed_out = watchdog.finish(self.id);
+
+        let isolate = self.runtime.v8_isolate();
+        if timed_out || isolate.is_execution_terminating() {
+            isolate.cancel_terminate_execution();
+        }
+
+        if self.out_of_memory.get() {
+            Err(ScriptError::OutOfMemory)
+        } else if timed_out {
+            Err(ScriptError::Timeout)
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+/// Numbers returned by scripts are doubles, integral ones are turned back into integers
+fn sanitize_numbers(value: Value) -> Value {
+    match value {
+        Value::Number(number) => match number.as_f64() {
+            Some(f)
+                if number.is_f64()
+                    && f.fract() == 0.0
+                    && f >= i64::MIN as f64
+                    && f <= i64::MAX as f64 =>
+            {
+                Value::Number(Number::from(f as i64))
+            }
+            _ => Value::Number(number),
+        },
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, sanitize_numbers(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(sanitize_numbers).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unified::generate_script_namespace;
+    use osentities::ErrorMeta;
+    use serde_json::json;
+
+    fn runtime(timeout: Duration, max_heap_bytes: usize, max_scripts: usize) -> JSRuntimeImpl {
+        JSRuntimeImpl::new(JSRuntimeConfig {
+            timeout,
+            max_heap_bytes,
+            max_scripts,
+        })
+    }
+
+    fn script(config: JSRuntimeConfig, fn_name: &str, namespace: &str, code: &str) -> JSScript {
+        JSScript {
+            config,
+            fn_name: fn_name.to_string(),
+            namespace: namespace.to_string(),
+            code: code.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_script_timeout() {
+        let runtime = runtime(Duration::from_millis(50), 128 * 1024 * 1024, 16);
+
+        let script = runtime
+            .create("spin", "spin", "function spin() { while (true) {} }")
+            .await
+            .expect("Failed to create script");
+
+        let error = script
+            .run::<_, Value>(&json!({}))
+            .await
+            .expect_err("Script should time out");
+        assert_eq!(error.key().to_string(), "err::application::script_timeout");
+
+        // The isolate keeps running other scripts once the looping one was terminated
+        let script = runtime
+            .create("echo", "echo", "function echo(input) { return input; }")
+            .await
+            .expect("Failed to create script");
+        let output: Value = script
+            .run(&json!({ "id": 1 }))
+            .await
+            .expect("Failed to run script");
+        assert_eq!(output, json!({ "id": 1 }));
+    }
+
+    #[tokio::test]
+    async fn test_script_out_of_memory() {
+        let runtime = runtime(Duration::from_secs(30), 16 * 1024 * 1024, 16);
+
+        let script = runtime
+            .create(
+                "grow",
+                "grow",
+                "function grow() { const chunks = []; while (true) { chunks.push(new Array(1024 * 1024).fill(1)); } }",
+            )
+            .await
+            .expect("Failed to create script");
+
+        let error = script
+            .run::<_, Value>(&json!({}))
+            .await
+            .expect_err("Script should go over the heap limit");
+        assert_eq!(error.key().to_string(), "err::application::bad_request");
+        assert!(error.to_string().contains("heap limit"));
+
+        // The sandbox that ran out of memory is dropped and recreated by the next script
+        let result = with_sandbox(runtime.config, |sandbox| {
+            let function = sandbox.compile(&script)?;
+            sandbox.call(&function, json!({}))
+        });
+        assert!(matches!(result, Err(ScriptError::OutOfMemory)));
+        assert!(JS_RUNTIME.with_borrow(Option::is_none));
+
+        let script = runtime
+            .create("echo", "echo", "function echo(input) { return input; }")
+            .await
+            .expect("Failed to create script");
+        let output: Value = script
+            .run(&json!([1, 2]))
+            .await
+            .expect("Failed to run script");
+        assert_eq!(output, json!([1, 2]));
+    }
+
+    #[test]
+    fn test_scripts_evicted_past_max_scripts() {
+        let config = JSRuntimeConfig {
+            max_scripts: 2,
+            ..Default::default()
+        };
+        let code = "function name(input) { return input.name; }";
+        let mut sandbox = Sandbox::new(config);
+
+        let first = script(config, "name", "first", code);
+        for namespace in ["first", "second", "third"] {
+            sandbox
+                .compile(&script(config, "name", namespace, code))
+                .expect("Failed to compile script");
+        }
+
+        let compiled = |sandbox: &Sandbox| {
+            let mut namespaces = sandbox
+                .scripts
+                .iter()
+                .map(|(namespace, _)| namespace.clone())
+                .collect::<Vec<_>>();
+            namespaces.sort();
+            namespaces
+        };
+        assert_eq!(compiled(&sandbox), vec!["second", "third"]);
+
+        // Evicted scripts are compiled again when they run
+        let function = sandbox
+            .compile(&first)
+            .expect("Failed to compile evicted script");
+        let output = sandbox
+            .call(&function, json!({ "name": "first" }))
+            .expect("Failed to run evicted script");
+        assert_eq!(output, json!("first"));
+        assert_eq!(compiled(&sandbox), vec!["first", "third"]);
+    }
+
+    #[tokio::test]
+    async fn test_scripts_do_not_block_the_runtime() {
+        let runtime = runtime(Duration::from_secs(5), 128 * 1024 * 1024, 16);
+
+        let script = runtime
+            .create(
+                "busy",
+                "busy",
+                "function busy() { const end = Date.now() + 500; while (Date.now() < end) {} return 1; }",
+            )
+            .await
+            .expect("Failed to create script");
+
+        // The single thread of the test runtime keeps running tasks while the script runs
+        let busy = tokio::spawn(async move { script.run::<_, i64>(&json!({})).await });
+        tokio::time::timeout(
+            Duration::from_millis(250),
+            tokio::time::sleep(Duration::from_millis(10)),
+        )
+        .await
+        .expect("The runtime was blocked by the script");
+
+        assert_eq!(busy.await.expect("Failed to join script"), Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_updated_scripts_compiled_again() {
+        let runtime = runtime(Duration::from_secs(5), 128 * 1024 * 1024, 16);
+        let key = "conn_mod_def::AAAAAAAAAAA::AAAAAAAAAAAAAAAAAAAAAA";
+
+        let namespace = generate_script_namespace(16, key, 1);
+        let script = runtime
+            .create("version", &namespace, "function version() { return 1; }")
+            .await
+            .expect("Failed to create script");
+        let output: i64 = script.run(&json!({})).await.expect("Failed to run script");
+        assert_eq!(output, 1);
+
+        // Scripts of a namespace are reused until they are evicted
+        let script = runtime
+            .create("version", &namespace, "function version() { return 2; }")
+            .await
+            .expect("Failed to create script");
+        let output: i64 = script.run(&json!({})).await.expect("Failed to run script");
+        assert_eq!(output, 1);
+
+        // Updating the definition changes the namespace, so the new code is compiled
+        let namespace = generate_script_namespace(16, key, 2);
+        let script = runtime
+            .create("version", &namespace, "function version() { return 2; }")
+            .await
+            .expect("Failed to create script");
+        let output: i64 = script.run(&json!({})).await.expect("Failed to run script");
+        assert_eq!(output, 2);
+    }
 
-        payload
+    #[test]
+    fn test_sanitize_numbers() {
+        assert_eq!(
+            sanitize_numbers(json!({
+                "count": 2.0,
+                "price": 9.99,
+                "items": [1.0, -3.0, 0.5],
+                "id": 42,
+                "name": "name"
+            })),
+            json!({
+                "count": 2,
+                "price": 9.99,
+                "items": [1, -3, 0.5],
+                "id": 42,
+                "name": "name"
+            })
+        );
+        assert!(sanitize_numbers(json!(2.0)).is_i64());
     }
 }
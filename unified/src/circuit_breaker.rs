@@ -0,0 +1,369 @@
+use osentities::{ApplicationError, PicaError};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Circuits are only swept once there are this many of them
+const SWEEP_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open a circuit, 0 disables the breaker
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects calls before letting a probe through
+    pub open_duration: Duration,
+}
+
+/// Calls are grouped by platform and by the host they are sent to, so that connections whose
+/// base URL is templated with their own host (e.g. `https://{{shop}}.myshopify.com`) don't share
+/// a circuit
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CircuitKey {
+    pub platform: String,
+    /// Base URL declared by the model definitions, before it is rendered
+    pub base_url: String,
+    /// Hash of the rendered host, so that the values of connections don't end up in the keys
+    pub host: String,
+}
+
+impl CircuitKey {
+    pub fn new(platform: &str, base_url: &str, rendered_base_url: &str) -> Self {
+        let mut hasher = DefaultHasher::new();
+        match Url::parse(rendered_base_url) {
+            Ok(url) => (url.host_str(), url.port_or_known_default()).hash(&mut hasher),
+            Err(_) => rendered_base_url.hash(&mut hasher),
+        }
+
+        Self {
+            platform: platform.to_string(),
+            base_url: base_url.to_string(),
+            host: format!("{:016x}", hasher.finish()),
+        }
+    }
+}
+
+/// Circuits that are closed without failures are not kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Circuit {
+    Closed {
+        failures: u32,
+        last_failure: Instant,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe was let through, other calls are rejected until it completes or times out
+    HalfOpen {
+        probe_until: Instant,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitStatus {
+    pub platform: String,
+    pub base_url: String,
+    pub host: String,
+    pub state: CircuitState,
+    pub failures: u32,
+    /// Milliseconds until the next probe is let through
+    pub retry_in_ms: Option<u64>,
+}
+
+/// Stops calling platforms that keep failing so callers don't wait for their timeouts
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    circuits: Mutex<HashMap<CircuitKey, Circuit>>,
+    config: CircuitBreakerConfig,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            circuits: Mutex::new(HashMap::new()),
+            config,
+        }
+    }
+
+    /// Fails fast while the circuit is open, letting a single probe through once it was open
+    /// for long enough
+    pub fn check(&self, key: &CircuitKey) -> Result<(), PicaError> {
+        self.check_at(key, Instant::now())
+    }
+
+    /// Records the outcome of a call let through by [`CircuitBreaker::check`]
+    pub fn record(&self, key: &CircuitKey, success: bool) {
+        self.record_at(key, success, Instant::now())
+    }
+
+    pub fn states(&self) -> Vec<CircuitStatus> {
+        let now = Instant::now();
+        let circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut states = circuits
+            .iter()
+            .map(|(key, circuit)| {
+                let (state, failures, until) = match *circuit {
+                    Circuit::Closed { failures, .. } => (CircuitState::Closed, failures, None),
+                    Circuit::Open { until } => (
+                        CircuitState::Open,
+                        self.config.failure_threshold,
+                        Some(until),
+                    ),
+                    Circuit::HalfOpen { probe_until } => (
+                        CircuitState::HalfOpen,
+                        self.config.failure_threshold,
+                        Some(probe_until),
+                    ),
+                };
+
+                CircuitStatus {
+                    platform: key.platform.clone(),
+                    base_url: key.base_url.clone(),
+                    host: key.host.clone(),
+                    state,
+                    failures,
+                    retry_in_ms: until
+                        .map(|until| until.saturating_duration_since(now).as_millis() as u64),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        states.sort_by(|a, b| {
+            (&a.platform, &a.base_url, &a.host).cmp(&(&b.platform, &b.base_url, &b.host))
+        });
+        states
+    }
+
+    fn check_at(&self, key: &CircuitKey, now: Instant) -> Result<(), PicaError> {
+        if self.config.failure_threshold == 0 {
+            return Ok(());
+        }
+
+        let mut circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(circuit) = circuits.get_mut(key) else {
+            return Ok(());
+        };
+
+        match *circuit {
+            Circuit::Closed { .. } => Ok(()),
+            Circuit::Open { until } | Circuit::HalfOpen { probe_until: until } if now >= until => {
+                *circuit = Circuit::HalfOpen {
+                    probe_until: now + self.config.open_duration,
+                };
+                Ok(())
+            }
+            Circuit::Open { until } | Circuit::HalfOpen { probe_until: until } => {
+                Err(ApplicationError::circuit_open(
+                    &format!(
+                        "Calls to {} at {} are suspended after repeated failures, retry in {}s",
+                        key.platform,
+                        key.base_url,
+                        until.saturating_duration_since(now).as_secs_f64().ceil()
+                    ),
+                    None,
+                ))
+            }
+        }
+    }
+
+    fn record_at(&self, key: &CircuitKey, success: bool, now: Instant) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+
+        let mut circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if success {
+            circuits.remove(key);
+            return;
+        }
+
+        if circuits.len() > SWEEP_THRESHOLD {
+            circuits.retain(|_, circuit| !self.is_stale(circuit, now));
+        }
+
+        let circuit = circuits.entry(key.clone()).or_insert(Circuit::Closed {
+            failures: 0,
+            last_failure: now,
+        });
+
+        let open = Circuit::Open {
+            until: now + self.config.open_duration,
+        };
+
+        *circuit = match *circuit {
+            Circuit::Closed { failures, .. } if failures + 1 < self.config.failure_threshold => {
+                Circuit::Closed {
+                    failures: failures + 1,
+                    last_failure: now,
+                }
+            }
+            Circuit::Closed { .. } | Circuit::HalfOpen { .. } => {
+                tracing::warn!(
+                    "Opening circuit of {} at {} for {}s",
+                    key.platform,
+                    key.base_url,
+                    self.config.open_duration.as_secs()
+                );
+                open
+            }
+            // Calls started before the circuit opened don't extend it
+            Circuit::Open { until } => Circuit::Open { until },
+        };
+    }
+
+    /// Circuits that saw no failure or probe for a whole open duration are dropped, the next
+    /// call to their host is let through as if they were closed
+    fn is_stale(&self, circuit: &Circuit, now: Instant) -> bool {
+        let since = match *circuit {
+            Circuit::Closed { last_failure, .. } => last_failure,
+            Circuit::Open { until } | Circuit::HalfOpen { probe_until: until } => until,
+        };
+
+        now.saturating_duration_since(since) >= self.config.open_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osentities::ErrorMeta;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_duration: Duration::from_secs(30),
+        })
+    }
+
+    fn key() -> CircuitKey {
+        CircuitKey::new(
+            "stripe",
+            "https://api.stripe.com/v1",
+            "https://api.stripe.com/v1",
+        )
+    }
+
+    #[test]
+    fn test_circuit_opens_after_consecutive_failures() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record_at(&key(), false, now);
+        breaker.record_at(&key(), true, now);
+        breaker.record_at(&key(), false, now);
+        assert!(breaker.check_at(&key(), now).is_ok());
+
+        breaker.record_at(&key(), false, now);
+        let error = breaker
+            .check_at(&key(), now + Duration::from_secs(10))
+            .expect_err("Circuit should be open");
+        assert_eq!(error.key().to_string(), "err::application::circuit_open");
+
+        let states = breaker.states();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_circuit_recovers_through_probes() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record_at(&key(), false, now);
+        breaker.record_at(&key(), false, now);
+
+        // A single probe is let through once the circuit was open for long enough
+        let later = now + Duration::from_secs(30);
+        assert!(breaker.check_at(&key(), later).is_ok());
+        assert!(breaker.check_at(&key(), later).is_err());
+
+        // A failed probe opens the circuit again
+        breaker.record_at(&key(), false, later);
+        assert!(breaker
+            .check_at(&key(), later + Duration::from_secs(29))
+            .is_err());
+
+        let later = later + Duration::from_secs(30);
+        assert!(breaker.check_at(&key(), later).is_ok());
+        breaker.record_at(&key(), true, later);
+        assert!(breaker.check_at(&key(), later).is_ok());
+        assert!(breaker.check_at(&key(), later).is_ok());
+    }
+
+    #[test]
+    fn test_disabled_circuit_breaker() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 0,
+            open_duration: Duration::from_secs(30),
+        });
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            breaker.record_at(&key(), false, now);
+        }
+
+        assert!(breaker.check_at(&key(), now).is_ok());
+        assert!(breaker.states().is_empty());
+    }
+
+    #[test]
+    fn test_circuits_are_not_kept_once_recovered() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record_at(&key(), false, now);
+        assert_eq!(breaker.states().len(), 1);
+
+        breaker.record_at(&key(), true, now);
+        assert!(breaker.states().is_empty());
+
+        // Hosts that stopped being called are swept once there are many circuits
+        for host in 0..=SWEEP_THRESHOLD {
+            let key = CircuitKey::new("shopify", "", &format!("https://{host}.myshopify.com"));
+            breaker.record_at(&key, false, now);
+        }
+        breaker.record_at(&key(), false, now + Duration::from_secs(30));
+
+        let states = breaker.states();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].platform, "stripe");
+    }
+
+    #[test]
+    fn test_circuits_of_templated_hosts() {
+        let breaker = breaker();
+        let now = Instant::now();
+        let template = "https://{{shop}}.myshopify.com/admin";
+
+        let first = CircuitKey::new("shopify", template, "https://first.myshopify.com/admin");
+        let second = CircuitKey::new("shopify", template, "https://second.myshopify.com/admin");
+        assert_ne!(first, second);
+        assert_eq!(
+            first,
+            CircuitKey::new("shopify", template, "https://first.myshopify.com/admin/api")
+        );
+        assert!(!first.host.contains("first"));
+
+        breaker.record_at(&first, false, now);
+        breaker.record_at(&first, false, now);
+
+        assert!(breaker.check_at(&first, now).is_err());
+        assert!(breaker.check_at(&second, now).is_ok());
+        assert_eq!(breaker.states().len(), 1);
+    }
+}
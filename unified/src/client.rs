@@ -1,23 +1,32 @@
+use chrono::Utc;
 use derive_builder::Builder;
-use http::HeaderMap;
+use http::{HeaderMap, StatusCode};
+use http_body_util::BodyExt;
 use indexmap::IndexMap;
 use osentities::{
-    api_model_config::{ApiModelConfig, AuthMethod, OAuthLegacyHashAlgorithm},
+    api_model_config::{ApiModelConfig, AuthMethod, ContentType, OAuthLegacyHashAlgorithm},
     oauth_secret::OAuthLegacySecret,
     prelude::oauth_secret::OAuthSecret,
     AuthorizationType, InternalError, Nonce, OAuthData, PicaError, SignableRequest,
     SignatureMethod, SigningKey,
 };
-use reqwest::{Client, Response, Url};
+use reqwest::{Client, Request, Response, Url};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::error::Error;
 
+use crate::{
+    multipart,
+    throttle::{retry_after, OutboundLimiter, Throttle},
+};
+
 #[derive(Debug, Clone, Builder)]
 pub struct CallerClient<'a> {
     config: &'a ApiModelConfig,
     action: http::Method,
     client: &'a Client,
+    #[builder(default)]
+    limiter: Option<(&'a OutboundLimiter, &'a Throttle)>,
 }
 
 impl<'a> CallerClient<'a> {
@@ -26,9 +35,17 @@ impl<'a> CallerClient<'a> {
             config,
             action,
             client,
+            limiter: None,
         }
     }
 
+    /// Waits for the limits of `throttle` before every retry, the first attempt being left to
+    /// the caller
+    pub fn with_limiter(mut self, limiter: &'a OutboundLimiter, throttle: &'a Throttle) -> Self {
+        self.limiter = Some((limiter, throttle));
+        self
+    }
+
     pub async fn make_request(
         &self,
         payload: Option<Vec<u8>>,
@@ -54,6 +71,15 @@ impl<'a> CallerClient<'a> {
         merged_headers.remove(http::header::ACCEPT_ENCODING);
         merged_headers.remove(http::header::HOST);
 
+        // Forms set their own content type, with the boundary of their parts
+        let (payload, form) = match (&self.config.content, payload) {
+            (Some(ContentType::Multipart), Some(payload)) => {
+                merged_headers.remove(http::header::CONTENT_TYPE);
+                (None, Some(multipart::form(&payload)?))
+            }
+            (_, payload) => (payload, None),
+        };
+
         for (key, value) in merged_headers.iter() {
             request_builder = request_builder.header(key, value);
         }
@@ -70,6 +96,11 @@ impl<'a> CallerClient<'a> {
             request_builder = request_builder.body(payload);
         }
 
+        let is_form = form.is_some();
+        if let Some(form) = form {
+            request_builder = request_builder.multipart(form);
+        }
+
         request_builder = match &self.config.auth_method {
             AuthMethod::BearerToken { value } => request_builder.bearer_auth(value),
             AuthMethod::ApiKey { key, value } => request_builder.header(key, value),
@@ -157,32 +188,122 @@ impl<'a> CallerClient<'a> {
             AuthMethod::None => request_builder,
         };
 
-        let res = request_builder.send().await.map_err(|e| {
-            tracing::error!("Failed to send request: {}", e.source().unwrap_or(&e));
-            InternalError::io_err(
-                &format!("Failed to send request: {}", e),
-                Some("reqwest::Error"),
-            )
-        })?;
+        let mut request = request_builder
+            .build()
+            .map_err(|e| InternalError::invalid_argument(&e.to_string(), Some("request")))?;
+
+        // Forms are streamed, they are buffered so the request can be retried
+        if is_form {
+            if let Some(body) = request.body_mut().take() {
+                let body = body
+                    .collect()
+                    .await
+                    .map_err(|e| InternalError::invalid_argument(&e.to_string(), Some("request")))?
+                    .to_bytes();
+                *request.body_mut() = Some(body.into());
+            }
+        }
 
-        Ok(res)
+        self.send(request).await
+    }
+
+    /// Sends the request, retrying it as allowed by the retry policy of the model. The number
+    /// of retries is stored in the extensions of the response as [`Retries`].
+    async fn send(&self, request: Request) -> Result<Response, PicaError> {
+        let policy = self
+            .config
+            .retry_policy
+            .as_ref()
+            .filter(|policy| policy.allows(&self.action));
+
+        let mut retries = 0;
+
+        loop {
+            // Streamed bodies can't be sent again, so they are only attempted once
+            let attempt = policy
+                .filter(|policy| retries + 1 < policy.max_attempts)
+                .zip(request.try_clone());
+
+            let Some((policy, attempt)) = attempt else {
+                let res = self.client.execute(request).await.map_err(send_error)?;
+                return Ok(retried(res, retries));
+            };
+
+            let backoff = policy.backoff(retries + 1, rand::random());
+
+            let delay = match self.client.execute(attempt).await {
+                // Rate limited calls are retried by the caller holding the limiter, which first
+                // holds back the calls made to the platform
+                Ok(res)
+                    if self.limiter.is_some() && res.status() == StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    return Ok(retried(res, retries))
+                }
+                Ok(res) if policy.is_retryable(res.status()) => {
+                    match retry_after(res.headers(), Utc::now()) {
+                        // Waiting longer is left to the caller
+                        Some(delay) if delay > policy.max_backoff() => {
+                            return Ok(retried(res, retries))
+                        }
+                        Some(delay) => delay.max(backoff),
+                        None => backoff,
+                    }
+                }
+                Ok(res) => return Ok(retried(res, retries)),
+                Err(e) if e.is_connect() || e.is_timeout() || e.is_request() => {
+                    tracing::warn!("Failed to send request, retrying: {e}");
+                    backoff
+                }
+                Err(e) => return Err(send_error(e)),
+            };
+
+            retries += 1;
+            tracing::info!(
+                "Retrying request to {} in {}ms. Retry: {retries}",
+                self.config.base_url,
+                delay.as_millis()
+            );
+
+            tokio::time::sleep(delay).await;
+
+            if let Some((limiter, throttle)) = self.limiter {
+                limiter.acquire(throttle).await?;
+            }
+        }
     }
 }
 
+/// Times a request was retried before its response was received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retries(pub u32);
+
+fn retried(mut res: Response, retries: u32) -> Response {
+    res.extensions_mut().insert(Retries(retries));
+    res
+}
+
+fn send_error(e: reqwest::Error) -> PicaError {
+    tracing::error!("Failed to send request: {}", e.source().unwrap_or(&e));
+    InternalError::io_err(
+        &format!("Failed to send request: {}", e),
+        Some("reqwest::Error"),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use http::StatusCode;
     use mockito::Server;
     use osentities::{
-        api_model_config::{SamplesInput, SchemasInput},
+        api_model_config::{RetryPolicy, SamplesInput, SchemasInput},
         connection_model_definition::{
             ConnectionModelDefinition, CrudAction, PlatformInfo, TestConnection,
         },
         id::Id,
+        rate_limit::{ProviderRateLimit, RateLimitScope},
     };
     use reqwest::Client;
-    use std::str::FromStr;
+    use std::{str::FromStr, time::Duration};
 
     #[tokio::test]
     async fn test_success_make_request() {
@@ -204,6 +325,7 @@ mod tests {
             headers: None,
             query_params: None,
             content: None,
+            response_content: None,
             schemas: SchemasInput {
                 headers: None,
                 query_params: None,
@@ -218,6 +340,7 @@ mod tests {
             },
             responses: vec![],
             paths: None,
+            retry_policy: None,
         };
 
         let stripe_model_config = ConnectionModelDefinition {
@@ -241,6 +364,8 @@ mod tests {
             mapping: None,
             supported: true,
             knowledge: None,
+            rate_limits: vec![],
+            cache_ttl_secs: None,
         };
 
         let client = Client::new();
@@ -279,6 +404,7 @@ mod tests {
             },
             headers: None,
             content: None,
+            response_content: None,
             query_params: None,
             schemas: SchemasInput {
                 headers: None,
@@ -294,6 +420,7 @@ mod tests {
             },
             responses: vec![],
             paths: None,
+            retry_policy: None,
         };
 
         let stripe_model_config = ConnectionModelDefinition {
@@ -317,6 +444,8 @@ mod tests {
             mapping: None,
             supported: true,
             knowledge: None,
+            rate_limits: vec![],
+            cache_ttl_secs: None,
         };
 
         let client = Client::new();
@@ -332,4 +461,225 @@ mod tests {
         let response = res.bytes().await.unwrap();
         assert_eq!(response, "Not found".as_bytes().to_vec());
     }
+
+    #[tokio::test]
+    async fn test_retry_make_request() {
+        let mut mock_server = Server::new_async().await;
+
+        let api_model_config = ApiModelConfig {
+            base_url: mock_server.url() + "/api",
+            path: "customers".to_string(),
+            auth_method: AuthMethod::None,
+            headers: None,
+            content: None,
+            response_content: None,
+            query_params: None,
+            schemas: SchemasInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            samples: SamplesInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            responses: vec![],
+            paths: None,
+            retry_policy: Some(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff_ms: 1,
+                ..Default::default()
+            }),
+        };
+
+        let client = Client::new();
+
+        let get = mock_server
+            .mock("GET", "/api/customers")
+            .with_status(503)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let res = CallerClient::new(&api_model_config, http::Method::GET, &client)
+            .make_request(None, None, None, None)
+            .await
+            .unwrap();
+
+        get.assert_async().await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.extensions().get::<Retries>(), Some(&Retries(2)));
+
+        // Non idempotent requests are only sent once
+        let post = mock_server
+            .mock("POST", "/api/customers")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let res = CallerClient::new(&api_model_config, http::Method::POST, &client)
+            .make_request(Some(b"{}".to_vec()), None, None, None)
+            .await
+            .unwrap();
+
+        post.assert_async().await;
+        assert_eq!(res.extensions().get::<Retries>(), Some(&Retries(0)));
+    }
+
+    #[tokio::test]
+    async fn test_retry_waits_for_limiter() {
+        let mut mock_server = Server::new_async().await;
+        let client = Client::new();
+
+        let api_model_config: ApiModelConfig = serde_json::from_value(serde_json::json!({
+            "baseUrl": mock_server.url(),
+            "path": "/customers",
+            "authMethod": { "type": "None" },
+            "schemas": {},
+            "samples": {},
+            "responses": [],
+            "retryPolicy": { "maxAttempts": 3, "initialBackoffMs": 1 }
+        }))
+        .expect("Failed to deserialize api model config");
+
+        let get = mock_server
+            .mock("GET", "/customers")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let limiter = OutboundLimiter::new(Duration::ZERO);
+        let throttle = Throttle::new("conn").with_limits(
+            "definition:1",
+            &[ProviderRateLimit {
+                requests: 1,
+                period_ms: 60_000,
+                scope: RateLimitScope::Connection,
+            }],
+        );
+
+        // The first attempt is acquired by the caller
+        limiter
+            .acquire(&throttle)
+            .await
+            .expect("Failed to acquire the first attempt");
+
+        let err = CallerClient::new(&api_model_config, http::Method::GET, &client)
+            .with_limiter(&limiter, &throttle)
+            .make_request(None, None, None, None)
+            .await
+            .expect_err("Retried past the rate limit of the platform");
+
+        get.assert_async().await;
+        assert_eq!(err.status(), 429);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_left_to_limiter() {
+        let mut mock_server = Server::new_async().await;
+        let client = Client::new();
+
+        let api_model_config: ApiModelConfig = serde_json::from_value(serde_json::json!({
+            "baseUrl": mock_server.url(),
+            "path": "/customers",
+            "authMethod": { "type": "None" },
+            "schemas": {},
+            "samples": {},
+            "responses": [],
+            "retryPolicy": { "maxAttempts": 3, "initialBackoffMs": 1 }
+        }))
+        .expect("Failed to deserialize api model config");
+
+        let get = mock_server
+            .mock("GET", "/customers")
+            .with_status(429)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let limiter = OutboundLimiter::new(Duration::ZERO);
+        let throttle = Throttle::new("conn");
+
+        let res = CallerClient::new(&api_model_config, http::Method::GET, &client)
+            .with_limiter(&limiter, &throttle)
+            .make_request(None, None, None, None)
+            .await
+            .expect("Failed to send request");
+
+        get.assert_async().await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.extensions().get::<Retries>(), Some(&Retries(0)));
+    }
+
+    #[tokio::test]
+    async fn test_multipart_request() {
+        let mut mock_server = Server::new_async().await;
+        let client = Client::new();
+
+        let api_model_config: ApiModelConfig = serde_json::from_value(serde_json::json!({
+            "baseUrl": mock_server.url(),
+            "path": "/files",
+            "authMethod": { "type": "None" },
+            // Declaring the content type of forms does not keep them from being encoded
+            "headers": { "content-type": "multipart/form-data" },
+            "content": "multipart",
+            "schemas": {},
+            "samples": {},
+            "responses": []
+        }))
+        .expect("Failed to deserialize api model config");
+
+        let upload = mock_server
+            .mock("POST", "/files")
+            .match_header(
+                "content-type",
+                mockito::Matcher::Regex("^multipart/form-data; boundary=.+$".to_string()),
+            )
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::Regex(
+                    "name=\"file\"; filename=\"a.txt\"\r\nContent-Type: text/plain\r\n\r\nhello"
+                        .to_string(),
+                ),
+                mockito::Matcher::Regex("name=\"title\"\r\n\r\nNotes".to_string()),
+            ]))
+            .with_status(201)
+            .create_async()
+            .await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static("application/json"),
+        );
+
+        let res = CallerClient::new(&api_model_config, http::Method::POST, &client)
+            .make_request(
+                Some(
+                    serde_json::json!({
+                        "title": "Notes",
+                        "file": {
+                            "filename": "a.txt",
+                            "contentType": "text/plain",
+                            "encoding": "base64",
+                            "data": "aGVsbG8="
+                        }
+                    })
+                    .to_string()
+                    .into_bytes(),
+                ),
+                None,
+                Some(headers),
+                None,
+            )
+            .await
+            .unwrap();
+
+        upload.assert_async().await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
 }
@@ -0,0 +1,437 @@
+use crate::multipart::FilePart;
+use http::{header::CONTENT_TYPE, HeaderMap};
+use osentities::{api_model_config::ResponseContent, InternalError, PicaError};
+use serde_json::{Map, Value};
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+const ATTRIBUTE_PREFIX: &str = "@";
+const TEXT_KEY: &str = "#text";
+
+/// Format of a response, `configured` takes precedence over its `Content-Type`. Responses
+/// without a known content type are expected to be JSON.
+pub fn response_content(
+    configured: Option<ResponseContent>,
+    headers: &HeaderMap,
+) -> ResponseContent {
+    if let Some(content) = configured {
+        return content;
+    }
+
+    let Some(mime) = content_type(headers) else {
+        return ResponseContent::Json;
+    };
+
+    let (kind, subtype) = mime.split_once('/').unwrap_or((mime.as_str(), ""));
+
+    match (kind, subtype) {
+        (_, "json") => ResponseContent::Json,
+        (_, s) if s.ends_with("+json") => ResponseContent::Json,
+        ("application" | "text", "xml") => ResponseContent::Xml,
+        (_, s) if s.ends_with("+xml") => ResponseContent::Xml,
+        ("text" | "application", "csv") => ResponseContent::Csv,
+        ("application", "x-www-form-urlencoded") => ResponseContent::Form,
+        ("image" | "audio" | "video" | "font", _) => ResponseContent::Binary,
+        ("application", "octet-stream" | "pdf" | "zip" | "gzip") => ResponseContent::Binary,
+        _ => ResponseContent::Json,
+    }
+}
+
+/// Converts the body of a platform response to JSON so it can be mapped like any other
+pub fn decode_body(
+    content: ResponseContent,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Value, PicaError> {
+    match content {
+        ResponseContent::Json => serde_json::from_slice(body)
+            .map_err(|e| InternalError::deserialize_error(&e.to_string(), None)),
+        ResponseContent::Xml => xml_to_json(text(body)?),
+        ResponseContent::Csv => csv_to_json(text(body)?),
+        ResponseContent::Form => form_to_json(body),
+        ResponseContent::Binary => {
+            serde_json::to_value(FilePart::new(None, content_type(headers), body))
+                .map_err(|e| InternalError::serialize_error(&e.to_string(), None))
+        }
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let mime = value.split(';').next()?.trim().to_ascii_lowercase();
+
+    (!mime.is_empty()).then_some(mime)
+}
+
+fn text(body: &[u8]) -> Result<&str, PicaError> {
+    std::str::from_utf8(body)
+        .map(|text| text.trim_start_matches('\u{feff}'))
+        .map_err(|e| InternalError::deserialize_error(&format!("Body is not UTF-8: {e}"), None))
+}
+
+#[derive(Default)]
+struct Element {
+    name: String,
+    fields: Map<String, Value>,
+    text: String,
+}
+
+impl Element {
+    fn insert(&mut self, name: String, value: Value) {
+        match self.fields.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None => {
+                self.fields.insert(name, value);
+            }
+        }
+    }
+
+    fn into_value(mut self) -> Value {
+        let text = self.text.trim();
+
+        if self.fields.is_empty() {
+            return if text.is_empty() {
+                Value::Null
+            } else {
+                Value::String(text.to_string())
+            };
+        }
+
+        if !text.is_empty() {
+            self.fields
+                .insert(TEXT_KEY.to_string(), Value::String(text.to_string()));
+        }
+
+        Value::Object(self.fields)
+    }
+}
+
+fn xml_to_json(xml: &str) -> Result<Value, PicaError> {
+    let error = |message: String| InternalError::deserialize_error(&message, None);
+
+    let mut stack: Vec<Element> = vec![];
+    let mut root = Element::default();
+
+    for token in Tokenizer::from(xml) {
+        match token.map_err(|e| error(format!("Invalid XML: {e}")))? {
+            Token::ElementStart { prefix, local, .. } => stack.push(Element {
+                name: qualified_name(prefix.as_str(), local.as_str()),
+                ..Default::default()
+            }),
+            Token::Attribute {
+                prefix,
+                local,
+                value,
+                ..
+            } => {
+                if let Some(element) = stack.last_mut() {
+                    element.insert(
+                        format!(
+                            "{ATTRIBUTE_PREFIX}{}",
+                            qualified_name(prefix.as_str(), local.as_str())
+                        ),
+                        Value::String(unescape(value.as_str())),
+                    );
+                }
+            }
+            Token::ElementEnd { end, .. } => {
+                if let ElementEnd::Open = end {
+                    continue;
+                }
+
+                let element = stack
+                    .pop()
+                    .ok_or_else(|| error("Invalid XML: unexpected closing tag".to_string()))?;
+
+                if let ElementEnd::Close(prefix, local) = end {
+                    let name = qualified_name(prefix.as_str(), local.as_str());
+                    if name != element.name {
+                        return Err(error(format!(
+                            "Invalid XML: expected </{}> but found </{name}>",
+                            element.name
+                        )));
+                    }
+                }
+
+                let parent = stack.last_mut().unwrap_or(&mut root);
+                parent.insert(element.name.clone(), element.into_value());
+            }
+            Token::Text { text } => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&unescape(text.as_str()));
+                }
+            }
+            Token::Cdata { text, .. } => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(text.as_str());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(element) = stack.last() {
+        return Err(error(format!(
+            "Invalid XML: <{}> is not closed",
+            element.name
+        )));
+    }
+
+    Ok(Value::Object(root.fields))
+}
+
+fn qualified_name(prefix: &str, local: &str) -> String {
+    match prefix {
+        "" => local.to_string(),
+        prefix => format!("{prefix}:{local}"),
+    }
+}
+
+/// Replaces the predefined and numeric character references of XML
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let reference = rest.find(';').map(|end| (&rest[1..end], end));
+        let character = reference.and_then(|(name, _)| match name {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => name
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| name.strip_prefix('#').map(|dec| dec.parse::<u32>()))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        });
+
+        match (character, reference) {
+            (Some(character), Some((_, end))) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+fn csv_to_json(csv: &str) -> Result<Value, PicaError> {
+    let mut rows = csv_rows(csv)?.into_iter();
+
+    let Some(header) = rows.next() else {
+        return Ok(Value::Array(vec![]));
+    };
+
+    Ok(Value::Array(
+        rows.map(|row| {
+            let mut fields = row.into_iter();
+            Value::Object(
+                header
+                    .iter()
+                    .map(|name| {
+                        (
+                            name.clone(),
+                            fields.next().map_or(Value::Null, Value::String),
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect(),
+    ))
+}
+
+/// Splits RFC 4180 CSV into rows, skipping blank lines
+fn csv_rows(csv: &str) -> Result<Vec<Vec<String>>, PicaError> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|f| !f.is_empty()) || row.len() > 1 {
+                    rows.push(std::mem::take(&mut row));
+                } else {
+                    row.clear();
+                }
+            }
+            (c, _) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(InternalError::deserialize_error(
+            "Invalid CSV: unterminated quoted field",
+            None,
+        ));
+    }
+
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+fn form_to_json(body: &[u8]) -> Result<Value, PicaError> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_bytes(body)
+        .map_err(|e| InternalError::deserialize_error(&format!("Invalid form: {e}"), None))?;
+
+    let mut form = Element::default();
+    for (key, value) in pairs {
+        form.insert(key, Value::String(value));
+    }
+
+    Ok(Value::Object(form.fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use serde_json::json;
+
+    fn headers(content_type: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        headers
+    }
+
+    #[test]
+    fn test_response_content() {
+        let detect = |content_type| response_content(None, &headers(content_type));
+
+        assert_eq!(
+            detect("application/json; charset=utf-8"),
+            ResponseContent::Json
+        );
+        assert_eq!(detect("application/vnd.api+json"), ResponseContent::Json);
+        assert_eq!(detect("text/xml"), ResponseContent::Xml);
+        assert_eq!(detect("application/soap+xml"), ResponseContent::Xml);
+        assert_eq!(detect("text/csv"), ResponseContent::Csv);
+        assert_eq!(
+            detect("application/x-www-form-urlencoded"),
+            ResponseContent::Form
+        );
+        assert_eq!(detect("application/pdf"), ResponseContent::Binary);
+        assert_eq!(detect("image/png"), ResponseContent::Binary);
+        assert_eq!(
+            response_content(None, &HeaderMap::new()),
+            ResponseContent::Json
+        );
+        assert_eq!(
+            response_content(Some(ResponseContent::Csv), &headers("text/plain")),
+            ResponseContent::Csv
+        );
+    }
+
+    #[test]
+    fn test_xml_to_json() {
+        let xml = r#"<?xml version="1.0"?>
+            <soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
+                <contacts total="2">
+                    <contact id="1"><name>Jane &amp; John</name></contact>
+                    <contact id="2"><name><![CDATA[<Doe>]]></name><email/></contact>
+                    <note lang="en">Hello</note>
+                </contacts>
+            </soap:Envelope>"#;
+
+        let value = decode_body(ResponseContent::Xml, &HeaderMap::new(), xml.as_bytes())
+            .expect("Failed to convert xml");
+
+        assert_eq!(
+            value,
+            json!({
+                "soap:Envelope": {
+                    "@xmlns:soap": "http://schemas.xmlsoap.org/soap/envelope/",
+                    "contacts": {
+                        "@total": "2",
+                        "contact": [
+                            { "@id": "1", "name": "Jane & John" },
+                            { "@id": "2", "name": "<Doe>", "email": null }
+                        ],
+                        "note": { "@lang": "en", "#text": "Hello" }
+                    }
+                }
+            })
+        );
+
+        assert!(xml_to_json("<a><b></a>").is_err());
+        assert!(xml_to_json("<a>").is_err());
+    }
+
+    #[test]
+    fn test_csv_to_json() {
+        let csv = "\u{feff}id,name,notes\r\n1,\"Doe, Jane\",\"said \"\"hi\"\"\"\r\n\r\n2,John\n";
+
+        let value = decode_body(ResponseContent::Csv, &HeaderMap::new(), csv.as_bytes())
+            .expect("Failed to convert csv");
+
+        assert_eq!(
+            value,
+            json!([
+                { "id": "1", "name": "Doe, Jane", "notes": "said \"hi\"" },
+                { "id": "2", "name": "John", "notes": null }
+            ])
+        );
+
+        assert!(csv_to_json("id\n\"1").is_err());
+    }
+
+    #[test]
+    fn test_form_and_binary() {
+        let value = decode_body(
+            ResponseContent::Form,
+            &HeaderMap::new(),
+            b"access_token=abc&scope=read&scope=write",
+        )
+        .expect("Failed to convert form");
+        assert_eq!(
+            value,
+            json!({ "access_token": "abc", "scope": ["read", "write"] })
+        );
+
+        let value = decode_body(
+            ResponseContent::Binary,
+            &headers("application/pdf"),
+            &[0, 159, 146, 150],
+        )
+        .expect("Failed to encode binary");
+        assert_eq!(
+            value,
+            json!({
+                "contentType": "application/pdf",
+                "encoding": "base64",
+                "data": "AJ+Slg==",
+            })
+        );
+    }
+}
@@ -182,6 +182,9 @@ pub struct UnifiedMetadata {
     connection_key: String,
     #[builder(setter(strip_option), default)]
     latency: Option<i32>,
+    /// Times the request to the platform was retried
+    #[builder(setter(strip_option), default)]
+    retries: Option<u32>,
     #[builder(setter(strip_option), default)]
     hash: Option<String>,
 }
@@ -198,3 +201,9 @@ pub struct UnifiedCache {
     ttl: u64,
     key: String,
 }
+
+impl UnifiedCache {
+    pub fn new(hit: bool, ttl: u64, key: String) -> Self {
+        Self { hit, ttl, key }
+    }
+}
@@ -1,5 +1,12 @@
 pub mod algebra;
+pub mod circuit_breaker;
 pub mod client;
+pub mod content;
 pub mod domain;
 pub mod helper;
+pub mod multipart;
+pub mod pagination;
+pub mod refresh;
+pub mod response_cache;
+pub mod throttle;
 pub mod unified;
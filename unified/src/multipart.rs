@@ -0,0 +1,304 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use futures::{future, stream};
+use http::{header::CONTENT_TYPE, HeaderMap};
+use multer::Multipart;
+use osentities::{ApplicationError, InternalError, PicaError};
+use reqwest::multipart::{Form, Part};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::convert::Infallible;
+
+pub const MULTIPART_FORM_DATA: &str = "multipart/form-data";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileEncoding {
+    Base64,
+}
+
+/// A file in a JSON body. Fields of a multipart body holding one become file parts, so
+/// mappings describe uploads by returning them. Binary responses are returned in this shape.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePart {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub encoding: FileEncoding,
+    pub data: String,
+}
+
+impl FilePart {
+    pub fn new(filename: Option<String>, content_type: Option<String>, data: &[u8]) -> Self {
+        Self {
+            filename,
+            content_type,
+            encoding: FileEncoding::Base64,
+            data: STANDARD.encode(data),
+        }
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, PicaError> {
+        STANDARD.decode(&self.data).map_err(|e| {
+            ApplicationError::bad_request(&format!("Invalid base64 file data: {e}"), None)
+        })
+    }
+}
+
+pub fn is_multipart(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.trim_start()
+                .to_ascii_lowercase()
+                .starts_with(MULTIPART_FORM_DATA)
+        })
+}
+
+/// Builds the `multipart/form-data` form of a JSON object. Arrays become repeated parts,
+/// objects that are not files become JSON parts and nulls are left out.
+pub fn form(payload: &[u8]) -> Result<Form, PicaError> {
+    let fields = match serde_json::from_slice::<Value>(payload) {
+        Ok(Value::Object(fields)) => fields,
+        Ok(Value::Null) => Map::new(),
+        Ok(_) => {
+            return Err(ApplicationError::bad_request(
+                "Multipart bodies must be JSON objects",
+                None,
+            ))
+        }
+        Err(e) => {
+            return Err(ApplicationError::bad_request(
+                &format!("Multipart bodies must be JSON objects: {e}"),
+                None,
+            ))
+        }
+    };
+
+    // Names are sent as they are, as platforms rarely support their encoded form
+    fields
+        .into_iter()
+        .try_fold(Form::new().percent_encode_noop(), |form, (name, value)| {
+            add_field(form, name, value)
+        })
+}
+
+fn add_field(form: Form, name: String, value: Value) -> Result<Form, PicaError> {
+    let part = match value {
+        Value::Null => return Ok(form),
+        Value::Array(values) => {
+            return values
+                .into_iter()
+                .try_fold(form, |form, value| add_field(form, name.clone(), value))
+        }
+        Value::Object(_) => match FilePart::deserialize(&value) {
+            Ok(file) => {
+                let content_type = file
+                    .content_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream");
+
+                Part::bytes(file.bytes()?)
+                    .file_name(file.filename.clone().unwrap_or_else(|| name.clone()))
+                    .mime_str(content_type)
+                    .map_err(|e| {
+                        ApplicationError::bad_request(
+                            &format!("Invalid content type of file {name}: {e}"),
+                            None,
+                        )
+                    })?
+            }
+            Err(_) => Part::text(value.to_string())
+                .mime_str("application/json")
+                .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))?,
+        },
+        Value::String(text) => Part::text(text),
+        value => Part::text(value.to_string()),
+    };
+
+    Ok(form.part(name, part))
+}
+
+/// Decodes a `multipart/form-data` body into a JSON object. Parts with a filename or that are
+/// neither text nor JSON become [`FilePart`]s and repeated names become arrays.
+pub async fn decode(content_type: &str, body: Bytes) -> Result<Value, PicaError> {
+    let invalid = |message: &str| {
+        ApplicationError::bad_request(&format!("Invalid multipart body: {message}"), None)
+    };
+
+    let boundary = multer::parse_boundary(content_type).map_err(|e| {
+        ApplicationError::bad_request(&format!("Missing multipart boundary: {e}"), None)
+    })?;
+    let mut multipart = Multipart::new(
+        stream::once(future::ready(Ok::<_, Infallible>(body))),
+        boundary,
+    );
+    let mut fields = Map::new();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| invalid(&e.to_string()))?
+    {
+        let name = field
+            .name()
+            .map(str::to_string)
+            .ok_or_else(|| invalid("part without a name"))?;
+        let filename = field.file_name().map(str::to_string);
+        let part_type = field.content_type().cloned();
+        let data = field.bytes().await.map_err(|e| invalid(&e.to_string()))?;
+
+        let json = filename.is_none()
+            && part_type
+                .as_ref()
+                .is_some_and(|t| t.essence_str() == "application/json");
+        let text = filename.is_none()
+            && part_type
+                .as_ref()
+                .is_none_or(|t| t.essence_str() == "text/plain");
+
+        let value = match (
+            serde_json::from_slice::<Value>(&data),
+            std::str::from_utf8(&data),
+        ) {
+            (Ok(value), _) if json => value,
+            (_, Ok(data)) if text => Value::String(data.to_string()),
+            _ => serde_json::to_value(FilePart::new(
+                filename,
+                part_type.map(|t| t.to_string()),
+                &data,
+            ))
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?,
+        };
+
+        match fields.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None => {
+                fields.insert(name, value);
+            }
+        }
+    }
+
+    Ok(Value::Object(fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use serde_json::json;
+
+    async fn encode(payload: Value) -> (String, Bytes) {
+        let mut request = reqwest::Client::new()
+            .post("http://localhost/files")
+            .multipart(form(payload.to_string().as_bytes()).expect("Failed to build form"))
+            .build()
+            .expect("Failed to build request");
+
+        let content_type = request.headers()[CONTENT_TYPE]
+            .to_str()
+            .expect("Invalid content type")
+            .to_string();
+        let body = request
+            .body_mut()
+            .take()
+            .expect("Missing body")
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+
+        (content_type, body)
+    }
+
+    #[tokio::test]
+    async fn test_encode_and_decode() {
+        let payload = json!({
+            "title": "Report",
+            "size": 3,
+            "tags": ["a", "b"],
+            "metadata": { "folder": "root" },
+            "file": {
+                "filename": "report \"q1\".pdf",
+                "contentType": "application/pdf",
+                "encoding": "base64",
+                "data": STANDARD.encode([0, 159, 146, 150]),
+            },
+            "empty": null,
+        });
+
+        let (content_type, body) = encode(payload).await;
+        assert!(content_type.starts_with("multipart/form-data; boundary="));
+
+        let text = String::from_utf8_lossy(&body);
+        assert!(text.contains(
+            "Content-Disposition: form-data; name=\"file\"; filename=\"report \\\"q1\\\".pdf\"\r\nContent-Type: application/pdf\r\n"
+        ));
+        assert!(!text.contains("name=\"empty\""));
+
+        let decoded = decode(&content_type, body)
+            .await
+            .expect("Failed to decode multipart");
+        assert_eq!(
+            decoded,
+            json!({
+                "title": "Report",
+                "size": "3",
+                "tags": ["a", "b"],
+                "metadata": { "folder": "root" },
+                "file": {
+                    "filename": "report \"q1\".pdf",
+                    "contentType": "application/pdf",
+                    "encoding": "base64",
+                    "data": STANDARD.encode([0, 159, 146, 150]),
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_browser_form() {
+        let body = Bytes::from_static(b"--XyZ\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhello\r\nworld\r\n--XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a;b.txt\"\r\nContent-Type: text/plain\r\n\r\nfile\r\n--XyZ--\r\n");
+
+        let decoded = decode("multipart/form-data; boundary=\"XyZ\"", body.clone())
+            .await
+            .expect("Failed to decode multipart");
+
+        assert_eq!(
+            decoded,
+            json!({
+                "note": "hello\r\nworld",
+                "upload": {
+                    "filename": "a;b.txt",
+                    "contentType": "text/plain",
+                    "encoding": "base64",
+                    "data": STANDARD.encode("file"),
+                },
+            })
+        );
+
+        assert!(decode("multipart/form-data", body.clone()).await.is_err());
+        assert!(decode("multipart/form-data; boundary=other", body)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_form_rejects_non_objects() {
+        assert!(form(b"[1, 2]").is_err());
+        assert!(form(b"not json").is_err());
+        assert!(form(
+            &json!({ "file": { "encoding": "base64", "data": "%%" } })
+                .to_string()
+                .into_bytes()
+        )
+        .is_err());
+    }
+}
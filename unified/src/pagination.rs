@@ -0,0 +1,321 @@
+use crate::{domain::RequestCrud, unified::UnifiedResponse};
+use osentities::{constant::*, ApplicationError, PicaError};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Limits of a request fetching every page of a `GetMany`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageBudget {
+    pub max_pages: usize,
+    pub max_records: Option<usize>,
+}
+
+impl PageBudget {
+    /// Takes the fetch all parameters out of `query_params`, returning a budget only when
+    /// `fetchAll=true`. The pages requested by the caller are capped at `max_pages`.
+    pub fn from_query_params(
+        query_params: &mut HashMap<String, String>,
+        max_pages: usize,
+    ) -> Result<Option<Self>, PicaError> {
+        let fetch_all = query_params.remove(FETCH_ALL_KEY);
+        let pages = query_params.remove(MAX_PAGES_KEY);
+        let records = query_params.remove(MAX_RECORDS_KEY);
+
+        if !fetch_all.is_some_and(|v| v.eq_ignore_ascii_case("true")) {
+            return Ok(None);
+        }
+
+        let parse = |key: &str, value: Option<String>| {
+            value
+                .map(|v| {
+                    v.parse::<usize>().ok().filter(|v| *v > 0).ok_or_else(|| {
+                        ApplicationError::bad_request(
+                            &format!("{key} must be a positive integer"),
+                            None,
+                        )
+                    })
+                })
+                .transpose()
+        };
+
+        Ok(Some(Self {
+            max_pages: parse(MAX_PAGES_KEY, pages)?.map_or(max_pages, |p| p.min(max_pages)),
+            max_records: parse(MAX_RECORDS_KEY, records)?,
+        }))
+    }
+}
+
+/// Walks the cursors of the pages of a `GetMany` until the last page or the budget is reached
+#[derive(Debug, Clone)]
+pub struct Pages {
+    budget: PageBudget,
+    pages: usize,
+    records: usize,
+    cursor: Option<String>,
+    /// Cursors already followed, platforms returning one twice would loop forever
+    seen: HashSet<String>,
+    done: bool,
+}
+
+impl Pages {
+    pub fn new(budget: PageBudget) -> Self {
+        Self {
+            budget,
+            pages: 0,
+            records: 0,
+            cursor: None,
+            seen: HashSet::new(),
+            done: false,
+        }
+    }
+
+    /// Request of the next page, asking for no more records than the budget has left
+    pub fn next_page(&self, params: &RequestCrud) -> Option<RequestCrud> {
+        if self.done {
+            return None;
+        }
+
+        let mut query_params = HashMap::new();
+
+        if let Some(cursor) = &self.cursor {
+            query_params.insert(CURSOR.to_string(), cursor.clone());
+        }
+
+        if let Some(remaining) = self.remaining_records() {
+            let limit = params
+                .get_query_params()
+                .get(LIMIT_KEY)
+                .and_then(|l| l.parse::<usize>().ok())
+                .map_or(remaining, |l| l.min(remaining));
+            query_params.insert(LIMIT_KEY.to_string(), limit.to_string());
+        }
+
+        Some(params.clone().extend_query_params(query_params))
+    }
+
+    /// Accounts for a fetched page, dropping the records of platforms ignoring the limit that
+    /// go past the budget
+    pub fn advance(&mut self, page: &mut UnifiedResponse) {
+        self.pages += 1;
+
+        if !page.response.status().is_success() {
+            self.done = true;
+            return;
+        }
+
+        let remaining = self.remaining_records();
+        let body = page.response.body_mut();
+
+        if let Some(Value::Array(records)) = body.get_mut(UNIFIED_KEY) {
+            if let Some(remaining) = remaining {
+                records.truncate(remaining);
+            }
+            self.records += records.len();
+        }
+
+        self.cursor = body
+            .pointer(&format!("/{PAGINATION_KEY}/{NEXT_CURSOR}"))
+            .and_then(|cursor| match cursor {
+                Value::String(s) if !s.is_empty() => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            });
+
+        self.done = match &self.cursor {
+            None => true,
+            Some(cursor) => {
+                !self.seen.insert(cursor.clone())
+                    || self.pages >= self.budget.max_pages
+                    || self.remaining_records() == Some(0)
+            }
+        };
+    }
+
+    pub fn stop(&mut self) {
+        self.done = true;
+    }
+
+    fn remaining_records(&self) -> Option<usize> {
+        self.budget
+            .max_records
+            .map(|max| max.saturating_sub(self.records))
+    }
+}
+
+/// Merges the pages of a `GetMany` into the last one, keeping its cursor so callers can carry
+/// on from where the budget ran out. A failed page is returned as is.
+pub fn merge_pages(pages: Vec<UnifiedResponse>) -> Option<UnifiedResponse> {
+    let count = pages.len();
+    let mut records = vec![];
+    let mut passthroughs = vec![];
+    let mut last = None;
+
+    for mut page in pages {
+        if !page.response.status().is_success() {
+            return Some(page);
+        }
+
+        if let Value::Object(body) = page.response.body_mut() {
+            if let Some(Value::Array(page_records)) = body.remove(UNIFIED_KEY) {
+                records.extend(page_records);
+            }
+            if let Some(passthrough) = body.remove(PASSTHROUGH_KEY) {
+                passthroughs.push(passthrough);
+            }
+        }
+
+        last = Some(page);
+    }
+
+    let mut merged = last?;
+
+    if let Value::Object(body) = merged.response.body_mut() {
+        let mut pagination = match body.remove(PAGINATION_KEY) {
+            Some(Value::Object(pagination)) => pagination,
+            _ => Default::default(),
+        };
+        pagination.insert(PAGE_SIZE_KEY.to_string(), json!(records.len()));
+        pagination.insert(PAGES_KEY.to_string(), json!(count));
+
+        body.insert(UNIFIED_KEY.to_string(), Value::Array(records));
+        body.insert(PAGINATION_KEY.to_string(), Value::Object(pagination));
+        if !passthroughs.is_empty() {
+            body.insert(PASSTHROUGH_KEY.to_string(), Value::Array(passthroughs));
+        }
+    }
+
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{RequestCrudBuilder, UnifiedMetadataBuilder};
+    use http::{HeaderMap, Response, StatusCode};
+    use osentities::id::{prefix::IdPrefix, Id};
+
+    fn page(records: &[i64], next_cursor: Option<&str>) -> UnifiedResponse {
+        UnifiedResponse {
+            response: Response::new(json!({
+                UNIFIED_KEY: records,
+                PAGINATION_KEY: { NEXT_CURSOR: next_cursor, LIMIT_KEY: 2 },
+            })),
+            metadata: UnifiedMetadataBuilder::default()
+                .timestamp(0)
+                .platform_rate_limit_remaining(0)
+                .rate_limit_remaining(0)
+                .transaction_key(Id::now(IdPrefix::Transaction))
+                .platform("platform")
+                .platform_version("v1")
+                .common_model_version("v1")
+                .connection_key("connection")
+                .build()
+                .expect("Failed to build metadata"),
+        }
+    }
+
+    fn params(query_params: &[(&str, &str)]) -> RequestCrud {
+        RequestCrudBuilder::default()
+            .query_params(
+                query_params
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            )
+            .headers(HeaderMap::new())
+            .build()
+            .expect("Failed to build request")
+    }
+
+    #[test]
+    fn test_budget_from_query_params() {
+        let mut query_params = HashMap::from([
+            (FETCH_ALL_KEY.to_string(), "true".to_string()),
+            (MAX_PAGES_KEY.to_string(), "500".to_string()),
+            (MAX_RECORDS_KEY.to_string(), "30".to_string()),
+            (LIMIT_KEY.to_string(), "10".to_string()),
+        ]);
+        let budget =
+            PageBudget::from_query_params(&mut query_params, 100).expect("Failed to parse budget");
+
+        assert_eq!(
+            budget,
+            Some(PageBudget {
+                max_pages: 100,
+                max_records: Some(30),
+            })
+        );
+        assert_eq!(query_params.len(), 1);
+
+        let mut query_params = HashMap::from([(MAX_PAGES_KEY.to_string(), "2".to_string())]);
+        assert_eq!(
+            PageBudget::from_query_params(&mut query_params, 100).expect("Failed to parse"),
+            None
+        );
+        assert!(query_params.is_empty());
+
+        let mut query_params = HashMap::from([
+            (FETCH_ALL_KEY.to_string(), "true".to_string()),
+            (MAX_RECORDS_KEY.to_string(), "0".to_string()),
+        ]);
+        assert!(PageBudget::from_query_params(&mut query_params, 100).is_err());
+    }
+
+    #[test]
+    fn test_pages_follow_cursors_within_budget() {
+        let mut pages = Pages::new(PageBudget {
+            max_pages: 10,
+            max_records: Some(5),
+        });
+        let request = params(&[(LIMIT_KEY, "2")]);
+
+        let first = pages.next_page(&request).expect("First page");
+        assert_eq!(first.get_query_params().get(LIMIT_KEY).unwrap(), "2");
+        assert_eq!(first.get_query_params().get(CURSOR), None);
+        pages.advance(&mut page(&[1, 2], Some("a")));
+
+        pages.advance(&mut page(&[3, 4], Some("b")));
+
+        // Only one record is left in the budget
+        let third = pages.next_page(&request).expect("Third page");
+        assert_eq!(third.get_query_params().get(CURSOR).unwrap(), "b");
+        assert_eq!(third.get_query_params().get(LIMIT_KEY).unwrap(), "1");
+
+        let mut last = page(&[5, 6], Some("c"));
+        pages.advance(&mut last);
+        assert_eq!(last.response.body()[UNIFIED_KEY], json!([5]));
+        assert!(pages.next_page(&request).is_none());
+    }
+
+    #[test]
+    fn test_pages_stop_on_repeated_cursor() {
+        let mut pages = Pages::new(PageBudget {
+            max_pages: 10,
+            max_records: None,
+        });
+
+        pages.advance(&mut page(&[1], Some("a")));
+        assert!(pages.next_page(&params(&[])).is_some());
+        pages.advance(&mut page(&[1], Some("a")));
+        assert!(pages.next_page(&params(&[])).is_none());
+    }
+
+    #[test]
+    fn test_merge_pages() {
+        let merged = merge_pages(vec![page(&[1, 2], Some("a")), page(&[3], Some("b"))])
+            .expect("Merged page");
+        let body = merged.response.body();
+
+        assert_eq!(body[UNIFIED_KEY], json!([1, 2, 3]));
+        assert_eq!(body[PAGINATION_KEY][NEXT_CURSOR], json!("b"));
+        assert_eq!(body[PAGINATION_KEY][PAGE_SIZE_KEY], json!(3));
+        assert_eq!(body[PAGINATION_KEY][PAGES_KEY], json!(2));
+
+        let mut failed = page(&[], None);
+        *failed.response.status_mut() = StatusCode::BAD_GATEWAY;
+        let merged = merge_pages(vec![page(&[1], Some("a")), failed]).expect("Failed page");
+        assert_eq!(merged.response.status(), StatusCode::BAD_GATEWAY);
+
+        assert!(merge_pages(vec![]).is_none());
+    }
+}
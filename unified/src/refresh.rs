@@ -0,0 +1,171 @@
+use http::StatusCode;
+use osentities::PicaError;
+use std::future::Future;
+
+/// Sends a request with `secret` and, when the platform rejects it with a 401 and `refresh`
+/// yields a new secret, sends it once more with that one. The response of the retry is returned
+/// as is, so a token the platform keeps rejecting is only refreshed once per call.
+pub async fn send_refreshing<T, S, SFut, R, RFut>(
+    secret: T,
+    send: S,
+    refresh: R,
+) -> Result<reqwest::Response, PicaError>
+where
+    S: Fn(T) -> SFut,
+    SFut: Future<Output = Result<reqwest::Response, PicaError>>,
+    R: FnOnce() -> RFut,
+    RFut: Future<Output = Option<T>>,
+{
+    let response = send(secret).await?;
+
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+
+    match refresh().await {
+        Some(secret) => send(secret).await,
+        None => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn send(server: &Server, token: &str) -> Result<reqwest::Response, PicaError> {
+        Ok(reqwest::Client::new()
+            .get(format!("{}/customers", server.url()))
+            .bearer_auth(token)
+            .send()
+            .await?)
+    }
+
+    #[tokio::test]
+    async fn test_retries_once_with_refreshed_secret() {
+        let mut server = Server::new_async().await;
+
+        let expired = server
+            .mock("GET", "/customers")
+            .match_header("authorization", "Bearer expired")
+            .with_status(401)
+            .expect(1)
+            .create_async()
+            .await;
+        let refreshed = server
+            .mock("GET", "/customers")
+            .match_header("authorization", "Bearer refreshed")
+            .with_status(200)
+            .with_body("[]")
+            .expect(1)
+            .create_async()
+            .await;
+
+        let refreshes = AtomicUsize::new(0);
+
+        let response = send_refreshing(
+            "expired".to_string(),
+            |token| {
+                let server = &server;
+                async move { send(server, &token).await }
+            },
+            || async {
+                refreshes.fetch_add(1, Ordering::SeqCst);
+                Some("refreshed".to_string())
+            },
+        )
+        .await
+        .expect("Failed to send request");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+        expired.assert_async().await;
+        refreshed.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_retries_only_once() {
+        let mut server = Server::new_async().await;
+
+        let rejected = server
+            .mock("GET", "/customers")
+            .match_header("authorization", Matcher::Any)
+            .with_status(401)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let refreshes = AtomicUsize::new(0);
+
+        let response = send_refreshing(
+            "expired".to_string(),
+            |token| {
+                let server = &server;
+                async move { send(server, &token).await }
+            },
+            || async {
+                refreshes.fetch_add(1, Ordering::SeqCst);
+                Some("revoked".to_string())
+            },
+        )
+        .await
+        .expect("Failed to send request");
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+        rejected.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_does_not_refresh_other_responses() {
+        let mut server = Server::new_async().await;
+
+        let forbidden = server
+            .mock("GET", "/customers")
+            .with_status(403)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let response = send_refreshing(
+            "valid".to_string(),
+            |token| {
+                let server = &server;
+                async move { send(server, &token).await }
+            },
+            || async { panic!("Refreshed a secret the platform did not reject") },
+        )
+        .await
+        .expect("Failed to send request");
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        forbidden.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_keeps_response_when_refresh_fails() {
+        let mut server = Server::new_async().await;
+
+        let expired = server
+            .mock("GET", "/customers")
+            .with_status(401)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let response = send_refreshing(
+            "expired".to_string(),
+            |token| {
+                let server = &server;
+                async move { send(server, &token).await }
+            },
+            || async { None },
+        )
+        .await
+        .expect("Failed to send request");
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        expired.assert_async().await;
+    }
+}
@@ -0,0 +1,389 @@
+use crate::domain::RequestCrud;
+use cache::{local::ExpiringCache, remote::RedisCache};
+use http::{HeaderMap, Method, StatusCode};
+use osentities::{connection_model_definition::CrudAction, hashed_secret::HashedSecret, PicaError};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+use tracing::warn;
+
+const REMOTE_KEY_PREFIX: &str = "unified-response";
+/// How long the generation of a model outlives its last invalidation or cached response. Cached
+/// responses never live longer, so none are left when a generation expires and starts over.
+const GENERATION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Value of the cache control header that skips cached responses
+pub const NO_CACHE: &str = "no-cache";
+
+/// Response of a unified read, stored as it was returned to the caller
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedResponse {
+    #[serde(with = "http_serde_ext_ios::status_code")]
+    pub status: StatusCode,
+    #[serde(with = "http_serde_ext_ios::header_map")]
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl CachedResponse {
+    /// Approximate size in bytes of the response, used to bound the local cache
+    pub fn weight(&self) -> u32 {
+        let headers = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum::<usize>();
+        let body = serde_json::to_vec(&self.body).map_or(0, |body| body.len());
+
+        u32::try_from(headers + body).unwrap_or(u32::MAX)
+    }
+}
+
+/// Identifies a unified read by its connection, model, action and normalized request, including
+/// the headers forwarded to the platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseCacheKey {
+    connection: String,
+    model: String,
+    action: CrudAction,
+    hash: String,
+}
+
+impl ResponseCacheKey {
+    pub fn new(
+        connection_key: &str,
+        model: &str,
+        action: &CrudAction,
+        id: Option<&str>,
+        passthrough: bool,
+        params: &RequestCrud,
+    ) -> Result<Self, PicaError> {
+        let hash = HashedSecret::try_from(json!({
+            "id": id,
+            "passthrough": passthrough,
+            "queryParams": params.get_query_params().iter().collect::<BTreeMap<_, _>>(),
+            "pathParams": params.get_path_params().map(|p| p.iter().collect::<BTreeMap<_, _>>()),
+            "body": params.get_body(),
+            "headers": forwarded_headers(params.get_headers()),
+        }))?;
+
+        Ok(Self {
+            connection: connection_key.to_string(),
+            model: model.to_lowercase(),
+            action: action.clone(),
+            hash: hash.inner().to_string(),
+        })
+    }
+
+    /// Only reads are cached
+    pub fn is_cacheable(action: &CrudAction) -> bool {
+        matches!(
+            action,
+            CrudAction::GetOne | CrudAction::GetMany | CrudAction::GetCount
+        )
+    }
+
+    /// Writes invalidate the cached reads of their model, as do custom actions unless they are
+    /// sent as reads
+    pub fn invalidates(action: &CrudAction, method: &Method) -> bool {
+        match action {
+            CrudAction::Create | CrudAction::Update | CrudAction::Upsert | CrudAction::Delete => {
+                true
+            }
+            CrudAction::Custom => !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS),
+            CrudAction::GetOne | CrudAction::GetMany | CrudAction::GetCount => false,
+        }
+    }
+}
+
+/// Forwarded headers by name, keeping the order of repeated ones
+fn forwarded_headers(headers: &HeaderMap) -> BTreeMap<&str, Vec<String>> {
+    headers.keys().fold(BTreeMap::new(), |mut forwarded, name| {
+        forwarded.insert(
+            name.as_str(),
+            headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                .collect(),
+        );
+        forwarded
+    })
+}
+
+impl Display for ResponseCacheKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}::{}::{}",
+            model_prefix(&self.connection, &self.model),
+            self.action,
+            self.hash
+        )
+    }
+}
+
+fn model_prefix(connection: &str, model: &str) -> String {
+    format!("{connection}::{}", model.to_lowercase())
+}
+
+/// Where a unified read is cached, resolved before the request is sent. With the remote cache a
+/// response fetched while its model is invalidated is then stored under the generation it was
+/// read from, which is never read again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheSlot {
+    key: String,
+    generation_key: Option<String>,
+}
+
+impl Display for CacheSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.key)
+    }
+}
+
+/// Responses of unified reads. Failures of the cache are logged and treated as misses so they
+/// never fail a request.
+#[derive(Clone)]
+pub enum ResponseCache {
+    /// Cached by each process, writes only invalidate the entries of the process handling them
+    Local(ExpiringCache<CachedResponse>),
+    /// Shared by every process through redis, writes bump a generation of the model instead of
+    /// deleting its entries, which then expire on their own
+    Remote(RedisCache),
+}
+
+impl ResponseCache {
+    /// Local cache holding at most `max_bytes` of responses
+    pub fn local(max_bytes: u64) -> Self {
+        ResponseCache::Local(ExpiringCache::weighted(max_bytes, CachedResponse::weight))
+    }
+
+    /// Resolves where `key` is cached, None if the cache can't be reached
+    pub async fn slot(&self, key: &ResponseCacheKey) -> Option<CacheSlot> {
+        match self {
+            ResponseCache::Local(_) => Some(CacheSlot {
+                key: key.to_string(),
+                generation_key: None,
+            }),
+            ResponseCache::Remote(redis) => {
+                let prefix = model_prefix(&key.connection, &key.model);
+                let generation_key = generation_key(&prefix);
+                let generation = redis
+                    .counter(&generation_key)
+                    .await
+                    .inspect_err(|e| warn!("Could not get the generation of {prefix}: {e}"))
+                    .ok()?;
+
+                Some(CacheSlot {
+                    key: format!(
+                        "{REMOTE_KEY_PREFIX}:{prefix}:{generation}:{}:{}",
+                        key.action, key.hash
+                    ),
+                    generation_key: Some(generation_key),
+                })
+            }
+        }
+    }
+
+    pub async fn get(&self, slot: &CacheSlot) -> Option<CachedResponse> {
+        match self {
+            ResponseCache::Local(cache) => cache.get(&slot.key).await,
+            ResponseCache::Remote(redis) => redis
+                .get_json(&slot.key)
+                .await
+                .inspect_err(|e| warn!("Could not get cached response {slot}: {e}"))
+                .ok()
+                .flatten(),
+        }
+    }
+
+    pub async fn insert(&self, slot: &CacheSlot, response: CachedResponse, ttl: Duration) {
+        match self {
+            ResponseCache::Local(cache) => cache.insert(slot.key.clone(), response, ttl).await,
+            ResponseCache::Remote(redis) => {
+                if let Some(generation_key) = &slot.generation_key {
+                    let _ = redis
+                        .touch(generation_key, GENERATION_TTL)
+                        .await
+                        .inspect_err(|e| warn!("Could not touch {generation_key}: {e}"));
+                }
+
+                let _ = redis
+                    .set_json(&slot.key, &response, ttl.min(GENERATION_TTL))
+                    .await
+                    .inspect_err(|e| warn!("Could not cache response {slot}: {e}"));
+            }
+        }
+    }
+
+    /// Invalidates the cached reads of `model` made with `connection_key`
+    pub async fn invalidate(&self, connection_key: &str, model: &str) {
+        let prefix = model_prefix(connection_key, model);
+
+        let result = match self {
+            ResponseCache::Local(cache) => cache.invalidate_prefix(&format!("{prefix}::")),
+            ResponseCache::Remote(redis) => redis
+                .increment(&generation_key(&prefix), GENERATION_TTL)
+                .await
+                .map(|_| ()),
+        };
+
+        if let Err(e) = result {
+            warn!("Could not invalidate cached responses of {prefix}: {e}");
+        }
+    }
+}
+
+fn generation_key(prefix: &str) -> String {
+    format!("{REMOTE_KEY_PREFIX}:{prefix}:generation")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::RequestCrudBuilder;
+    use std::collections::HashMap;
+
+    fn params(query_params: &[(&str, &str)], body: Option<Value>) -> RequestCrud {
+        params_with_headers(query_params, HeaderMap::new(), body)
+    }
+
+    fn params_with_headers(
+        query_params: &[(&str, &str)],
+        headers: HeaderMap,
+        body: Option<Value>,
+    ) -> RequestCrud {
+        RequestCrudBuilder::default()
+            .query_params(
+                query_params
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            )
+            .headers(headers)
+            .body(body)
+            .build()
+            .expect("Failed to build request")
+    }
+
+    fn key(action: CrudAction, params: &RequestCrud) -> ResponseCacheKey {
+        ResponseCacheKey::new("conn", "Contacts", &action, None, false, params)
+            .expect("Failed to create key")
+    }
+
+    #[test]
+    fn test_key_normalizes_request() {
+        let first = key(
+            CrudAction::GetMany,
+            &params(&[("limit", "10"), ("cursor", "abc")], None),
+        );
+        let second = key(
+            CrudAction::GetMany,
+            &params(&[("cursor", "abc"), ("limit", "10")], None),
+        );
+        assert_eq!(first, second);
+        assert!(first.to_string().starts_with("conn::contacts::getMany::"));
+
+        assert_ne!(
+            first,
+            key(CrudAction::GetMany, &params(&[("limit", "20")], None))
+        );
+        assert_ne!(
+            first,
+            key(
+                CrudAction::GetCount,
+                &params(&[("limit", "10"), ("cursor", "abc")], None)
+            )
+        );
+        assert_ne!(
+            key(CrudAction::GetOne, &params(&[], Some(json!({ "a": 1 })))),
+            key(CrudAction::GetOne, &params(&[], Some(json!({ "a": 2 }))))
+        );
+    }
+
+    #[test]
+    fn test_key_includes_forwarded_headers() {
+        let headers = |version: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-api-version", http::HeaderValue::from_static(version));
+            headers
+        };
+
+        let first = key(
+            CrudAction::GetMany,
+            &params_with_headers(&[], headers("2024-01"), None),
+        );
+        assert_eq!(
+            first,
+            key(
+                CrudAction::GetMany,
+                &params_with_headers(&[], headers("2024-01"), None)
+            )
+        );
+        assert_ne!(
+            first,
+            key(
+                CrudAction::GetMany,
+                &params_with_headers(&[], headers("2025-01"), None)
+            )
+        );
+        assert_ne!(first, key(CrudAction::GetMany, &params(&[], None)));
+    }
+
+    #[test]
+    fn test_custom_writes_invalidate() {
+        assert!(ResponseCacheKey::invalidates(
+            &CrudAction::Create,
+            &Method::POST
+        ));
+        assert!(ResponseCacheKey::invalidates(
+            &CrudAction::Custom,
+            &Method::POST
+        ));
+        assert!(!ResponseCacheKey::invalidates(
+            &CrudAction::Custom,
+            &Method::GET
+        ));
+        assert!(!ResponseCacheKey::invalidates(
+            &CrudAction::GetMany,
+            &Method::GET
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_local_invalidation() {
+        let cache = ResponseCache::local(1024 * 1024);
+        let response = CachedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: json!({ "unified": [] }),
+        };
+        let ttl = Duration::from_secs(60);
+
+        let contacts = key(CrudAction::GetMany, &params(&[], None));
+        let other_connection = ResponseCacheKey::new(
+            "other",
+            "Contacts",
+            &CrudAction::GetMany,
+            None,
+            false,
+            &params(&[], None),
+        )
+        .expect("Failed to create key");
+
+        let contacts = cache.slot(&contacts).await.expect("Failed to resolve slot");
+        let other_connection = cache
+            .slot(&other_connection)
+            .await
+            .expect("Failed to resolve slot");
+
+        cache.insert(&contacts, response.clone(), ttl).await;
+        cache.insert(&other_connection, response.clone(), ttl).await;
+        assert_eq!(cache.get(&contacts).await, Some(response.clone()));
+
+        cache.invalidate("conn", "contacts").await;
+        assert_eq!(cache.get(&contacts).await, None);
+        assert_eq!(cache.get(&other_connection).await, Some(response));
+    }
+}
@@ -0,0 +1,482 @@
+use cache::window::{SlidingWindow, SlidingWindows};
+use chrono::{DateTime, Utc};
+use http::{header::RETRY_AFTER, HeaderMap};
+use osentities::{
+    rate_limit::{ProviderRateLimit, RateLimitScope},
+    ApplicationError, PicaError,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Windows are only swept once there are this many of them
+const SWEEP_THRESHOLD: usize = 1024;
+
+/// Prefix of the keys of the windows shared through redis
+const SHARED_KEY_PREFIX: &str = "outbound";
+
+/// Provider limits that apply to a call made with a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Throttle {
+    connection: String,
+    limits: Vec<(String, ProviderRateLimit)>,
+}
+
+impl Throttle {
+    pub fn new(connection_key: &str) -> Self {
+        Self {
+            connection: connection_key.to_string(),
+            limits: vec![],
+        }
+    }
+
+    /// Adds the limits declared by `owner`, keyed by the connection or the platform depending
+    /// on their scope. Limits allowing no requests are ignored.
+    pub fn with_limits(mut self, owner: &str, limits: &[ProviderRateLimit]) -> Self {
+        for limit in limits.iter().filter(|l| l.requests > 0) {
+            let key = match limit.scope {
+                RateLimitScope::Connection => format!(
+                    "{owner}:{}:{}/{}ms",
+                    self.connection, limit.requests, limit.period_ms
+                ),
+                RateLimitScope::Platform => {
+                    format!("{owner}:{}/{}ms", limit.requests, limit.period_ms)
+                }
+            };
+
+            self.limits.push((key, *limit));
+        }
+
+        self
+    }
+
+    /// Keys held back when the platform rejects a call with a 429. Limits shared by every
+    /// connection to the platform are held back for all of them, otherwise only the connection.
+    fn blocked_keys(&self) -> Vec<&str> {
+        let platform = self
+            .limits
+            .iter()
+            .filter(|(_, limit)| limit.scope == RateLimitScope::Platform)
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>();
+
+        if platform.is_empty() {
+            vec![self.connection.as_str()]
+        } else {
+            platform
+        }
+    }
+}
+
+/// Calls made within the period of a limit
+#[derive(Debug)]
+struct Window {
+    calls: VecDeque<Instant>,
+    period: Duration,
+}
+
+impl Window {
+    fn new(period: Duration) -> Self {
+        Self {
+            calls: VecDeque::new(),
+            period,
+        }
+    }
+
+    /// How long until another call fits in the window
+    fn wait(&mut self, limit: &ProviderRateLimit, now: Instant) -> Duration {
+        while self
+            .calls
+            .front()
+            .is_some_and(|call| now.saturating_duration_since(*call) >= self.period)
+        {
+            self.calls.pop_front();
+        }
+
+        match self.calls.front() {
+            Some(oldest) if self.calls.len() as u64 >= limit.requests => {
+                (*oldest + self.period).saturating_duration_since(now)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.calls
+            .back()
+            .is_none_or(|call| now.saturating_duration_since(*call) >= self.period)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Windows {
+    limits: HashMap<String, Window>,
+    /// Connections or platforms the platform asked to back off until then
+    blocked: HashMap<String, Instant>,
+}
+
+impl Windows {
+    /// Records a call if every limit allows it, otherwise returns how long to wait for
+    fn reserve(&mut self, throttle: &Throttle, now: Instant) -> Option<Duration> {
+        if self.limits.len() + self.blocked.len() > SWEEP_THRESHOLD {
+            self.limits.retain(|_, window| !window.is_idle(now));
+            self.blocked.retain(|_, until| *until > now);
+        }
+
+        let blocked = throttle
+            .blocked_keys()
+            .into_iter()
+            .filter_map(|key| self.blocked.get(key))
+            .map(|until| until.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default();
+
+        let wait = throttle
+            .limits
+            .iter()
+            .map(|(key, limit)| {
+                self.limits
+                    .entry(key.clone())
+                    .or_insert_with(|| Window::new(limit.period()))
+                    .wait(limit, now)
+            })
+            .fold(blocked, Duration::max);
+
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+
+        for (key, _) in &throttle.limits {
+            if let Some(window) = self.limits.get_mut(key) {
+                window.calls.push_back(now);
+            }
+        }
+
+        None
+    }
+
+    fn block(&mut self, throttle: &Throttle, until: Instant) {
+        for key in throttle.blocked_keys() {
+            let blocked = self.blocked.entry(key.to_string()).or_insert(until);
+            *blocked = (*blocked).max(until);
+        }
+    }
+}
+
+/// Queues the calls made to providers so they stay within their declared limits. Limits are
+/// shared by every instance of a service through redis when it is configured, otherwise they
+/// are tracked by each process and every instance gets the whole allowance. Calls fall back to
+/// the limits of the process while redis can't be reached.
+#[derive(Debug)]
+pub struct OutboundLimiter {
+    windows: Mutex<Windows>,
+    shared: Option<SlidingWindows>,
+    max_wait: Duration,
+}
+
+impl OutboundLimiter {
+    pub fn new(max_wait: Duration) -> Self {
+        Self {
+            windows: Mutex::new(Windows::default()),
+            shared: None,
+            max_wait,
+        }
+    }
+
+    /// Shares the limits with the other instances through `windows`
+    pub fn with_shared_windows(mut self, windows: SlidingWindows) -> Self {
+        self.shared = Some(windows);
+        self
+    }
+
+    pub fn max_wait(&self) -> Duration {
+        self.max_wait
+    }
+
+    /// Waits until a call is allowed by every limit of `throttle`, failing right away when
+    /// that would take longer than the maximum wait
+    pub async fn acquire(&self, throttle: &Throttle) -> Result<(), PicaError> {
+        let deadline = Instant::now() + self.max_wait;
+
+        loop {
+            let now = Instant::now();
+            let wait = self.reserve(throttle, now).await;
+
+            match wait {
+                None => return Ok(()),
+                Some(wait) if now + wait > deadline => {
+                    return Err(ApplicationError::too_many_requests(
+                        &format!(
+                            "Rate limit of the platform exceeded for connection {}, retry in {}s",
+                            throttle.connection,
+                            wait.as_secs_f64().ceil()
+                        ),
+                        None,
+                    ));
+                }
+                Some(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    async fn reserve(&self, throttle: &Throttle, now: Instant) -> Option<Duration> {
+        if let Some(shared) = &self.shared {
+            match reserve_shared(shared, throttle).await {
+                Ok(wait) => return wait,
+                Err(e) => tracing::warn!(
+                    "Could not check the shared rate limits of connection {}: {e}",
+                    throttle.connection
+                ),
+            }
+        }
+
+        self.windows
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .reserve(throttle, now)
+    }
+
+    /// Holds back the calls made with the connection of `throttle` for `duration`, or every call
+    /// to the platform when it has limits shared by all of its connections
+    pub async fn block(&self, throttle: &Throttle, duration: Duration) {
+        self.windows
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .block(throttle, Instant::now() + duration);
+
+        if let Some(shared) = &self.shared {
+            let now = Utc::now().timestamp_millis();
+
+            for key in throttle.blocked_keys() {
+                if let Err(e) = shared
+                    .block(&blocked_key(key), now + duration.as_millis() as i64, now)
+                    .await
+                {
+                    tracing::warn!(
+                        "Could not share the block of connection {}: {e}",
+                        throttle.connection
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn blocked_key(key: &str) -> String {
+    format!("{SHARED_KEY_PREFIX}:blocked:{key}")
+}
+
+/// Records a call in the shared windows if every limit allows it, otherwise returns how long to
+/// wait for
+async fn reserve_shared(
+    shared: &SlidingWindows,
+    throttle: &Throttle,
+) -> Result<Option<Duration>, PicaError> {
+    let now = Utc::now().timestamp_millis();
+
+    let mut blocked_until = None;
+    for key in throttle.blocked_keys() {
+        blocked_until = blocked_until.max(shared.blocked_until(&blocked_key(key)).await?);
+    }
+
+    if let Some(until) = blocked_until.filter(|until| *until > now) {
+        return Ok(Some(Duration::from_millis((until - now) as u64)));
+    }
+
+    let windows = throttle
+        .limits
+        .iter()
+        .map(|(key, limit)| SlidingWindow {
+            key: format!("{SHARED_KEY_PREFIX}:{key}"),
+            limit: limit.requests,
+            window: limit.period(),
+        })
+        .collect::<Vec<_>>();
+
+    let (allowed, counts) = shared.check(&windows, now).await?;
+    if allowed {
+        return Ok(None);
+    }
+
+    let wait = windows
+        .iter()
+        .zip(counts)
+        .filter(|(window, count)| count.count >= window.limit)
+        .map(|(_, count)| Duration::from_millis(count.reset_ms.max(1) as u64))
+        .max()
+        .unwrap_or(Duration::from_millis(1));
+
+    Ok(Some(wait))
+}
+
+/// Delay requested by a `Retry-After` header, given either in seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn limit(requests: u64, period_ms: u64, scope: RateLimitScope) -> ProviderRateLimit {
+        ProviderRateLimit {
+            requests,
+            period_ms,
+            scope,
+        }
+    }
+
+    #[test]
+    fn test_throttle_keys() {
+        let throttle = Throttle::new("conn").with_limits(
+            "definition:1",
+            &[
+                limit(10, 1_000, RateLimitScope::Connection),
+                limit(100, 60_000, RateLimitScope::Platform),
+                limit(0, 1_000, RateLimitScope::Platform),
+            ],
+        );
+
+        let keys = throttle
+            .limits
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec!["definition:1:conn:10/1000ms", "definition:1:100/60000ms"]
+        );
+    }
+
+    #[test]
+    fn test_reserve_within_limits() {
+        let mut windows = Windows::default();
+        let now = Instant::now();
+
+        let first = Throttle::new("first").with_limits(
+            "definition:1",
+            &[
+                limit(2, 1_000, RateLimitScope::Connection),
+                limit(3, 1_000, RateLimitScope::Platform),
+            ],
+        );
+        let second = Throttle::new("second").with_limits(
+            "definition:1",
+            &[
+                limit(2, 1_000, RateLimitScope::Connection),
+                limit(3, 1_000, RateLimitScope::Platform),
+            ],
+        );
+
+        assert_eq!(windows.reserve(&first, now), None);
+        assert_eq!(
+            windows.reserve(&first, now + Duration::from_millis(200)),
+            None
+        );
+        // The connection used its allowance until the first call leaves the window
+        assert_eq!(
+            windows.reserve(&first, now + Duration::from_millis(400)),
+            Some(Duration::from_millis(600))
+        );
+
+        // Other connections share the platform allowance only
+        assert_eq!(
+            windows.reserve(&second, now + Duration::from_millis(400)),
+            None
+        );
+        assert_eq!(
+            windows.reserve(&second, now + Duration::from_millis(500)),
+            Some(Duration::from_millis(500))
+        );
+
+        assert_eq!(
+            windows.reserve(&first, now + Duration::from_millis(1_000)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_blocked_connection() {
+        let mut windows = Windows::default();
+        let now = Instant::now();
+        let throttle = Throttle::new("conn");
+
+        windows.block(&throttle, now + Duration::from_secs(2));
+        windows.block(&throttle, now + Duration::from_secs(1));
+
+        assert_eq!(
+            windows.reserve(&throttle, now),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(windows.reserve(&Throttle::new("other"), now), None);
+        assert_eq!(
+            windows.reserve(&throttle, now + Duration::from_secs(2)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_blocked_platform() {
+        let mut windows = Windows::default();
+        let now = Instant::now();
+        let throttle = |connection| {
+            Throttle::new(connection).with_limits(
+                "definition:1",
+                &[
+                    limit(10, 1_000, RateLimitScope::Connection),
+                    limit(100, 60_000, RateLimitScope::Platform),
+                ],
+            )
+        };
+
+        windows.block(&throttle("conn"), now + Duration::from_secs(2));
+
+        // The limit of the platform is shared, so are the 429s it responds with
+        assert_eq!(
+            windows.reserve(&throttle("other"), now),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(windows.reserve(&Throttle::new("unrelated"), now), None);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .expect("Failed to parse date")
+            .with_timezone(&Utc);
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(
+            retry_after(&headers("120"), now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            retry_after(&headers("Mon, 01 Jan 2024 00:00:30 GMT"), now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            retry_after(&headers("Sun, 31 Dec 2023 23:59:00 GMT"), now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after(&headers("soon"), now), None);
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+    }
+}
@@ -1,20 +1,29 @@
 use crate::domain::{ResponseCrudToMapBuilder, ResponseCrudToMapRequest};
 use crate::{
-    algebra::jsruntime::JSRuntimeImpl,
-    client::CallerClient,
-    domain::{RequestCrud, ResponseCrud, UnifiedMetadata, UnifiedMetadataBuilder},
+    algebra::jsruntime::{JSRuntimeImpl, JSScript},
+    circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitKey},
+    client::{CallerClient, Retries},
+    content::{decode_body, response_content},
+    domain::{RequestCrud, ResponseCrud, UnifiedCache, UnifiedMetadata, UnifiedMetadataBuilder},
     helper::{match_route, template_route},
+    pagination::{PageBudget, Pages},
+    refresh::send_refreshing,
+    response_cache::{CachedResponse, ResponseCache, ResponseCacheKey, NO_CACHE},
+    throttle::{retry_after, OutboundLimiter, Throttle},
 };
 use bson::doc;
-use cache::local::{
-    ConnectionCache, ConnectionModelDefinitionCacheIdKey, ConnectionModelDefinitionCacheIdKeyInner,
-    ConnectionModelDefinitionDestinationCache, ConnectionModelSchemaCache, LocalCacheExt,
-    SecretCache,
+use cache::{
+    local::{
+        ConnectionCache, ConnectionDefinitionCache, ConnectionModelDefinitionCacheIdKey,
+        ConnectionModelDefinitionCacheIdKeyInner, ConnectionModelDefinitionDestinationCache,
+        ConnectionModelSchemaCache, LocalCacheExt, OAuthRefreshCache, SecretCache,
+    },
+    window::SlidingWindows,
 };
 use chrono::Utc;
 use futures::{
     future::{join_all, OptionFuture},
-    FutureExt,
+    stream, Future, FutureExt, Stream,
 };
 use handlebars::Handlebars;
 use http::{HeaderMap, HeaderName, HeaderValue, Response, StatusCode};
@@ -25,6 +34,7 @@ use mongodb::{
 use osentities::{
     algebra::JsonExt,
     api_model_config::{ModelPaths, RequestModelPaths},
+    connection_definition::ConnectionDefinition,
     connection_model_definition::{ConnectionModelDefinition, CrudAction, PlatformInfo},
     connection_model_schema::ConnectionModelSchema,
     constant::*,
@@ -35,12 +45,20 @@ use osentities::{
     hashed_secret::HashedSecret,
     id::{prefix::IdPrefix, Id},
     prelude::{MongoStore, TimedExt},
-    ApplicationError, Connection, ErrorMeta, PicaError, Secret, SecretExt, Store,
+    ApplicationError, Connection, ErrorMeta, OAuth, OAuthRefresher, PicaError, Secret, SecretExt,
+    Store,
 };
 use serde_json::{json, Number, Value};
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
 use tracing::error;
 
+/// How long a refreshed connection is reused for callers that observed the same stale secret
+const OAUTH_REFRESH_COALESCE_SECS: u64 = 60;
+/// Longest a call is queued for to stay within the rate limits of a platform
+const OUTBOUND_MAX_WAIT_SECS: u64 = 10;
+/// Times a call rejected with a `Retry-After` is sent again
+const MAX_RATE_LIMITED_RETRIES: usize = 2;
+
 pub struct UnifiedResponse {
     pub response: Response<Value>,
     pub metadata: UnifiedMetadata,
@@ -58,17 +76,28 @@ pub struct SendToDestinationUnified {
 pub struct UnifiedDestination {
     pub connections_cache: ConnectionCache,
     pub connections_store: MongoStore<Connection>,
+    pub connection_definitions_cache: ConnectionDefinitionCache,
+    pub connection_definitions_store: MongoStore<ConnectionDefinition>,
     pub connection_model_definitions_cache: ConnectionModelDefinitionDestinationCache,
     pub connection_model_definitions_store: MongoStore<ConnectionModelDefinition>,
     pub connection_model_schemas_cache: ConnectionModelSchemaCache,
     pub connection_model_schemas_store: MongoStore<ConnectionModelSchema>,
     pub secrets_client: Arc<dyn SecretExt + Sync + Send>,
     pub secrets_cache: SecretCache,
+    pub oauth_refresher: OAuthRefresher,
+    pub oauth_refreshes: OAuthRefreshCache,
     pub http_client: reqwest::Client,
+    /// Client of passthrough calls, whose bodies may be streamed back to the caller
+    pub streaming_client: reqwest::Client,
+    pub outbound_limiter: Arc<OutboundLimiter>,
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    pub response_cache: ResponseCache,
+    pub js_runtime: JSRuntimeImpl,
 }
 
 pub struct UnifiedCacheTTLs {
     pub connection_cache_ttl_secs: u64,
+    pub connection_definition_cache_ttl_secs: u64,
     pub connection_model_definition_cache_ttl_secs: u64,
     pub connection_model_schema_cache_ttl_secs: u64,
     pub secret_cache_ttl_secs: u64,
@@ -80,10 +109,16 @@ impl UnifiedDestination {
         cache_size: u64,
         secrets_client: Arc<dyn SecretExt + Sync + Send>,
         cache_ttls: UnifiedCacheTTLs,
+        http_client: reqwest::Client,
+        circuit_breaker: CircuitBreakerConfig,
+        response_cache: ResponseCache,
     ) -> Result<Self, PicaError> {
-        let http_client = reqwest::Client::new();
         let connections_cache =
             ConnectionCache::new(cache_size, cache_ttls.connection_cache_ttl_secs);
+        let connection_definitions_cache = ConnectionDefinitionCache::new(
+            cache_size,
+            cache_ttls.connection_definition_cache_ttl_secs,
+        );
         let connection_model_definitions_cache = ConnectionModelDefinitionDestinationCache::new(
             cache_size,
             cache_ttls.connection_model_definition_cache_ttl_secs,
@@ -93,6 +128,7 @@ impl UnifiedDestination {
             cache_ttls.connection_model_schema_cache_ttl_secs,
         );
         let secrets_cache = SecretCache::new(cache_size, cache_ttls.secret_cache_ttl_secs);
+        let oauth_refreshes = OAuthRefreshCache::new(cache_size, OAUTH_REFRESH_COALESCE_SECS);
 
         let client = Client::with_uri_str(&db_config.control_db_url)
             .await
@@ -106,24 +142,68 @@ impl UnifiedDestination {
         let db = client.database(&db_config.control_db_name);
 
         let connections_store = MongoStore::new(&db, &Store::Connections).await?;
+        let connection_definitions_store =
+            MongoStore::new(&db, &Store::ConnectionDefinitions).await?;
         let connection_model_definitions_store =
             MongoStore::new(&db, &Store::ConnectionModelDefinitions).await?;
         let connection_model_schemas_store =
             MongoStore::new(&db, &Store::ConnectionModelSchemas).await?;
+        let connection_oauth_definitions_store =
+            MongoStore::new(&db, &Store::ConnectionOAuthDefinitions).await?;
+
+        let oauth_refresher = OAuthRefresher::new(
+            connections_store.clone(),
+            connection_oauth_definitions_store,
+            secrets_client.clone(),
+            http_client.clone(),
+        );
 
         Ok(Self {
             connections_cache,
             connections_store,
+            connection_definitions_cache,
+            connection_definitions_store,
             connection_model_definitions_cache,
             connection_model_definitions_store,
             connection_model_schemas_cache,
             connection_model_schemas_store,
             secrets_client,
             secrets_cache,
+            oauth_refresher,
+            oauth_refreshes,
+            streaming_client: http_client.clone(),
             http_client,
+            outbound_limiter: Arc::new(OutboundLimiter::new(Duration::from_secs(
+                OUTBOUND_MAX_WAIT_SECS,
+            ))),
+            circuit_breaker: Arc::new(CircuitBreaker::new(circuit_breaker)),
+            response_cache,
+            js_runtime: JSRuntimeImpl::default(),
         })
     }
 
+    /// Shares the limits of the platforms with the other instances through `windows`
+    pub fn with_shared_rate_limits(mut self, windows: SlidingWindows) -> Self {
+        self.outbound_limiter = Arc::new(
+            OutboundLimiter::new(Duration::from_secs(OUTBOUND_MAX_WAIT_SECS))
+                .with_shared_windows(windows),
+        );
+        self
+    }
+
+    /// Sends passthrough calls with `client`, which should not bound how long their bodies take
+    /// to be read when they are streamed back to the caller
+    pub fn with_streaming_client(mut self, client: reqwest::Client) -> Self {
+        self.streaming_client = client;
+        self
+    }
+
+    /// Limits the mapping scripts run for unified calls
+    pub fn with_js_runtime(mut self, js_runtime: JSRuntimeImpl) -> Self {
+        self.js_runtime = js_runtime;
+        self
+    }
+
     pub async fn get_connection_model_definition(
         &self,
         destination: &Destination,
@@ -222,6 +302,7 @@ impl UnifiedDestination {
         config: &ConnectionModelDefinition,
         params: &RequestCrud,
         secret: &Value,
+        throttle: Option<&Throttle>,
     ) -> Result<reqwest::Response, PicaError> {
         let context = match params.get_body() {
             None | Some(Value::Null) => None,
@@ -241,6 +322,7 @@ impl UnifiedDestination {
             params.get_query_params(),
             secret,
             context,
+            throttle,
         )
         .await
     }
@@ -252,28 +334,69 @@ impl UnifiedDestination {
         query_params: &HashMap<String, String>,
         secret: &Value,
         context: Option<Vec<u8>>,
+        throttle: Option<&Throttle>,
+    ) -> Result<reqwest::Response, PicaError> {
+        self.execute_model_definition_with(
+            &self.http_client,
+            config,
+            headers,
+            query_params,
+            secret,
+            context,
+            throttle,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn execute_model_definition_with(
+        &self,
+        client: &reqwest::Client,
+        config: &ConnectionModelDefinition,
+        headers: HeaderMap,
+        query_params: &HashMap<String, String>,
+        secret: &Value,
+        context: Option<Vec<u8>>,
+        throttle: Option<&Throttle>,
     ) -> Result<reqwest::Response, PicaError> {
         let renderer = Handlebars::new();
 
         let config_str = serde_json::to_string(&config)
             .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))?;
 
-        let config = renderer
+        let rendered = renderer
             .render_template(&config_str, secret)
             .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))?;
 
-        let config: ConnectionModelDefinition = serde_json::from_str(&config)
+        let rendered: ConnectionModelDefinition = serde_json::from_str(&rendered)
             .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))?;
 
-        match config.platform_info {
+        let circuit = circuit_key(config, &rendered);
+        self.circuit_breaker.check(&circuit)?;
+
+        match rendered.platform_info {
             PlatformInfo::Api(ref c) => {
-                let api_caller = CallerClient::new(c, config.action, &self.http_client);
+                let mut api_caller = CallerClient::new(c, rendered.action, client);
+                if let Some(throttle) = throttle {
+                    api_caller = api_caller.with_limiter(&self.outbound_limiter, throttle);
+                }
 
                 let response = api_caller
                     .make_request(context, Some(secret), Some(headers), Some(query_params))
-                    .await?;
+                    .await;
 
-                Ok(response)
+                match &response {
+                    Ok(response) => self.circuit_breaker.record(
+                        &circuit,
+                        !matches!(response.status().as_u16(), 500 | 502 | 503 | 504),
+                    ),
+                    Err(PicaError::Internal(InternalError::IOErr { .. })) => {
+                        self.circuit_breaker.record(&circuit, false)
+                    }
+                    Err(_) => {}
+                }
+
+                response
             }
         }
     }
@@ -306,6 +429,45 @@ impl UnifiedDestination {
             })
     }
 
+    /// Fetches the pages of a `GetMany` one after the other, following their cursors until the
+    /// last page or the budget is reached. The stream ends after a failed page.
+    pub fn dispatch_unified_pages(
+        &self,
+        connection: Arc<Connection>,
+        action: Action,
+        environment: Environment,
+        params: RequestCrud,
+        cache: ConnectionModelDefinitionCacheIdKey,
+        budget: PageBudget,
+    ) -> impl Stream<Item = Result<UnifiedResponse, PicaError>> + Send + 'static {
+        let destination = Arc::new(self.clone());
+
+        stream::unfold(Pages::new(budget), move |mut pages| {
+            let destination = destination.clone();
+            let connection = connection.clone();
+            let action = action.clone();
+            let cache = cache.clone();
+            let params = pages.next_page(&params);
+
+            async move {
+                let page = destination
+                    .dispatch_unified_request(connection, action, environment, params?, cache)
+                    .await;
+
+                match page {
+                    Ok(mut page) => {
+                        pages.advance(&mut page);
+                        Some((Ok(page), pages))
+                    }
+                    Err(e) => {
+                        pages.stop();
+                        Some((Err(e), pages))
+                    }
+                }
+            }
+        })
+    }
+
     async fn perform_unified_request(
         &self,
         connection: Arc<Connection>,
@@ -328,6 +490,9 @@ impl UnifiedDestination {
                 id,
                 passthrough: is_passthrough,
             } => {
+                let (params, cache_control) = params.remove_header(PICA_CACHE_CONTROL_HEADER);
+                let bypass_cache = cache_control.is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(NO_CACHE.as_bytes()));
+
                 // (ConnectionModelDefinition, Secret, ConnectionModelSchema)
                 let (config, secret, cms) = self.get_dependencies(&key, &connection, &name, cache).await.inspect_err(|e| {
                     error!("Failed to get dependencies for unified destination. Destination: {:?}, Error: {e}", key.platform);
@@ -338,19 +503,41 @@ impl UnifiedDestination {
                     .action(action.to_string())
                     .common_model(config.mapping.as_ref().map(|m| m.common_model_name.clone()).unwrap_or_default());
 
+                let cache_slot = match config.cache_ttl_secs {
+                    Some(ttl) if ttl > 0 && ResponseCacheKey::is_cacheable(&action) => {
+                        let cache_key = ResponseCacheKey::new(&connection.key, &name, &action, id.as_deref(), is_passthrough, &params)?;
+                        // Resolved before sending the request, so an invalidation made meanwhile is not overwritten
+                        let slot = self.response_cache.slot(&cache_key).await;
+                        let cached = match &slot {
+                            Some(slot) if !bypass_cache => self.response_cache.get(slot).await,
+                            _ => None,
+                        };
+
+                        metadata.cache(UnifiedCache::new(cached.is_some(), ttl, cache_key.to_string()));
+
+                        if let Some(cached) = cached {
+                            tracing::debug!("Cached response found for unified destination. Key: {cache_key}");
+                            return cached_unified_response(cached, metadata);
+                        }
+
+                        slot.map(|slot| (slot, Duration::from_secs(ttl)))
+                    }
+                    _ => None,
+                };
+
                 let secret = insert_action_id(secret.as_value()?, id.as_ref());
 
                 // Namespace for js scripts
-                let jsruntime = JSRuntimeImpl;
-                let crud_namespace = generate_script_namespace(self.secrets_cache.max_capacity(), &config.id.to_string());
-                let schema_namespace = generate_script_namespace(self.secrets_cache.max_capacity(), &cms.id.to_string());
+                let jsruntime = self.js_runtime;
+                let crud_namespace = generate_script_namespace(self.secrets_cache.max_capacity(), &config.id.to_string(), config.record_metadata.updated_at);
+                let schema_namespace = generate_script_namespace(self.secrets_cache.max_capacity(), &cms.id.to_string(), cms.record_metadata.updated_at);
 
                 let body = params.get_body();
                 let body = match cms.mapping.as_ref().map(|m| m.from_common_model.as_str()) {
                     Some(code) if body.is_some() => {
                         let namespace = schema_namespace.clone() + "_mapFromCommonModel";
 
-                        jsruntime.create("mapFromCommonModel", &namespace, code)?.run::<Option<&Value>, Option<Value>>(&body, &namespace).await?.map(|v| v.drop_nulls())
+                        jsruntime.create("mapFromCommonModel", &namespace, code).await?.run::<Option<&Value>, Option<Value>>(&body).await?.map(|v| v.drop_nulls())
                     }
                     _ => body.cloned()
                 };
@@ -362,7 +549,7 @@ impl UnifiedDestination {
                             None => Ok(params),
                             Some(code) => {
                                 let namespace = crud_namespace.clone() + "_mapFromCrudRequest";
-                                let jsruntime = jsruntime.create("mapCrudRequest", &namespace, &code)?;
+                                let script = jsruntime.create("mapCrudRequest", &namespace, &code).await?;
 
                                 tracing::debug!("Code for mapping crud request ready for unified destination. Code: {code}, Namespace: {namespace}");
 
@@ -370,7 +557,7 @@ impl UnifiedDestination {
 
                                 tracing::debug!("Request crud prepared for unified destination. RequestCrud: {:?}", payload);
 
-                                let params: RequestCrud = jsruntime.run(&payload, &namespace).await?;
+                                let params: RequestCrud = script.run(&payload).await?;
                                 let params: RequestCrud = params.extend_body(body);
 
                                 Ok(params)
@@ -388,9 +575,29 @@ impl UnifiedDestination {
 
                 tracing::debug!("Request crud prepared for unified destination. RequestCrud: {:?}", params);
 
-                let response: reqwest::Response = self.execute_model_definition_from_request(&config, &params, &secret).timed(|_, duration| {
-                    metadata.latency(duration.as_millis() as i32);
-                }).await?;
+                let throttle = self.throttle(&connection, &config).await;
+
+                let response: reqwest::Response = {
+                    let (throttle, config, params) = (&throttle, &config, &params);
+
+                    send_refreshing(
+                        secret,
+                        |secret| async move { self.send_throttled(throttle, || self.execute_model_definition_from_request(config, params, &secret, Some(throttle))).await },
+                        || async {
+                            let secret = self.refresh_on_unauthorized(&connection).await?.as_value().inspect_err(|e| {
+                                error!("Failed to read refreshed secret for connection {}: {e}", connection.id);
+                            }).ok()?;
+
+                            Some(extend_secret(insert_action_id(secret, id.as_ref()), params.get_path_params()))
+                        },
+                    ).timed(|_, duration| {
+                        metadata.latency(duration.as_millis() as i32);
+                    }).await?
+                };
+
+                if let Some(Retries(retries)) = response.extensions().get() {
+                    metadata.retries(*retries);
+                }
 
                 let status: StatusCode = response.status();
                 let headers: HeaderMap = response.headers().clone();
@@ -403,10 +610,15 @@ impl UnifiedDestination {
                     Ok(())
                 };
 
-                let body: Result<Value, PicaError> = response.json().await.map_err(|e| {
-                    error!("Failed to get json body from successful response. ID: {}, Error: {}", config.id, e);
+                let content = response_content(config.platform_info.config().response_content, &headers);
+                let body: Result<Value, PicaError> = match response.bytes().await {
+                    Ok(bytes) => decode_body(content, &headers, &bytes).map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }
+                .map_err(|e| {
+                    error!("Failed to get {content:?} body from response. ID: {}, Error: {}", config.id, e);
 
-                    PicaError::from_err_code(status, &e.to_string(), None)
+                    PicaError::from_err_code(status, &e, None)
                 });
 
                 let body: Option<Value> = match error_for_status {
@@ -437,7 +649,7 @@ impl UnifiedDestination {
                                 tracing::debug!("Code for mapping crud request ready for unified destination. Code: {code}, Namespace: {crud_namespace}");
 
                                 let namespace = crud_namespace.clone() + "_mapToCrudRequest";
-                                let jsruntime: JSRuntimeImpl = jsruntime.create("mapCrudRequest", &namespace, code).inspect_err(|e| {
+                                let script: JSScript = jsruntime.create("mapCrudRequest", &namespace, code).await.inspect_err(|e| {
                                     error!("Failed to create request crud mapping script for connection model. ID: {}, Error: {}", config.id, e);
                                 })?;
 
@@ -448,7 +660,7 @@ impl UnifiedDestination {
                                     .request(ResponseCrudToMapRequest::new(params.get_query_params()))
                                     .build()?;
 
-                                let response: ResponseCrud = jsruntime.run(&res_to_map, &namespace).await?;
+                                let response: ResponseCrud = script.run(&res_to_map).await?;
 
                                 response.get_pagination().cloned()
                             }
@@ -465,17 +677,16 @@ impl UnifiedDestination {
                             Some(code) => {
                                 let namespace = schema_namespace.clone() + "_mapToCommonModel";
 
-                                let jsruntime = jsruntime.create("mapToCommonModel", &namespace, code).inspect_err(|e| {
+                                let script = jsruntime.create("mapToCommonModel", &namespace, code).await.inspect_err(|e| {
                                     error!("Failed to create request schema mapping script for connection model schema. ID: {}, Error: {}", config.id, e);
                                 })?;
 
                                 let mapped_body = match body {
                                     Ok(Some(Value::Array(arr))) => {
                                         let futures = arr.into_iter().map(|body| {
-                                            let jsruntime = &jsruntime;
-                                            let namespace = &namespace;
+                                            let script = &script;
                                             async move {
-                                                let mut response = jsruntime.run::<Value, Value>(&body, namespace).await.inspect_err(|e| {
+                                                let mut response = script.run::<Value, Value>(&body).await.inspect_err(|e| {
                                                     error!("Failed to run request schema mapping script for connection model schema. ID: {}, Error: {}", config.id, e);
                                                 })?.drop_nulls();
 
@@ -497,7 +708,7 @@ impl UnifiedDestination {
                                         Ok(Value::Array(values))
                                     }
                                     Ok(Some(body)) => {
-                                        Ok(jsruntime.run::<Value, Value>(&body, &namespace).await.inspect_err(|e| {
+                                        Ok(script.run::<Value, Value>(&body).await.inspect_err(|e| {
                                             error!("Failed to run request schema mapping script for connection model schema. ID: {}, Error: {}", config.id, e);
                                         })?.drop_nulls())
                                     }
@@ -522,7 +733,25 @@ impl UnifiedDestination {
                     CrudAction::Update | CrudAction::Delete => Ok(None),
                 }?;
 
-                build_unified_response(config, metadata, is_passthrough)(body, pagination, passthrough, params, status, headers)
+                let invalidates = ResponseCacheKey::invalidates(&config.action_name, &config.action);
+                let response = build_unified_response(config, metadata, is_passthrough)(body, pagination, passthrough, params, status, headers)?;
+
+                if !response.response.status().is_success() {
+                    return Ok(response);
+                }
+
+                if let Some((slot, ttl)) = cache_slot {
+                    let cached = CachedResponse {
+                        status: response.response.status(),
+                        headers: response.response.headers().clone(),
+                        body: response.response.body().clone(),
+                    };
+                    self.response_cache.insert(&slot, cached, ttl).await;
+                } else if invalidates {
+                    self.response_cache.invalidate(&connection.key, &name).await;
+                }
+
+                Ok(response)
             }
             Action::Passthrough { method, path, .. } => Err(InternalError::invalid_argument(
                 &format!("Passthrough action is not supported for destination {}, in method {method} and path {path}", key.connection_key),
@@ -581,24 +810,7 @@ impl UnifiedDestination {
             ));
         }
 
-        let secret = self
-            .secrets_cache
-            .get_or_insert_with_fn(connection.as_ref(), || async {
-                match self
-                    .secrets_client
-                    .get(&connection.secrets_service_id, &connection.ownership.id)
-                    .map(|v| Some(v).transpose())
-                    .await
-                {
-                    Ok(Some(c)) => Ok(c),
-                    Ok(None) => Err(InternalError::key_not_found("Secrets", None)),
-                    Err(e) => Err(InternalError::connection_error(
-                        format!("Failed to get secret: {}", e.message().as_ref()).as_str(),
-                        None,
-                    )),
-                }
-            })
-            .await?;
+        let secret = self.get_secret(connection.as_ref()).await?;
 
         // Template the route for passthrough actions
         let templated_config = match &destination.action {
@@ -613,16 +825,200 @@ impl UnifiedDestination {
             _ => config.clone(),
         };
 
-        self.execute_model_definition(
+        let throttle = self.throttle(&connection, &config).await;
+        let secret = secret.as_value()?;
+        let client = match &destination.action {
+            Action::Passthrough { .. } => &self.streaming_client,
+            _ => &self.http_client,
+        };
+
+        let (throttle, templated_config, headers, query_params, context) = (
+            &throttle,
             &templated_config,
-            headers,
+            &headers,
             &query_params,
-            &secret.as_value()?,
-            context,
+            &context,
+        );
+
+        send_refreshing(
+            secret,
+            |secret| async move {
+                self.send_throttled(throttle, || {
+                    self.execute_model_definition_with(
+                        client,
+                        templated_config,
+                        headers.clone(),
+                        query_params,
+                        &secret,
+                        context.clone(),
+                        Some(throttle),
+                    )
+                })
+                .await
+            },
+            || async {
+                self.refresh_on_unauthorized(&connection)
+                    .await?
+                    .as_value()
+                    .inspect_err(|e| {
+                        error!(
+                            "Failed to read refreshed secret for connection {}: {e}",
+                            connection.id
+                        );
+                    })
+                    .ok()
+            },
         )
         .await
     }
 
+    /// Limits of the platform that apply to calls made with `config` on `connection`
+    async fn throttle(
+        &self,
+        connection: &Connection,
+        config: &ConnectionModelDefinition,
+    ) -> Throttle {
+        let definition = self
+            .connection_definitions_cache
+            .get_or_insert_with_filter(
+                &connection.connection_definition_id,
+                self.connection_definitions_store.clone(),
+                doc! { "_id": connection.connection_definition_id.to_string() },
+                None,
+            )
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Failed to get connection definition {} for rate limits: {e}",
+                    connection.connection_definition_id
+                );
+            })
+            .ok();
+
+        let throttle = Throttle::new(&connection.key)
+            .with_limits(&format!("model:{}", config.id), &config.rate_limits);
+
+        match definition {
+            Some(definition) => throttle.with_limits(
+                &format!("definition:{}", definition.id),
+                &definition.rate_limits,
+            ),
+            None => throttle,
+        }
+    }
+
+    /// Sends a request once the limits of the platform allow it. Requests the platform still
+    /// rejects with a 429 are retried after its `Retry-After` when it is short enough.
+    async fn send_throttled<F, Fut>(
+        &self,
+        throttle: &Throttle,
+        send: F,
+    ) -> Result<reqwest::Response, PicaError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<reqwest::Response, PicaError>>,
+    {
+        let mut retries = 0;
+
+        loop {
+            self.outbound_limiter.acquire(throttle).await?;
+
+            let response = send().await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(response);
+            }
+
+            let Some(delay) = retry_after(response.headers(), Utc::now()) else {
+                return Ok(response);
+            };
+
+            self.outbound_limiter.block(throttle, delay).await;
+
+            if retries >= MAX_RATE_LIMITED_RETRIES || delay > self.outbound_limiter.max_wait() {
+                return Ok(response);
+            }
+
+            retries += 1;
+            tracing::info!(
+                "Platform rate limited the request, retrying in {}ms. Attempt: {retries}",
+                delay.as_millis()
+            );
+        }
+    }
+
+    /// Returns the secret of the connection, ignoring cached entries that no
+    /// longer match the connection's `secretsServiceId` (e.g. after a refresh).
+    async fn get_secret(&self, connection: &Connection) -> Result<Secret, PicaError> {
+        if let Some(secret) = self.secrets_cache.get(connection).await? {
+            if secret.id() == connection.secrets_service_id {
+                return Ok(secret);
+            }
+        }
+
+        let secret = match self
+            .secrets_client
+            .get(&connection.secrets_service_id, &connection.ownership.id)
+            .map(|v| Some(v).transpose())
+            .await
+        {
+            Ok(Some(c)) => Ok(c),
+            Ok(None) => Err(InternalError::key_not_found("secret", None)),
+            Err(e) => Err(InternalError::connection_error(
+                format!("Failed to get secret: {}", e.message().as_ref()).as_str(),
+                None,
+            )),
+        }?;
+
+        self.secrets_cache.insert(connection, &secret).await?;
+
+        Ok(secret)
+    }
+
+    /// Called once the platform rejected a request with a 401. If the connection
+    /// uses OAuth, refreshes its token and returns the new secret so the request
+    /// can be retried once.
+    ///
+    /// Concurrent refreshes of the same connection are coalesced within this
+    /// process. Refreshes across processes are kept apart by the claim the
+    /// refresher takes on the connection, callers that lose it wait for the
+    /// token the other process obtained.
+    async fn refresh_on_unauthorized(&self, connection: &Connection) -> Option<Secret> {
+        if !matches!(connection.oauth, Some(OAuth::Enabled { .. })) {
+            return None;
+        }
+
+        tracing::info!(
+            "Received 401 for OAuth connection {}, refreshing token",
+            connection.id
+        );
+
+        let key = (connection.id, connection.secrets_service_id.clone());
+        let refreshed = self
+            .oauth_refreshes
+            .get_or_try_insert_coalesced(&key, self.oauth_refresher.refresh(connection))
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Failed to refresh OAuth token for connection {}: {e}",
+                    connection.id
+                );
+            })
+            .ok()?;
+
+        let _ = self.secrets_cache.remove(connection).await;
+        let _ = self.connections_cache.remove(&connection.key).await;
+
+        self.get_secret(&refreshed)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Failed to get refreshed secret for connection {}: {e}",
+                    connection.id
+                );
+            })
+            .ok()
+    }
+
     async fn get_dependencies(
         &self,
         key: &Destination,
@@ -647,23 +1043,7 @@ impl UnifiedDestination {
                 }
             });
 
-        let secret_fut = self
-            .secrets_cache
-            .get_or_insert_with_fn(connection, || async {
-                match self
-                    .secrets_client
-                    .get(&connection.secrets_service_id, &connection.ownership.id)
-                    .map(|v| Some(v).transpose())
-                    .await
-                {
-                    Ok(Some(c)) => Ok(c),
-                    Ok(None) => Err(InternalError::key_not_found("secret", None)),
-                    Err(e) => Err(InternalError::connection_error(
-                        format!("Failed to get secret: {}", e.message().as_ref()).as_str(),
-                        None,
-                    )),
-                }
-            });
+        let secret_fut = self.get_secret(connection);
 
         let schema_key: (Arc<str>, Arc<str>) = (connection.platform.clone(), name.into());
 
@@ -699,6 +1079,52 @@ impl UnifiedDestination {
     }
 }
 
+fn circuit_key(
+    template: &ConnectionModelDefinition,
+    rendered: &ConnectionModelDefinition,
+) -> CircuitKey {
+    let PlatformInfo::Api(ref template_config) = template.platform_info;
+    let PlatformInfo::Api(ref rendered_config) = rendered.platform_info;
+
+    CircuitKey::new(
+        &template.connection_platform,
+        &template_config.base_url,
+        &rendered_config.base_url,
+    )
+}
+
+/// Rebuilds a cached response with the metadata of the current request
+fn cached_unified_response(
+    cached: CachedResponse,
+    metadata: &mut UnifiedMetadataBuilder,
+) -> Result<UnifiedResponse, PicaError> {
+    let CachedResponse {
+        status,
+        headers,
+        mut body,
+    } = cached;
+
+    if let Some(hash) = body
+        .pointer(&format!("/{META_KEY}/hash"))
+        .and_then(Value::as_str)
+    {
+        metadata.hash(hash.to_string());
+    }
+
+    let metadata = metadata.build()?;
+    if let Value::Object(ref mut resp) = body {
+        resp.insert(META_KEY.to_string(), metadata.as_value());
+    }
+
+    let mut response = Response::builder()
+        .status(status)
+        .body(body)
+        .map_err(|e| PicaError::from_err_code(status, &e.to_string(), None))?;
+    *response.headers_mut() = headers;
+
+    Ok(UnifiedResponse { response, metadata })
+}
+
 fn build_unified_response(
     config: ConnectionModelDefinition,
     metadata: &mut UnifiedMetadataBuilder,
@@ -943,11 +1369,13 @@ fn extend_secret(mut secret: Value, get_path_params: Option<&HashMap<String, Str
     secret
 }
 
-fn generate_script_namespace(max_capacity: u64, key: &str) -> String {
+/// Namespaces change with the definition holding the script so updated scripts are compiled
+/// again instead of reusing the ones compiled before
+pub(crate) fn generate_script_namespace(max_capacity: u64, key: &str, updated_at: i64) -> String {
     if max_capacity == 0 {
         "$".to_string() + &uuid::Uuid::new_v4().simple().to_string()
     } else {
-        key.to_string().replace([':', '-'], "_")
+        format!("{key}_{updated_at}").replace([':', '-'], "_")
     }
 }
 
@@ -1022,3 +1450,264 @@ fn prepare_crud_mapping(
         .extend_header(custom_headers)
         .add_path_param(ID_KEY.to_string(), id.as_ref().map(|id| id.to_string())))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use mockito::{Matcher, Server};
+    use osentities::{
+        connection_oauth_definition::ConnectionOAuthDefinition, oauth_secret::OAuthSecret,
+        SecretVersion,
+    };
+    use std::sync::Mutex;
+    use testcontainers_modules::{mongo::Mongo, testcontainers::clients::Cli as Docker};
+    use uuid::Uuid;
+
+    #[derive(Default)]
+    struct MemorySecrets(Mutex<HashMap<String, Secret>>);
+
+    #[async_trait]
+    impl SecretExt for MemorySecrets {
+        async fn get(&self, id: &str, _buildable_id: &str) -> Result<Secret, PicaError> {
+            self.0
+                .lock()
+                .expect("Secrets poisoned")
+                .get(id)
+                .cloned()
+                .ok_or_else(|| ApplicationError::not_found(id, None))
+        }
+
+        async fn create(&self, secret: &Value, buildable_id: &str) -> Result<Secret, PicaError> {
+            let secret = Secret::new(
+                secret.to_string(),
+                Some(SecretVersion::V2),
+                buildable_id.to_string(),
+                None,
+            );
+
+            self.0
+                .lock()
+                .expect("Secrets poisoned")
+                .insert(secret.id(), secret.clone());
+
+            Ok(secret)
+        }
+    }
+
+    fn api_config(base_url: &str, path: &str) -> Value {
+        json!({
+            "baseUrl": base_url,
+            "path": path,
+            "authMethod": { "type": "None" },
+            "queryParams": {
+                "refresh_token": "{{OAUTH_REFRESH_TOKEN}}"
+            },
+            "schemas": {},
+            "samples": {},
+            "responses": []
+        })
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_calls_share_one_refresh() {
+        let docker = Docker::default();
+        let mongo = docker.run(Mongo);
+
+        let db_config = DatabaseConfig {
+            control_db_url: format!(
+                "mongodb://127.0.0.1:{}/?directConnection=true",
+                mongo.get_host_port_ipv4(27017)
+            ),
+            control_db_name: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+
+        let secrets = Arc::new(MemorySecrets::default());
+
+        let destination = UnifiedDestination::new(
+            db_config.clone(),
+            100,
+            secrets.clone(),
+            UnifiedCacheTTLs {
+                connection_cache_ttl_secs: 60,
+                connection_definition_cache_ttl_secs: 60,
+                connection_model_definition_cache_ttl_secs: 60,
+                connection_model_schema_cache_ttl_secs: 60,
+                secret_cache_ttl_secs: 60,
+            },
+            reqwest::Client::new(),
+            CircuitBreakerConfig {
+                failure_threshold: 0,
+                open_duration: Duration::from_secs(1),
+            },
+            ResponseCache::local(1024 * 1024),
+        )
+        .await
+        .expect("Failed to create destination");
+
+        let mut server = Server::new_async().await;
+
+        let token = server
+            .mock("POST", "/oauth/token")
+            .match_query(Matcher::UrlEncoded(
+                "refresh_token".to_string(),
+                "refresh".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"access_token":"new","expires_in":1800}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let expired = server
+            .mock("GET", "/customers")
+            .match_header("authorization", "Bearer old")
+            .with_status(401)
+            .expect(2)
+            .create_async()
+            .await;
+        let refreshed = server
+            .mock("GET", "/customers")
+            .match_header("authorization", "Bearer new")
+            .with_status(200)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let definition: ConnectionOAuthDefinition = serde_json::from_value(json!({
+            "_id": Id::now(IdPrefix::ConnectionOAuthDefinition),
+            "connectionPlatform": "acme",
+            "configuration": {
+                "init": api_config(&server.url(), "/oauth/token"),
+                "refresh": api_config(&server.url(), "/oauth/token")
+            },
+            "compute": {
+                "init": {
+                    "response": { "entry": "compute", "function": "", "language": "javascript" }
+                },
+                "refresh": {
+                    "response": {
+                        "entry": "compute",
+                        "function": "function compute(payload) { return { accessToken: payload.access_token, expiresIn: payload.expires_in }; }",
+                        "language": "javascript"
+                    }
+                }
+            },
+            "frontend": {
+                "platformRedirectUri": "https://acme.com/oauth",
+                "scopes": "",
+                "iosRedirectUri": "https://acme.com/oauth"
+            }
+        }))
+        .expect("Failed to deserialize connection oauth definition");
+
+        let db = Client::with_uri_str(&db_config.control_db_url)
+            .await
+            .expect("Failed to connect to mongo")
+            .database(&db_config.control_db_name);
+        MongoStore::new(&db, &Store::ConnectionOAuthDefinitions)
+            .await
+            .expect("Failed to create store")
+            .create_one(&definition)
+            .await
+            .expect("Failed to insert connection oauth definition");
+
+        let secret = secrets
+            .create(
+                &OAuthSecret {
+                    client_id: "client".to_string(),
+                    client_secret: "secret".to_string(),
+                    access_token: "old".to_string(),
+                    token_type: Some("Bearer".to_string()),
+                    refresh_token: Some("refresh".to_string()),
+                    expires_in: 3600,
+                    metadata: Value::Null,
+                    request_payload: None,
+                }
+                .as_json(),
+                "owner-id",
+            )
+            .await
+            .expect("Failed to create secret");
+
+        let connection: Connection = serde_json::from_value(json!({
+            "_id": Id::now(IdPrefix::Connection),
+            "platformVersion": "1.0.0",
+            "connectionDefinitionId": Id::now(IdPrefix::ConnectionDefinition),
+            "type": { "api": {} },
+            "key": "live::acme::default::1",
+            "group": "group",
+            "environment": "live",
+            "platform": "acme",
+            "secretsServiceId": secret.id(),
+            "settings": {
+                "parseWebhookBody": false,
+                "showSecret": false,
+                "allowCustomEvents": false,
+                "oauth": true
+            },
+            "throughput": { "key": "throughput-key", "limit": 100 },
+            "ownership": { "buildableId": "owner-id" },
+            "oauth": {
+                "enabled": {
+                    "connection_oauth_definition_id": definition.id,
+                    "expires_in": 3600,
+                    "expires_at": 0
+                }
+            },
+            "createdAt": 0,
+            "updatedAt": 0,
+            "updated": false,
+            "version": "1.0.0",
+            "lastModifiedBy": "system",
+            "deleted": false,
+            "active": true,
+            "deprecated": false
+        }))
+        .expect("Failed to deserialize connection");
+
+        destination
+            .connections_store
+            .create_one(&connection)
+            .await
+            .expect("Failed to insert connection");
+
+        let url = format!("{}/customers", server.url());
+        let call = || {
+            send_refreshing(
+                "old".to_string(),
+                |token| {
+                    let request = reqwest::Client::new().get(&url).bearer_auth(token);
+                    async move { Ok(request.send().await?) }
+                },
+                || async {
+                    destination
+                        .refresh_on_unauthorized(&connection)
+                        .await?
+                        .decode::<OAuthSecret>()
+                        .ok()
+                        .map(|secret| secret.access_token)
+                },
+            )
+        };
+
+        let (first, second) = tokio::join!(call(), call());
+
+        assert_eq!(first.expect("Failed first call").status(), StatusCode::OK);
+        assert_eq!(second.expect("Failed second call").status(), StatusCode::OK);
+
+        token.assert_async().await;
+        expired.assert_async().await;
+        refreshed.assert_async().await;
+
+        let stored = destination
+            .connections_store
+            .get_one_by_id(&connection.id.to_string())
+            .await
+            .expect("Failed to get connection")
+            .expect("Connection not found");
+
+        assert_ne!(stored.secrets_service_id, connection.secrets_service_id);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::config::WatchdogConfig;
+use google_cloud_storage::{
+    client::{Client as GClient, ClientConfig},
+    http::objects::upload::{Media, UploadObjectRequest, UploadType},
+};
+use osentities::{InternalError, PicaError};
+use std::path::PathBuf;
+use strum::{AsRefStr, EnumString};
+
+#[derive(Debug, Clone, PartialEq, Eq, EnumString, AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum BlobStorageProvider {
+    GoogleCloud,
+    Local,
+}
+
+/// Storage of the response bodies that exceed the size limit of task runs
+#[derive(Clone)]
+pub enum BlobStore {
+    GoogleCloud {
+        client: Box<GClient>,
+        bucket: String,
+    },
+    Local {
+        directory: PathBuf,
+    },
+}
+
+impl BlobStore {
+    pub async fn new(config: &WatchdogConfig) -> Result<Option<Self>, PicaError> {
+        let store = match config.task_run_blob_storage {
+            None => return Ok(None),
+            Some(BlobStorageProvider::GoogleCloud) => {
+                let client_config = ClientConfig::default().with_auth().await.map_err(|e| {
+                    InternalError::connection_error(
+                        &format!("Could not authenticate to Google Cloud Storage: {e}"),
+                        None,
+                    )
+                })?;
+
+                BlobStore::GoogleCloud {
+                    client: Box::new(GClient::new(client_config)),
+                    bucket: config.task_run_blob_bucket.clone(),
+                }
+            }
+            Some(BlobStorageProvider::Local) => {
+                let directory = PathBuf::from(&config.task_run_blob_path);
+                tokio::fs::create_dir_all(&directory).await.map_err(|e| {
+                    InternalError::io_err(
+                        &format!("Could not create blob directory {directory:?}: {e}"),
+                        None,
+                    )
+                })?;
+
+                BlobStore::Local { directory }
+            }
+        };
+
+        Ok(Some(store))
+    }
+
+    /// Stores `data` under `name` and returns its fully qualified location
+    pub async fn put(&self, name: &str, data: Vec<u8>) -> Result<String, PicaError> {
+        match self {
+            BlobStore::GoogleCloud { client, bucket } => {
+                client
+                    .upload_object(
+                        &UploadObjectRequest {
+                            bucket: bucket.clone(),
+                            ..Default::default()
+                        },
+                        data,
+                        &UploadType::Simple(Media::new(name.to_string())),
+                    )
+                    .await
+                    .map_err(|e| {
+                        InternalError::io_err(&format!("Could not upload {name}: {e}"), None)
+                    })?;
+
+                Ok(format!("gs://{bucket}/{name}"))
+            }
+            BlobStore::Local { directory } => {
+                let path = directory.join(name);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await.map_err(|e| {
+                        InternalError::io_err(&format!("Could not create {parent:?}: {e}"), None)
+                    })?;
+                }
+
+                tokio::fs::write(&path, data).await.map_err(|e| {
+                    InternalError::io_err(&format!("Could not write {path:?}: {e}"), None)
+                })?;
+
+                Ok(path.to_string_lossy().into_owned())
+            }
+        }
+    }
+}
@@ -1,23 +1,47 @@
-use crate::config::WatchdogConfig;
+use crate::{blob::BlobStore, config::WatchdogConfig, refresh::RefreshWorker};
 use bson::doc;
-use cache::remote::RedisCache;
+use bytes::Bytes;
+use cache::{
+    local::ConnectionModelDefinitionCacheIdKey, remote::RedisCache, window::SlidingWindows,
+};
 use chrono::Utc;
 use futures::{stream::FuturesUnordered, StreamExt};
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use mongodb::options::ReturnDocument;
 use osentities::{
-    cache::CacheConfig, database::DatabaseConfig, task::Task, Id, InternalError, MongoStore,
-    PicaError, Store, Unit,
+    cache::CacheConfig,
+    connection_oauth_definition::ConnectionOAuthDefinition,
+    constant::{PICA_SIGNATURE_HEADER, PICA_TIMESTAMP_HEADER},
+    database::DatabaseConfig,
+    destination::{Action, Destination},
+    secret::Secret,
+    secrets::SecretServiceProvider,
+    task::{Task, TaskCallback, TaskResponse, TaskRun, TaskState, TaskTarget},
+    ApplicationError, Connection, GoogleKms, IOSKms, Id, InternalError, MongoStore, OAuthRefresher,
+    PicaError, SecretExt, Store, Unit,
 };
 use redis::{AsyncCommands, RedisResult};
+use serde_json::json;
 use std::fmt::Display;
-use std::time::Duration;
-use tracing::{error, info};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tracing::{error, info, warn};
+use unified::{
+    algebra::jsruntime::{JSRuntimeConfig, JSRuntimeImpl},
+    circuit_breaker::CircuitBreakerConfig,
+    domain::RequestCrudBuilder,
+    response_cache::ResponseCache,
+    unified::{UnifiedCacheTTLs, UnifiedDestination},
+};
+use uuid::Uuid;
 
 pub struct WatchdogClient {
     watchdog: WatchdogConfig,
     cache: CacheConfig,
     database: DatabaseConfig,
-    client: reqwest::Client,
     tasks: MongoStore<Task>,
+    lease: Lease,
+    executor: Executor,
+    refresh_worker: Option<RefreshWorker>,
 }
 
 impl Display for WatchdogClient {
@@ -44,13 +68,117 @@ impl WatchdogClient {
         let db = client.database(&database.event_db_name);
 
         let tasks: MongoStore<Task> = MongoStore::new(&db, &Store::Tasks).await?;
+        let task_runs: MongoStore<TaskRun> = MongoStore::new(&db, &Store::TaskRuns).await?;
+
+        let control_client = mongodb::Client::with_uri_str(&database.control_db_url).await?;
+        let control_db = control_client.database(&database.control_db_name);
+
+        let secrets_store = MongoStore::<Secret>::new(&control_db, &Store::Secrets).await?;
+        let secrets_client: Arc<dyn SecretExt> = match watchdog.secrets.provider {
+            SecretServiceProvider::GoogleKms => {
+                Arc::new(GoogleKms::new(&watchdog.secrets, secrets_store).await?)
+            }
+            SecretServiceProvider::IosKms => {
+                Arc::new(IOSKms::new(&watchdog.secrets, secrets_store).await?)
+            }
+        };
+
+        let refresh_worker = if watchdog.oauth_refresh_enabled {
+            let connections: MongoStore<Connection> =
+                MongoStore::new(&control_db, &Store::Connections).await?;
+            let definitions: MongoStore<ConnectionOAuthDefinition> =
+                MongoStore::new(&control_db, &Store::ConnectionOAuthDefinitions).await?;
+
+            let refresh_client = reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(watchdog.http_client_timeout_secs))
+                .build()?;
+
+            Some(RefreshWorker {
+                refresher: OAuthRefresher::new(
+                    connections,
+                    definitions,
+                    secrets_client.clone(),
+                    refresh_client,
+                ),
+                interval: Duration::from_secs(watchdog.oauth_refresh_interval_secs),
+                window_secs: watchdog.oauth_refresh_window_secs,
+                batch_size: watchdog.oauth_refresh_batch_size,
+                concurrency: watchdog.oauth_refresh_concurrency,
+            })
+        } else {
+            None
+        };
+
+        let lease = Lease {
+            worker_id: watchdog
+                .worker_id
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().to_string()),
+            duration: Duration::from_secs(watchdog.task_lease_secs),
+        };
+
+        info!("Watchdog worker id is {}", lease.worker_id);
+
+        let unified = UnifiedDestination::new(
+            database.clone(),
+            watchdog.cache_size,
+            secrets_client,
+            UnifiedCacheTTLs {
+                connection_cache_ttl_secs: watchdog.connection_cache_ttl_secs,
+                connection_definition_cache_ttl_secs: watchdog.connection_definition_cache_ttl_secs,
+                connection_model_definition_cache_ttl_secs: watchdog
+                    .connection_model_definition_cache_ttl_secs,
+                connection_model_schema_cache_ttl_secs: watchdog
+                    .connection_model_schema_cache_ttl_secs,
+                secret_cache_ttl_secs: watchdog.secret_cache_ttl_secs,
+            },
+            http_client.clone(),
+            CircuitBreakerConfig {
+                failure_threshold: watchdog.circuit_breaker_failure_threshold,
+                open_duration: Duration::from_secs(watchdog.circuit_breaker_open_secs),
+            },
+            ResponseCache::local(watchdog.response_cache_max_mb * 1024 * 1024),
+        )
+        .await?
+        .with_js_runtime(JSRuntimeImpl::new(JSRuntimeConfig {
+            timeout: Duration::from_millis(watchdog.js_runtime_timeout_ms),
+            max_heap_bytes: watchdog.js_runtime_max_heap_mb * 1024 * 1024,
+            max_scripts: watchdog.js_runtime_max_scripts,
+        }));
+
+        // Without redis every instance gets the whole allowance of the platforms
+        let unified = match RedisCache::new(&cache).await {
+            Ok(redis) => unified.with_shared_rate_limits(SlidingWindows::new(redis)),
+            Err(e) => {
+                warn!("Could not connect to redis to share the rate limits of the platforms: {e}");
+                unified
+            }
+        };
+
+        let executor = Executor {
+            http_client,
+            unified: Arc::new(unified),
+            model_definitions: ConnectionModelDefinitionCacheIdKey::new(
+                watchdog.cache_size,
+                watchdog.connection_model_definition_cache_ttl_secs,
+            ),
+            tasks: tasks.clone(),
+            task_runs,
+            timeout: Duration::from_secs(watchdog.http_client_timeout_secs),
+            lease: lease.clone(),
+            body_limit: watchdog.task_run_body_limit_bytes,
+            blobs: BlobStore::new(&watchdog).await?,
+            blob_limit: watchdog.task_run_blob_limit_bytes,
+        };
 
         Ok(Self {
             watchdog,
             cache,
             database,
-            client: http_client,
             tasks,
+            lease,
+            executor,
+            refresh_worker,
         })
     }
 
@@ -58,9 +186,40 @@ impl WatchdogClient {
         self.run().await
     }
 
-    async fn run(self) -> Result<Unit, PicaError> {
+    /// Atomically claims the next due task, so that no other watchdog executes it
+    async fn claim(&self, now: i64) -> Result<Option<Task>, PicaError> {
+        let task = self
+            .tasks
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "active": true,
+                    "workerId": 0,
+                    "startTime": { "$lte": now }
+                },
+                doc! {
+                    "$set": {
+                        "workerId": 1,
+                        "active": false,
+                        "claimedBy": &self.lease.worker_id,
+                        "leaseExpiresAt": self.lease.expires_at(now)
+                    }
+                },
+            )
+            .sort(doc! { "startTime": 1 })
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        Ok(task)
+    }
+
+    async fn run(mut self) -> Result<Unit, PicaError> {
         info!("Starting watchdog");
 
+        if let Some(refresh_worker) = self.refresh_worker.take() {
+            tokio::spawn(refresh_worker.run());
+        }
+
         let cache = RedisCache::new(&self.cache).await.map_err(|e| {
             error!("Could not connect to cache: {e}");
             InternalError::io_err(e.to_string().as_str(), None)
@@ -77,62 +236,67 @@ impl WatchdogClient {
             }
         });
 
-        let key = self.cache.api_throughput_key.clone();
-        let mut redis_clone = cache.inner.clone();
-
-        tracing::info!("Rate limiter enabled. Connecting to initialized cache");
-
+        // API rate limits expire on their own, only tasks are handled here
         loop {
-            let _: RedisResult<String> = async { redis_clone.del(key.clone()).await }.await;
-            tracing::info!("Rate limiter cleared for {key} at {}", Utc::now());
+            let now = Utc::now().timestamp_millis();
 
-            let tasks: Vec<Task> = self
+            // Tasks of workers that stopped renewing their lease count as a failed attempt
+            let expired = self
                 .tasks
                 .get_many(
                     Some(doc! {
-                    "active": true,
-                    "workerId": 0,
-                    "startTime": {
-                        "$lte": Utc::now().timestamp_millis(),
-                    }}),
+                        "workerId": 1,
+                        "leaseExpiresAt": { "$lt": now }
+                    }),
+                    None,
                     None,
                     None,
-                    Some(self.watchdog.max_amount_of_tasks_to_process),
                     None,
                 )
                 .await?;
 
-            tracing::info!("Executing {} tasks", tasks.len());
+            for task in expired {
+                match self.executor.expire(task).await {
+                    Ok(Some((id, TaskState::DeadLettered))) => {
+                        tracing::warn!("Task {id} exhausted its attempts after its lease expired")
+                    }
+                    Ok(Some((id, _))) => {
+                        tracing::warn!("Requeued task {id} whose lease expired")
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::error!("Error requeueing task: {e}"),
+                }
+            }
 
-            self.tasks
-                .update_many(
-                    doc! {
-                        "_id": {
-                            "$in": tasks.iter().map(|t| t.id.to_string()).collect::<Vec<_>>()
-                        }
-                    },
-                    doc! {
-                        "$set": {
-                            "workerId": 1,
-                            "active": false
-                        }
-                    },
-                )
-                .await?;
+            let mut tasks = vec![];
+            while tasks.len() < self.watchdog.max_amount_of_tasks_to_process as usize {
+                match self.claim(now).await? {
+                    Some(task) => tasks.push(task),
+                    None => break,
+                }
+            }
 
-            let client = self.client.clone();
-            let tasks_store = self.tasks.clone();
-            let timeout = self.watchdog.http_client_timeout_secs;
+            tracing::info!("Executing {} tasks", tasks.len());
+
+            let executor = self.executor.clone();
 
             tokio::spawn(async move {
                 let mut tasks = tasks
                     .into_iter()
-                    .map(|task| execute(task, client.clone(), tasks_store.clone(), timeout))
+                    .map(|task| executor.execute(task))
                     .collect::<FuturesUnordered<_>>();
 
                 while let Some(result) = tasks.next().await {
                     match result {
-                        Ok(id) => tracing::info!("Task {id} executed successfully"),
+                        Ok((id, TaskState::Completed)) => {
+                            tracing::info!("Task {id} executed successfully")
+                        }
+                        Ok((id, TaskState::Pending)) => {
+                            tracing::info!("Task {id} will be retried")
+                        }
+                        Ok((id, TaskState::DeadLettered)) => {
+                            tracing::warn!("Task {id} exhausted its attempts")
+                        }
                         Err(e) => {
                             tracing::error!("Error executing task: {e}");
                         }
@@ -148,59 +312,519 @@ impl WatchdogClient {
     }
 }
 
-async fn execute(
-    task: Task,
+/// Executes claimed tasks and records their outcome
+#[derive(Clone)]
+struct Executor {
     http_client: reqwest::Client,
-    tasks_store: MongoStore<Task>,
-    timeout: u64,
-) -> Result<Id, PicaError> {
-    let timeout = if task.r#await {
-        Duration::from_secs(300)
-    } else {
-        Duration::from_secs(timeout)
-    };
-
-    let response = http_client
-        .post(task.endpoint)
-        .timeout(timeout)
-        .json(&task.payload)
-        .send()
-        .await?;
-
-    let status = response.status();
-    let mut stream = response.bytes_stream();
-    let mut log_trail = vec![];
-
-    while let Some(item) = stream.next().await {
-        tracing::debug!("Response from API {:?}", item);
-        log_trail.push(item);
+    unified: Arc<UnifiedDestination>,
+    model_definitions: ConnectionModelDefinitionCacheIdKey,
+    tasks: MongoStore<Task>,
+    task_runs: MongoStore<TaskRun>,
+    timeout: Duration,
+    lease: Lease,
+    /// Size up to which response bodies are recorded
+    body_limit: usize,
+    blobs: Option<BlobStore>,
+    /// Size up to which response bodies are stored in `blobs`
+    blob_limit: usize,
+}
+
+/// Response of the target of a task
+struct TargetResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    /// Only the first `body_limit` bytes are read, or `blob_limit` bytes when there is a blob store
+    body: Vec<u8>,
+    size: u64,
+}
+
+impl Executor {
+    async fn execute(&self, task: Task) -> Result<(Id, TaskState), PicaError> {
+        let started_at = Utc::now();
+
+        let timeout = if task.r#await {
+            Duration::from_secs(300)
+        } else {
+            self.timeout
+        };
+
+        let request = async {
+            match tokio::time::timeout(timeout, self.send(&task)).await {
+                Ok(Ok(response)) => (Some(response), None),
+                Ok(Err(e)) => {
+                    error!("Could not reach target of task {}: {e}", task.id);
+                    (None, Some(e))
+                }
+                Err(_) => {
+                    error!("Task {} timed out after {timeout:?}", task.id);
+                    (None, Some(format!("Timed out after {timeout:?}")))
+                }
+            }
+        };
+
+        let (response, error) = tokio::select! {
+            outcome = request => outcome,
+            _ = self.lease.keep_alive(&self.tasks, task.id) => {
+                return Err(InternalError::timeout(
+                    &format!("Lease of task {} was lost during its execution", task.id),
+                    None,
+                ));
+            }
+        };
+
+        let mut run = TaskRun::new(&task, started_at);
+        let attempts = run.attempt;
+        let status = response.as_ref().map(|response| response.status);
+        if let Some(response) = response {
+            run.response = Some(self.record(&run, response).await);
+        }
+
+        // Only the recorded part of the body is kept on the task
+        let log_trail = run
+            .response
+            .iter()
+            .map(|response| Bytes::from(response.body.clone()))
+            .collect::<Vec<_>>();
+        let bson_log_trail = bson::to_bson(&log_trail).map_err(|e| {
+            error!("Could not convert log trail to BSON: {e}");
+            InternalError::io_err(e.to_string().as_str(), None)
+        })?;
+
+        // Network errors are always retried
+        let failed = status.is_none_or(|status| task.retry_policy.retries(status.as_u16()));
+
+        let ended_at = Utc::now();
+        let now = ended_at.timestamp_millis();
+        let mut update = doc! {
+            "attempts": attempts,
+            "logTrail": bson_log_trail,
+        };
+        // The lease of finished tasks is released, the worker is kept as the one that executed them
+        let mut unset = doc! { "leaseExpiresAt": "" };
+
+        run.ended_at = now;
+        run.status = status.map(|status| status.to_string());
+        run.error = match (status, error) {
+            (Some(status), _) if failed => Some(format!("Endpoint responded with {status}")),
+            (Some(_), _) => None,
+            (None, error) => error,
+        };
+
+        if let Some(status) = &run.status {
+            update.insert("status", status);
+        }
+        if let Some(error) = &run.error {
+            update.insert("lastError", error);
+        }
+
+        run.state = if failed && task.retry_policy.allows_attempt(attempts) {
+            let backoff = task.retry_policy.backoff(attempts);
+            tracing::warn!(
+                "Task {} failed on attempt {attempts}, retrying in {backoff:?}",
+                task.id
+            );
+
+            update.insert("startTime", now + backoff.as_millis() as i64);
+            update.insert("workerId", 0);
+            update.insert("active", true);
+            unset.insert("claimedBy", "");
+
+            TaskState::Pending
+        } else {
+            update.insert("endTime", now);
+
+            if failed {
+                tracing::error!("Task {} failed after {attempts} attempts", task.id);
+                TaskState::DeadLettered
+            } else {
+                TaskState::Completed
+            }
+        };
+
+        let state = match task.next_start_time(ended_at) {
+            // Recurring tasks get a fresh set of attempts for their next run
+            Some(next) if run.state != TaskState::Pending => {
+                tracing::info!("Task {} scheduled to run again at {next}", task.id);
+
+                update.insert("startTime", next);
+                update.insert("attempts", 0);
+                update.insert("workerId", 0);
+                update.insert("active", true);
+                unset.insert("claimedBy", "");
+
+                TaskState::Pending
+            }
+            _ => run.state,
+        };
+
+        if let Err(e) = self.task_runs.create_one(&run).await {
+            error!("Could not record run of task {}: {e}", task.id);
+        }
+
+        update.insert(
+            "state",
+            bson::to_bson(&state).map_err(|e| {
+                error!("Could not convert task state to BSON: {e}");
+                InternalError::serialize_error(e.to_string().as_str(), None)
+            })?,
+        );
+
+        let updated = self
+            .tasks
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "_id": task.id.to_string(), // Filter by task ID
+                    "claimedBy": &self.lease.worker_id
+                },
+                doc! {
+                    "$set": update,
+                    "$unset": unset
+                },
+            )
+            .await?;
+
+        match (updated, &task.callback) {
+            (None, _) => tracing::warn!(
+                "Task {} was claimed by another worker, its outcome is not recorded",
+                task.id
+            ),
+            (Some(_), Some(callback)) if run.state != TaskState::Pending => {
+                if let Err(e) = self.notify(callback, &run).await {
+                    error!("Could not notify callback of task {}: {e}", task.id);
+                }
+            }
+            _ => {}
+        }
+
+        Ok((task.id, run.state))
     }
 
-    let log_trail = log_trail
-        .into_iter()
-        .filter_map(|x| x.ok())
-        .collect::<Vec<_>>();
-
-    let bson_log_trail = bson::to_bson(&log_trail).map_err(|e| {
-        error!("Could not convert log trail to BSON: {e}");
-        InternalError::io_err(e.to_string().as_str(), None)
-    })?;
-
-    tasks_store
-        .collection
-        .find_one_and_update(
-            doc! {
-                "_id": task.id.to_string() // Filter by task ID
-            },
-            doc! {
-                "$set": {
-                    "status": status.to_string(),
-                    "endTime": Utc::now().timestamp_millis(),
-                    "logTrail": bson_log_trail,
+    /// Records the run of a task whose worker stopped renewing its lease as a failed attempt, and
+    /// makes the task available again unless it exhausted its attempts. Returns `None` when the
+    /// lease was renewed in the meantime.
+    async fn expire(&self, task: Task) -> Result<Option<(Id, TaskState)>, PicaError> {
+        let ended_at = Utc::now();
+        let now = ended_at.timestamp_millis();
+
+        let mut run = TaskRun::new(&task, ended_at);
+        let attempts = run.attempt;
+        let error = "Lease of the worker executing the task expired".to_string();
+
+        let mut update = doc! {
+            "attempts": attempts,
+            "lastError": &error,
+        };
+
+        run.error = Some(error);
+        run.state = if task.retry_policy.allows_attempt(attempts) {
+            TaskState::Pending
+        } else {
+            update.insert("endTime", now);
+            TaskState::DeadLettered
+        };
+
+        let state = match task.next_start_time(ended_at) {
+            // Recurring tasks get a fresh set of attempts for their next run
+            Some(next) if run.state != TaskState::Pending => {
+                update.insert("startTime", next);
+                update.insert("attempts", 0);
+
+                TaskState::Pending
+            }
+            _ => run.state,
+        };
+
+        // Dead-lettered tasks are left claimed, like the ones that finished
+        if state == TaskState::Pending {
+            update.insert("workerId", 0);
+            update.insert("active", true);
+        }
+
+        update.insert(
+            "state",
+            bson::to_bson(&state).map_err(|e| {
+                error!("Could not convert task state to BSON: {e}");
+                InternalError::serialize_error(e.to_string().as_str(), None)
+            })?,
+        );
+
+        let updated = self
+            .tasks
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "_id": task.id.to_string(),
+                    "workerId": 1,
+                    "leaseExpiresAt": { "$lt": now }
+                },
+                doc! {
+                    "$set": update,
+                    "$unset": { "claimedBy": "", "leaseExpiresAt": "" }
+                },
+            )
+            .await?;
+
+        if updated.is_none() {
+            return Ok(None);
+        }
+
+        if let Err(e) = self.task_runs.create_one(&run).await {
+            error!("Could not record run of task {}: {e}", task.id);
+        }
+
+        if let (Some(callback), TaskState::DeadLettered) = (&task.callback, run.state) {
+            if let Err(e) = self.notify(callback, &run).await {
+                error!("Could not notify callback of task {}: {e}", task.id);
+            }
+        }
+
+        Ok(Some((task.id, run.state)))
+    }
+
+    /// Calls the endpoint or connection targeted by the task
+    async fn send(&self, task: &Task) -> Result<TargetResponse, String> {
+        let headers = header_map(&task.headers)?;
+
+        let Some(target) = &task.target else {
+            let mut request = self
+                .http_client
+                .request(task.method.clone(), &task.endpoint)
+                .headers(headers)
+                .query(&task.query_params);
+
+            if !task.payload.is_null() {
+                request = request.json(&task.payload);
+            }
+
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            return self.read_body(response).await;
+        };
+
+        let connection = self.connection(target).await.map_err(|e| e.to_string())?;
+        let query_params = task.query_params.clone().into_iter().collect();
+
+        match target.action(&task.method) {
+            action @ Action::Unified { .. } => {
+                let params = RequestCrudBuilder::default()
+                    .headers(headers)
+                    .query_params(query_params)
+                    .body((!task.payload.is_null()).then(|| task.payload.clone()))
+                    .build()
+                    .map_err(|e| format!("Could not build unified request: {e}"))?;
+
+                let response = self
+                    .unified
+                    .dispatch_unified_request(
+                        connection.clone(),
+                        action,
+                        connection.environment,
+                        params,
+                        self.model_definitions.clone(),
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                let (parts, body) = response.response.into_parts();
+                let body = serde_json::to_vec(&body)
+                    .map_err(|e| format!("Could not serialize unified response: {e}"))?;
+
+                Ok(TargetResponse {
+                    status: parts.status,
+                    headers: parts.headers,
+                    size: body.len() as u64,
+                    body,
+                })
+            }
+            action @ Action::Passthrough { .. } => {
+                let destination = Destination {
+                    platform: connection.platform.clone(),
+                    action,
+                    connection_key: connection.key.clone(),
+                };
+                let body = if task.payload.is_null() {
+                    None
+                } else {
+                    Some(task.payload.to_string().into_bytes())
+                };
+
+                let response = self
+                    .unified
+                    .dispatch_destination_request(
+                        Some(connection),
+                        &destination,
+                        headers,
+                        query_params,
+                        body,
+                        self.model_definitions.clone(),
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                self.read_body(response).await
+            }
+        }
+    }
+
+    /// Reads the body of a response up to the size that can be recorded. Bodies that could not
+    /// be read whole fail the run rather than being recorded as complete.
+    async fn read_body(&self, response: reqwest::Response) -> Result<TargetResponse, String> {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let mut stream = response.bytes_stream();
+        let mut body = vec![];
+        let mut size = 0;
+
+        let limit = if self.blobs.is_some() {
+            self.blob_limit.max(self.body_limit)
+        } else {
+            self.body_limit
+        };
+
+        while let Some(item) = stream.next().await {
+            tracing::debug!("Response from API {:?}", item);
+            let chunk = item.map_err(|e| format!("Could not read response body: {e}"))?;
+
+            size += chunk.len() as u64;
+            if body.len() < limit {
+                let kept = chunk.len().min(limit - body.len());
+                body.extend_from_slice(&chunk[..kept]);
+            }
+        }
+
+        Ok(TargetResponse {
+            status,
+            headers,
+            body,
+            size,
+        })
+    }
+
+    /// Bounds the recorded body of a response, storing it in the blob store when it exceeds the
+    /// limit. Blobs are cut at `blob_limit` themselves, which is flagged on the response.
+    async fn record(&self, run: &TaskRun, response: TargetResponse) -> TaskResponse {
+        let mut recorded = TaskResponse::new(
+            &response.headers,
+            &response.body,
+            response.size,
+            self.body_limit,
+        );
+
+        if let (true, Some(blobs)) = (recorded.truncated, &self.blobs) {
+            let name = format!("{}/{}", run.task_id, run.id);
+            let blob_truncated = (response.body.len() as u64) < response.size;
+            match blobs.put(&name, response.body).await {
+                Ok(location) => {
+                    recorded.blob = Some(location);
+                    recorded.blob_truncated = blob_truncated;
                 }
-            },
-        )
-        .await?;
+                Err(e) => error!("Could not store response body of run {}: {e}", run.id),
+            }
+        }
 
-    Ok(task.id)
+        recorded
+    }
+
+    async fn connection(&self, target: &TaskTarget) -> Result<Arc<Connection>, PicaError> {
+        let mut filter = doc! { "key": &target.connection_key, "deleted": false };
+        if let Some(ownership_id) = &target.ownership_id {
+            filter.insert("ownership.buildableId", ownership_id);
+        }
+
+        self.unified
+            .connections_store
+            .get_one(filter)
+            .await?
+            .map(Arc::new)
+            .ok_or_else(|| {
+                ApplicationError::not_found(
+                    &format!("Connection with key {} not found", target.connection_key),
+                    None,
+                )
+            })
+    }
+
+    /// Sends the outcome of a run to the callback, signed with its secret
+    async fn notify(&self, callback: &TaskCallback, run: &TaskRun) -> Result<Unit, PicaError> {
+        let body = serde_json::to_vec(&json!({
+            "taskId": run.task_id,
+            "runId": run.id,
+            "attempt": run.attempt,
+            "state": run.state,
+            "status": run.status,
+            "error": run.error,
+            "endedAt": run.ended_at,
+        }))
+        .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+
+        let timestamp = Utc::now().timestamp_millis();
+        let signature = callback.sign(timestamp, &body)?;
+
+        self.http_client
+            .post(&callback.url)
+            .timeout(self.timeout)
+            .header(CONTENT_TYPE, "application/json")
+            .header(PICA_TIMESTAMP_HEADER, timestamp)
+            .header(PICA_SIGNATURE_HEADER, format!("sha256={signature}"))
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+fn header_map(headers: &BTreeMap<String, String>) -> Result<HeaderMap, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = HeaderName::try_from(name.as_str())
+                .map_err(|e| format!("Invalid header name {name}: {e}"))?;
+            let value = HeaderValue::try_from(value.as_str())
+                .map_err(|e| format!("Invalid value for header {name}: {e}"))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Claim of a worker over the tasks it executes, renewed while they run
+#[derive(Debug, Clone)]
+struct Lease {
+    worker_id: String,
+    duration: Duration,
+}
+
+impl Lease {
+    fn expires_at(&self, now: i64) -> i64 {
+        now + self.duration.as_millis() as i64
+    }
+
+    /// Renews the lease of a task until it is lost to another worker
+    async fn keep_alive(&self, tasks_store: &MongoStore<Task>, id: Id) {
+        loop {
+            tokio::time::sleep(self.duration / 3).await;
+
+            let renewed = tasks_store
+                .collection
+                .update_one(
+                    doc! {
+                        "_id": id.to_string(),
+                        "claimedBy": &self.worker_id
+                    },
+                    doc! {
+                        "$set": {
+                            "leaseExpiresAt": self.expires_at(Utc::now().timestamp_millis())
+                        }
+                    },
+                )
+                .await;
+
+            match renewed {
+                Ok(result) if result.matched_count == 0 => return,
+                Ok(_) => tracing::debug!("Renewed lease of task {id}"),
+                Err(e) => error!("Could not renew lease of task {id}: {e}"),
+            }
+        }
+    }
 }
@@ -1,5 +1,6 @@
+use crate::blob::BlobStorageProvider;
 use envconfig::Envconfig;
-use osentities::{cache::CacheConfig, database::DatabaseConfig};
+use osentities::{cache::CacheConfig, database::DatabaseConfig, secrets::SecretsConfig};
 use std::fmt::{Display, Formatter};
 
 #[derive(Envconfig, Clone)] // Intentionally no Debug so secret is not printed
@@ -8,12 +9,76 @@ pub struct WatchdogConfig {
     pub rate_limiter_refresh_interval: u64,
     #[envconfig(from = "HTTP_CLIENT_TIMEOUT_SECS", default = "10")]
     pub http_client_timeout_secs: u64,
+    /// Consecutive failures of a platform that open its circuit, 0 disables the circuit breaker
+    #[envconfig(from = "CIRCUIT_BREAKER_FAILURE_THRESHOLD", default = "5")]
+    pub circuit_breaker_failure_threshold: u32,
+    /// How long calls to a platform with an open circuit fail before it is probed again
+    #[envconfig(from = "CIRCUIT_BREAKER_OPEN_SECS", default = "30")]
+    pub circuit_breaker_open_secs: u64,
+    /// How long a mapping script may run before it is terminated
+    #[envconfig(from = "JS_RUNTIME_TIMEOUT_MS", default = "5000")]
+    pub js_runtime_timeout_ms: u64,
+    /// Heap of the isolate running the mapping scripts of each thread
+    #[envconfig(from = "JS_RUNTIME_MAX_HEAP_MB", default = "128")]
+    pub js_runtime_max_heap_mb: usize,
+    /// Compiled mapping scripts kept by each thread
+    #[envconfig(from = "JS_RUNTIME_MAX_SCRIPTS", default = "1024")]
+    pub js_runtime_max_scripts: usize,
+    /// Size of the responses kept by the response cache
+    #[envconfig(from = "RESPONSE_CACHE_MAX_MB", default = "64")]
+    pub response_cache_max_mb: u64,
     #[envconfig(from = "MAX_AMOUNT_OF_TASKS_TO_PROCESS", default = "100")]
     pub max_amount_of_tasks_to_process: u64,
+    /// Identifies the tasks claimed by this watchdog, a random one is generated if unset
+    #[envconfig(from = "WORKER_ID")]
+    pub worker_id: Option<String>,
+    /// Tasks claimed by a watchdog that stopped renewing its lease for this long are executed again
+    #[envconfig(from = "TASK_LEASE_SECS", default = "60")]
+    pub task_lease_secs: u64,
+    /// Response bodies of task runs are truncated to this size
+    #[envconfig(from = "TASK_RUN_BODY_LIMIT_BYTES", default = "16384")]
+    pub task_run_body_limit_bytes: usize,
+    /// Response bodies that exceed the limit are stored here when set,
+    /// either `google-cloud` or `local`
+    #[envconfig(from = "TASK_RUN_BLOB_STORAGE")]
+    pub task_run_blob_storage: Option<BlobStorageProvider>,
+    #[envconfig(from = "TASK_RUN_BLOB_BUCKET", default = "task-runs")]
+    pub task_run_blob_bucket: String,
+    #[envconfig(from = "TASK_RUN_BLOB_PATH", default = "task-runs")]
+    pub task_run_blob_path: String,
+    /// Stored response bodies are truncated to this size, which is flagged as `blobTruncated`
+    #[envconfig(from = "TASK_RUN_BLOB_LIMIT_BYTES", default = "10485760")]
+    pub task_run_blob_limit_bytes: usize,
+    /// Caches of the connections called by tasks with a target
+    #[envconfig(from = "CACHE_SIZE", default = "100")]
+    pub cache_size: u64,
+    #[envconfig(from = "CONNECTION_CACHE_TTL_SECS", default = "120")]
+    pub connection_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_MODEL_SCHEMA_TTL_SECS", default = "86400")]
+    pub connection_model_schema_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_DEFINITION_CACHE_TTL_SECS", default = "86400")]
+    pub connection_definition_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_MODEL_DEFINITION_CACHE_TTL_SECS", default = "86400")]
+    pub connection_model_definition_cache_ttl_secs: u64,
+    #[envconfig(from = "SECRET_CACHE_TTL_SECS", default = "300")]
+    pub secret_cache_ttl_secs: u64,
+    #[envconfig(from = "OAUTH_REFRESH_ENABLED", default = "true")]
+    pub oauth_refresh_enabled: bool,
+    #[envconfig(from = "OAUTH_REFRESH_INTERVAL_SECS", default = "60")]
+    pub oauth_refresh_interval_secs: u64,
+    /// Connections whose token expires within this many seconds are refreshed
+    #[envconfig(from = "OAUTH_REFRESH_WINDOW_SECS", default = "600")]
+    pub oauth_refresh_window_secs: i64,
+    #[envconfig(from = "OAUTH_REFRESH_BATCH_SIZE", default = "100")]
+    pub oauth_refresh_batch_size: u64,
+    #[envconfig(from = "OAUTH_REFRESH_CONCURRENCY", default = "10")]
+    pub oauth_refresh_concurrency: usize,
     #[envconfig(nested = true)]
     pub redis: CacheConfig,
     #[envconfig(nested = true)]
     pub db: DatabaseConfig,
+    #[envconfig(nested = true)]
+    pub secrets: SecretsConfig,
 }
 
 impl Display for WatchdogConfig {
@@ -28,7 +93,95 @@ impl Display for WatchdogConfig {
             "HTTP_CLIENT_TIMEOUT_SECS: {}",
             self.http_client_timeout_secs
         )?;
+        writeln!(
+            f,
+            "CIRCUIT_BREAKER_FAILURE_THRESHOLD: {}",
+            self.circuit_breaker_failure_threshold
+        )?;
+        writeln!(
+            f,
+            "CIRCUIT_BREAKER_OPEN_SECS: {}",
+            self.circuit_breaker_open_secs
+        )?;
+        writeln!(f, "JS_RUNTIME_TIMEOUT_MS: {}", self.js_runtime_timeout_ms)?;
+        writeln!(f, "JS_RUNTIME_MAX_HEAP_MB: {}", self.js_runtime_max_heap_mb)?;
+        writeln!(f, "JS_RUNTIME_MAX_SCRIPTS: {}", self.js_runtime_max_scripts)?;
+        writeln!(f, "RESPONSE_CACHE_MAX_MB: {}", self.response_cache_max_mb)?;
+        writeln!(
+            f,
+            "MAX_AMOUNT_OF_TASKS_TO_PROCESS: {}",
+            self.max_amount_of_tasks_to_process
+        )?;
+        writeln!(f, "WORKER_ID: {:?}", self.worker_id)?;
+        writeln!(f, "TASK_LEASE_SECS: {}", self.task_lease_secs)?;
+        writeln!(
+            f,
+            "TASK_RUN_BODY_LIMIT_BYTES: {}",
+            self.task_run_body_limit_bytes
+        )?;
+        match &self.task_run_blob_storage {
+            Some(provider @ BlobStorageProvider::GoogleCloud) => {
+                writeln!(f, "TASK_RUN_BLOB_STORAGE: {}", provider.as_ref())?;
+                writeln!(f, "TASK_RUN_BLOB_BUCKET: {}", self.task_run_blob_bucket)?;
+            }
+            Some(provider @ BlobStorageProvider::Local) => {
+                writeln!(f, "TASK_RUN_BLOB_STORAGE: {}", provider.as_ref())?;
+                writeln!(f, "TASK_RUN_BLOB_PATH: {}", self.task_run_blob_path)?;
+            }
+            None => writeln!(f, "TASK_RUN_BLOB_STORAGE: None")?,
+        }
+        if self.task_run_blob_storage.is_some() {
+            writeln!(
+                f,
+                "TASK_RUN_BLOB_LIMIT_BYTES: {}",
+                self.task_run_blob_limit_bytes
+            )?;
+        }
+        writeln!(f, "CACHE_SIZE: {}", self.cache_size)?;
+        writeln!(
+            f,
+            "CONNECTION_CACHE_TTL_SECS: {}",
+            self.connection_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_MODEL_SCHEMA_TTL_SECS: {}",
+            self.connection_model_schema_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_DEFINITION_CACHE_TTL_SECS: {}",
+            self.connection_definition_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_MODEL_DEFINITION_CACHE_TTL_SECS: {}",
+            self.connection_model_definition_cache_ttl_secs
+        )?;
+        writeln!(f, "SECRET_CACHE_TTL_SECS: {}", self.secret_cache_ttl_secs)?;
+        writeln!(f, "OAUTH_REFRESH_ENABLED: {}", self.oauth_refresh_enabled)?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_INTERVAL_SECS: {}",
+            self.oauth_refresh_interval_secs
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_WINDOW_SECS: {}",
+            self.oauth_refresh_window_secs
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_BATCH_SIZE: {}",
+            self.oauth_refresh_batch_size
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_CONCURRENCY: {}",
+            self.oauth_refresh_concurrency
+        )?;
         writeln!(f, "{}", self.redis)?;
-        writeln!(f, "{}", self.db)
+        writeln!(f, "{}", self.db)?;
+        writeln!(f, "{}", self.secrets)
     }
 }
@@ -1,5 +1,7 @@
+mod blob;
 mod client;
 mod config;
+mod refresh;
 
 use crate::client::WatchdogClient;
 use anyhow::{Context, Result};
@@ -0,0 +1,95 @@
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use osentities::{is_refresh_rejected, Connection, OAuthRefresher, PicaError};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+pub struct RefreshWorker {
+    pub refresher: OAuthRefresher,
+    pub interval: Duration,
+    pub window_secs: i64,
+    pub batch_size: u64,
+    pub concurrency: usize,
+}
+
+impl RefreshWorker {
+    pub async fn run(self) {
+        info!("Starting OAuth refresh worker");
+
+        loop {
+            match self.refresh_expiring().await {
+                Ok(0) => {}
+                Ok(count) => info!("Processed {count} expiring OAuth connections"),
+                Err(e) => error!("Could not fetch expiring OAuth connections: {e}"),
+            }
+
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Refreshes up to a batch of expiring connections. Connections are
+    /// claimed as slots free up, so that their claim does not lapse while
+    /// they wait and other replicas skip them. Connections are claimed once
+    /// per batch, even when their new token expires within the window.
+    async fn refresh_expiring(&self) -> Result<usize, PicaError> {
+        let before = Utc::now().timestamp() + self.window_secs;
+        let count = AtomicUsize::new(0);
+        let failed = Mutex::new(None);
+        let error = &failed;
+
+        let claims = stream::unfold(vec![], |mut claimed: Vec<String>| async move {
+            if claimed.len() as u64 >= self.batch_size {
+                return None;
+            }
+
+            match self.refresher.claim_expiring(before, &claimed).await {
+                Ok(Some(connection)) => {
+                    claimed.push(connection.id.to_string());
+                    Some((connection, claimed))
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    *error.lock().await = Some(e);
+                    None
+                }
+            }
+        });
+
+        claims
+            .for_each_concurrent(self.concurrency, |connection| {
+                count.fetch_add(1, Ordering::Relaxed);
+                self.refresh_one(connection)
+            })
+            .await;
+
+        match failed.into_inner() {
+            Some(e) => Err(e),
+            None => Ok(count.into_inner()),
+        }
+    }
+
+    async fn refresh_one(&self, mut connection: Connection) {
+        match self.refresher.refresh_claimed(&connection).await {
+            Ok(_) => info!("Refreshed OAuth token for connection {}", connection.id),
+            // The claim was released, so the refresh is attempted again on a later run
+            Err(e) if !is_refresh_rejected(&e) => error!(
+                "Could not refresh OAuth token for connection {}, retrying later: {e}",
+                connection.id
+            ),
+            Err(e) => {
+                error!(
+                    "OAuth token refresh for connection {} was rejected: {e}",
+                    connection.id
+                );
+
+                if let Err(e) = self.refresher.mark_failed(&mut connection, &e).await {
+                    error!("Could not mark connection {} as failed: {e}", connection.id);
+                }
+            }
+        }
+    }
+}